@@ -0,0 +1,94 @@
+//! End-to-end cost of wrapping a command, against spawning it directly.
+//!
+//! Run with `cargo bench --bench overhead`. On a single-core Linux VM:
+//!
+//! | case                          | direct | wrapped |
+//! |-------------------------------|--------|---------|
+//! | no output (`true`)            | 0.82ms | 1.21ms  |
+//! | no output, streams inherited  |        | 1.04ms  |
+//! | 1MB of output                 | 0.92ms | 16.1ms  |
+//! | 1M short lines (`seq`)        | 9.1ms  | 221ms   |
+//! | 1M short lines, echoed        |        | 188ms   |
+//!
+//! The no-output overhead stays under a millisecond; long outputs are
+//! dominated by splitting and storing lines. Echoing 1M lines took 912ms
+//! while every line was flushed on its own, before reads were batched. The
+//! echoed case points the bench's stdout at /dev/null, so it only runs on Unix.
+
+use std::process::{Command, Stdio};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rust_telemetry_driver::{BannerMode, Config, TelemetryDriver};
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn driver(log: &std::path::Path, capture: bool, quiet: bool) -> TelemetryDriver {
+    let mut config = Config::default();
+    config.telemetry_file = log.to_string_lossy().into_owned();
+    config.banner = BannerMode::Never;
+    config.quiet = quiet;
+    config.capture_stdout = capture;
+    config.capture_stderr = capture;
+    TelemetryDriver::new(config)
+}
+
+fn direct(command: &[String]) {
+    Command::new(&command[0])
+        .args(&command[1..])
+        .stdout(Stdio::null())
+        .status()
+        .expect("spawn");
+}
+
+/// Runs `f` with the bench's stdout pointed at /dev/null, so a driver's echo
+/// costs its real writes without burying criterion's report.
+#[cfg(unix)]
+fn discarding_stdout<R>(f: impl FnOnce() -> R) -> R {
+    use std::os::fd::AsRawFd;
+
+    let null = std::fs::OpenOptions::new().write(true).open("/dev/null").expect("open /dev/null");
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    unsafe { libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO) };
+    let result = f();
+    unsafe {
+        libc::dup2(saved, libc::STDOUT_FILENO);
+        libc::close(saved);
+    }
+    result
+}
+
+fn overhead(c: &mut Criterion) {
+    let log = std::env::temp_dir().join(format!("telemetry-bench-{}.jsonl", std::process::id()));
+    let captured = driver(&log, true, true);
+    #[cfg(unix)]
+    let echoed = driver(&log, true, false);
+    let inherited = driver(&log, false, true);
+
+    let cases = [
+        ("no_output", command(&["true"])),
+        ("1mb_output", command(&["head", "-c", "1048576", "/dev/zero"])),
+        ("1m_lines", command(&["seq", "1000000"])),
+    ];
+    for (name, command) in &cases {
+        let mut group = c.benchmark_group(*name);
+        if *name != "no_output" {
+            group.sample_size(10);
+        }
+        group.bench_function("direct", |b| b.iter(|| direct(command)));
+        group.bench_function("wrapped", |b| b.iter(|| captured.run(command).expect("run")));
+        if *name == "no_output" {
+            group.bench_function("inherited", |b| b.iter(|| inherited.run(command).expect("run")));
+        }
+        #[cfg(unix)]
+        if *name == "1m_lines" {
+            group.bench_function("echoed", |b| b.iter(|| discarding_stdout(|| echoed.run(command).expect("run"))));
+        }
+        group.finish();
+        let _ = std::fs::remove_file(&log);
+    }
+}
+
+criterion_group!(benches, overhead);
+criterion_main!(benches);
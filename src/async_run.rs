@@ -0,0 +1,395 @@
+//! [`TelemetryDriver::run`] as a future, for embedding in a tokio runtime
+//! (feature `tokio`). The child's streams are read by the runtime instead of
+//! by a thread each, so a server can wrap many commands at once; setup,
+//! capture rules and events are shared with the blocking path.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tokio::sync::oneshot;
+
+use crate::capture::{
+    line_echo, raw_echo, take_line_chunk, CaptureOptions, LineCapture, RawCapture, StreamCapture, READ_BUFFER_BYTES,
+};
+use crate::heartbeat::{Heartbeat, HeartbeatSource};
+use crate::net_trace::{NetSampler, DEFAULT_NET_SAMPLE_INTERVAL};
+use crate::sampler::ResourceSampler;
+use crate::{Config, Outcome, ProcessStats, TelemetryDriver, TelemetryEvent};
+
+/// Runs `command` under telemetry capture; see [`TelemetryDriver::run_async`].
+pub async fn run_async(command: &[String], config: &Config) -> io::Result<ProcessStats> {
+    TelemetryDriver::new(config.clone()).run_async(command).await
+}
+
+impl TelemetryDriver {
+    /// [`run`](TelemetryDriver::run) without blocking the calling task.
+    ///
+    /// The child's stdin is closed rather than forwarded, and PTYs, file and
+    /// tree tracing, signal forwarding and `resource_usage` (the runtime reaps
+    /// the child, so its own usage never reaches the driver) are left out.
+    /// Setup, event writes and stopping the samplers run on the blocking pool,
+    /// so a slow sink holds up only its own run. Unless `quiet` is set, output
+    /// is echoed to the process's stdout and stderr.
+    pub async fn run_async(&self, command: &[String]) -> io::Result<ProcessStats> {
+        let mut attempt = 1;
+        loop {
+            let stats = self.run_attempt_async(command, attempt).await?;
+            if !self.retries_after(&stats, attempt) {
+                return Ok(stats);
+            }
+            tokio::time::sleep(self.config.retry_delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn run_attempt_async(&self, command: &[String], attempt: u32) -> io::Result<ProcessStats> {
+        let config = &self.config;
+        if config.pty || config.trace_files || config.capture_tree {
+            eprintln!("⚠️ TELEMETRY_PTY, TELEMETRY_TRACE_FILES and TELEMETRY_CAPTURE_TREE don't apply to async runs");
+        }
+        // The attempt lives on a blocking thread from setup to report, since
+        // both touch git, the filesystem and the sinks
+        let (setup_tx, setup_rx) = oneshot::channel();
+        let (outcome_tx, outcome_rx) = mpsc::channel::<io::Result<Outcome>>();
+        let driver = self.clone();
+        let owned_command = command.to_vec();
+        let report = tokio::task::spawn_blocking(move || {
+            let command = &owned_command;
+            let (state, cmd) = driver.begin_attempt(command, attempt)?;
+            let template = TelemetryEvent { env: HashMap::new(), env_source: None, ..state.pre_event.clone() };
+            if setup_tx.send((cmd, template)).is_err() {
+                return Err(io::Error::other("the run was cancelled"));
+            }
+            match outcome_rx.recv() {
+                Ok(Ok(outcome)) => Ok(driver.finish_attempt(command, state, outcome)),
+                Ok(Err(e)) => Err(driver.spawn_failed(command, state, e)),
+                Err(_) => Err(io::Error::other("the run was cancelled")),
+            }
+        });
+        let Ok((cmd, template)) = setup_rx.await else {
+            // Setup failed; the task holds its error
+            return report.await?;
+        };
+        let mut cmd = tokio::process::Command::from(cmd);
+        cmd.stdin(Stdio::null()).kill_on_drop(true);
+        // See process::own_process_group. Concurrent runs can't all hold a terminal's
+        // foreground, so with one on stdin the child stays in the caller's group instead,
+        // and uses the terminal and follows its job control as the caller does
+        #[cfg(unix)]
+        if !std::io::IsTerminal::is_terminal(&io::stdin()) {
+            cmd.process_group(0);
+        }
+
+        let spawned_at = Instant::now();
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = outcome_tx.send(Err(e));
+                return report.await?;
+            }
+        };
+        let pid = child.id().unwrap_or_default();
+
+        let interleaved = (config.interleave_output || config.combine_output).then(|| Arc::new(Mutex::new(Vec::new())));
+        let stdout_options = self.capture_options(command, spawned_at, interleaved.clone());
+        let stderr_options = CaptureOptions {
+            bytes_read: Arc::new(AtomicUsize::new(0)),
+            ..stdout_options.clone()
+        };
+
+        let sampler = config.sample_interval.map(|interval| ResourceSampler::start(interval, pid, spawned_at));
+        let net_sampler = config.trace_net.then(|| {
+            NetSampler::start(config.sample_interval.unwrap_or(DEFAULT_NET_SAMPLE_INTERVAL), pid)
+        });
+        let heartbeat = config.heartbeat.map(|interval| {
+            Heartbeat::start(interval, config.clone(), template, HeartbeatSource {
+                child_pid: pid,
+                started: spawned_at,
+                stdout_bytes: Arc::clone(&stdout_options.bytes_read),
+                stderr_bytes: Arc::clone(&stderr_options.bytes_read),
+            })
+        });
+
+        let quiet = config.quiet;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let stdout_capture = async {
+            let echo = if quiet { Box::new(tokio::io::sink()) as AsyncEcho } else { Box::new(tokio::io::stdout()) };
+            match stdout {
+                Some(stdout) => capture_stream(stdout, echo, "stdout", &stdout_options).await,
+                None => StreamCapture::default(),
+            }
+        };
+        let stderr_capture = async {
+            let echo = if quiet { Box::new(tokio::io::sink()) as AsyncEcho } else { Box::new(tokio::io::stderr()) };
+            match stderr {
+                Some(stderr) => capture_stream(stderr, echo, "stderr", &stderr_options).await,
+                None => StreamCapture::default(),
+            }
+        };
+        let exit = async {
+            let status = wait_with_timeout(&mut child, config.timeout).await;
+            let exited_at = Instant::now();
+            let end_time = SystemTime::now();
+            // Stopping joins each sampler's thread, which may be mid-write
+            let stopped = tokio::task::spawn_blocking(|| {
+                let heartbeats = heartbeat.map(Heartbeat::stop).unwrap_or_default();
+                (sampler.map(ResourceSampler::stop), net_sampler.map(NetSampler::stop), heartbeats)
+            });
+            (status, exited_at, end_time, stopped.await)
+        };
+        let ((status, exited_at, end_time, stopped), stdout_capture, stderr_capture) =
+            tokio::join!(exit, stdout_capture, stderr_capture);
+        let (status, timed_out) = status?;
+        let (resource_samples, net_connections, heartbeats) = stopped?;
+        let peak_open_fds = resource_samples
+            .as_ref()
+            .and_then(|samples| samples.iter().filter_map(|sample| sample.open_fds).max());
+
+        let outcome = Outcome {
+            status,
+            timed_out,
+            spawned_at,
+            exited_at,
+            end_time,
+            stdout_capture,
+            stderr_capture,
+            capture_error: None,
+            interleaved,
+            stdin_provided: None,
+            stdin_encoding: None,
+            stdin_truncated: None,
+            resource_usage: None,
+            resource_samples,
+            peak_open_fds,
+            files_opened: None,
+            child_processes: None,
+            interrupted_by: None,
+            net_connections,
+            heartbeats,
+        };
+        // The blocking thread is still waiting, since it holds the other end
+        let _ = outcome_tx.send(Ok(outcome));
+        report.await?
+    }
+}
+
+/// Waits for `child`, killing its process group (or just the child, if it shares the
+/// caller's) once `timeout` passes. Returns the exit status and whether the timeout fired.
+async fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return child.wait().await.map(|status| (status, false));
+    };
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status.map(|status| (status, false)),
+        Err(_) => {
+            // The child may have exited in the meantime; wait() still reaps it
+            #[cfg(unix)]
+            let killed = child.id().is_some_and(crate::process::kill_process_group);
+            #[cfg(not(unix))]
+            let killed = false;
+            if !killed {
+                let _ = child.start_kill();
+            }
+            child.wait().await.map(|status| (status, true))
+        }
+    }
+}
+
+type AsyncEcho = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// The async counterpart of [`capture_stream_lines`](crate::capture::capture_stream_lines).
+async fn capture_stream(
+    reader: impl AsyncRead + Unpin,
+    mut out: impl AsyncWrite + Unpin + Send,
+    stream: &'static str,
+    options: &CaptureOptions,
+) -> StreamCapture {
+    if options.capture_raw {
+        return capture_stream_raw(reader, out, stream, options).await;
+    }
+    let pending = PendingEcho::default();
+    // Exact passthrough echoes bytes as they're consumed; otherwise each line is echoed as it's split off
+    let (mut tee, mut line_echo) = if options.exact_passthrough {
+        (Some(pending.clone()), None)
+    } else {
+        (None, Some(line_echo(pending.clone(), stream, options)))
+    };
+    let mut muted = io::sink();
+    let echo: &mut (dyn Write + Send) = match &mut line_echo {
+        Some(echo) => echo,
+        None => &mut muted,
+    };
+    let mut hasher = options.hash_output.then(Sha256::new);
+    let mut reader = BufReader::with_capacity(READ_BUFFER_BYTES, reader);
+    let mut capture = LineCapture::new(stream, options);
+
+    loop {
+        if capture.awaiting_output() && reader.fill_buf().await.is_ok_and(|buf| !buf.is_empty()) {
+            capture.output_started();
+        }
+
+        let mut raw = Vec::new();
+        let read = read_capped_line(&mut reader, &mut raw, options.max_line_bytes, echo, |bytes| {
+            if let Some(hasher) = &mut hasher {
+                hasher.update(bytes);
+            }
+            if let Some(tee) = &mut tee {
+                let _ = tee.write_all(bytes).and_then(|()| tee.flush());
+            }
+        });
+        match read.await {
+            Ok((0, _)) => break, // EOF
+            Ok((n, dropped)) => capture.push(raw, n, dropped, echo, !reader.buffer().is_empty()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+        // Pass the echo on before waiting for more output, or once it's a buffer's worth
+        if reader.buffer().is_empty() || pending.len() >= READ_BUFFER_BYTES {
+            pending.drain(&mut out).await;
+        }
+    }
+    let _ = echo.flush();
+    pending.drain(&mut out).await;
+
+    capture.finish(hasher.map(|hasher| format!("{:x}", hasher.finalize())))
+}
+
+async fn capture_stream_raw(
+    mut reader: impl AsyncRead + Unpin,
+    mut out: impl AsyncWrite + Unpin + Send,
+    stream: &str,
+    options: &CaptureOptions,
+) -> StreamCapture {
+    let pending = PendingEcho::default();
+    let mut echo = raw_echo(pending.clone(), stream, options);
+    let mut capture = RawCapture::new(options);
+    let mut buf = vec![0; READ_BUFFER_BYTES];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => capture.push(&buf[..n], &mut echo),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+        pending.drain(&mut out).await;
+    }
+    capture.finish()
+}
+
+/// Reads one line into `raw`, keeping at most `limit` bytes of it and writing
+/// the rest straight to `echo`, as the blocking capture does. Every byte
+/// consumed is also handed to `consumed`. Returns the bytes read, newline
+/// included, and the number of content bytes dropped.
+async fn read_capped_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    raw: &mut Vec<u8>,
+    limit: Option<usize>,
+    echo: &mut (dyn Write + Send),
+    mut consumed: impl FnMut(&[u8]),
+) -> io::Result<(usize, usize)> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut total = 0;
+    let mut dropped = 0;
+    loop {
+        let available = match reader.fill_buf().await {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok((total, dropped));
+        }
+
+        let (chunk_len, ended) = take_line_chunk(available, raw, limit, &mut dropped, echo);
+        consumed(&available[..chunk_len]);
+        total += chunk_len;
+        reader.consume(chunk_len);
+        if ended {
+            return Ok((total, dropped));
+        }
+    }
+}
+
+/// Echo bytes held for an async writer. The capture's echo chain writes
+/// synchronously, so it writes here and [`drain`](PendingEcho::drain) passes
+/// the bytes on without blocking a runtime thread on a slow terminal or pipe.
+#[derive(Clone, Default)]
+struct PendingEcho(Arc<Mutex<Vec<u8>>>);
+
+impl PendingEcho {
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Writes everything held so far to `out`.
+    async fn drain(&self, out: &mut (impl AsyncWrite + Unpin)) {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        if !bytes.is_empty() {
+            let _ = out.write_all(&bytes).await;
+            let _ = out.flush().await;
+        }
+    }
+}
+
+impl Write for PendingEcho {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_line_bytes: Option<usize>, capture_raw: bool) -> CaptureOptions {
+        CaptureOptions {
+            max_capture_bytes: usize::MAX,
+            max_lines: None,
+            head_lines: None,
+            tail_lines: None,
+            max_line_bytes,
+            started: Instant::now(),
+            timestamp_lines: false,
+            interleaved: None,
+            bytes_read: Arc::default(),
+            hash_output: false,
+            strip_ansi: false,
+            echo_prefix: None,
+            exact_passthrough: false,
+            fail_pattern: None,
+            capture_raw,
+        }
+    }
+
+    #[tokio::test]
+    async fn async_capture_matches_the_blocking_one() {
+        let mut input = b"short\n".to_vec();
+        input.resize(input.len() + 3 * READ_BUFFER_BYTES, b'x');
+        input.extend_from_slice(b"\nlast");
+        for (max_line_bytes, capture_raw) in [(None, false), (Some(100), false), (None, true)] {
+            let options = options(max_line_bytes, capture_raw);
+            let mut blocking_echo = Vec::new();
+            let reader = io::Cursor::new(input.clone());
+            let blocking = crate::capture::capture_stream_lines(reader, &mut blocking_echo, "stdout", &options);
+            let mut echo = Vec::new();
+            let capture = capture_stream(&input[..], &mut echo, "stdout", &options).await;
+
+            assert_eq!(capture.lines, blocking.lines, "{:?}", max_line_bytes);
+            assert_eq!(capture.raw, blocking.raw);
+            assert_eq!((capture.total_bytes, capture.total_lines), (blocking.total_bytes, blocking.total_lines));
+            assert!(echo == blocking_echo && echo == input, "echo differs with {:?}", max_line_bytes);
+        }
+    }
+}
@@ -0,0 +1,725 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use regex::bytes::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::event::OutputLine;
+
+/// Size of each read from a child's stream, and of the echo buffer batching
+/// the lines split from it into one write.
+pub(crate) const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+/// How the stdout and stderr threads capture their stream.
+#[derive(Clone)]
+pub(crate) struct CaptureOptions {
+    pub(crate) max_capture_bytes: usize,
+    /// Most lines kept; later lines are passed through and counted only.
+    pub(crate) max_lines: Option<usize>,
+    /// Keep only the first `head_lines` and the last `tail_lines` lines, with a
+    /// `…[omitted N lines]` marker for the gap, in place of `max_lines`.
+    pub(crate) head_lines: Option<usize>,
+    /// `max_capture_bytes` still bounds the tail.
+    pub(crate) tail_lines: Option<usize>,
+    /// Longest line kept in memory; the rest of a longer line is passed through and counted only.
+    pub(crate) max_line_bytes: Option<usize>,
+    /// Reference point for line timestamps, taken just before the child is spawned.
+    pub(crate) started: Instant,
+    /// Record when each retained line arrived.
+    pub(crate) timestamp_lines: bool,
+    /// Shared record of both streams in arrival order, when interleaving is enabled.
+    pub(crate) interleaved: Option<Arc<Mutex<Vec<OutputLine>>>>,
+    /// Running byte count of this stream, readable while the capture is in progress.
+    pub(crate) bytes_read: Arc<AtomicUsize>,
+    /// Compute a SHA-256 of the raw stream.
+    pub(crate) hash_output: bool,
+    /// Store lines with ANSI escape sequences removed; the echo and hash still see them.
+    pub(crate) strip_ansi: bool,
+    /// Put before each echoed line, with `{stream}` replaced by the stream name; stored lines don't get it.
+    pub(crate) echo_prefix: Option<String>,
+    /// Echo the stream's bytes exactly as read, CRLFs and all, instead of line by line;
+    /// `echo_prefix` doesn't apply and interleaving no longer orders the echo.
+    pub(crate) exact_passthrough: bool,
+    /// Flag the stream if any line, retained or not, matches.
+    pub(crate) fail_pattern: Option<Regex>,
+    /// Keep the bytes as read instead of split lines; see [`capture_stream_raw`].
+    pub(crate) capture_raw: bool,
+}
+
+/// Output captured from one of the child's streams.
+#[derive(Default)]
+pub(crate) struct StreamCapture {
+    pub(crate) lines: Vec<String>,
+    /// Milliseconds since spawn at which each entry of `lines` arrived, if requested.
+    pub(crate) line_offsets_ms: Option<Vec<f64>>,
+    pub(crate) total_lines: usize,
+    pub(crate) total_bytes: usize,
+    /// Set once the retained lines hit the capture cap; `total_bytes` keeps counting.
+    pub(crate) truncated: bool,
+    /// Set once the retained lines hit `max_lines`; `total_lines` keeps counting.
+    pub(crate) truncated_lines: bool,
+    /// Set when a retained line wasn't valid UTF-8 and was stored lossily.
+    pub(crate) lossy: bool,
+    /// Milliseconds since spawn at which the first byte arrived.
+    pub(crate) first_output_ms: Option<f64>,
+    /// Hex SHA-256 of every byte read, when `hash_output` is set.
+    pub(crate) sha256: Option<String>,
+    /// Set when `strip_ansi` removed an escape sequence from a retained line.
+    pub(crate) had_ansi: bool,
+    /// Set when a line matched `fail_pattern`.
+    pub(crate) pattern_matched: bool,
+    /// The bytes as read, up to the capture cap, under `capture_raw`.
+    pub(crate) raw: Option<Vec<u8>>,
+}
+
+/// Hashes bytes as they are read, beneath any buffering or line splitting,
+/// and copies them unchanged to `tee` for exact passthrough.
+struct SourceReader<R, W> {
+    inner: R,
+    hasher: Option<Sha256>,
+    tee: Option<W>,
+}
+
+impl<R: Read, W: Write> Read for SourceReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        if let Some(tee) = &mut self.tee {
+            let _ = tee.write_all(&buf[..n]).and_then(|()| tee.flush());
+        }
+        Ok(n)
+    }
+}
+
+/// The last lines of a stream, for `tail_lines`.
+struct TailLines {
+    max_lines: usize,
+    /// Each line with its arrival in ms since spawn.
+    lines: VecDeque<(String, f64)>,
+    bytes: usize,
+    /// Lines pushed out so far.
+    omitted: usize,
+    first_omitted_ms: Option<f64>,
+}
+
+impl TailLines {
+    fn new(max_lines: usize) -> Self {
+        TailLines { max_lines, lines: VecDeque::new(), bytes: 0, omitted: 0, first_omitted_ms: None }
+    }
+
+    /// Whether any line has reached the ring yet, which closes the head.
+    fn started(&self) -> bool {
+        !self.lines.is_empty() || self.omitted > 0
+    }
+
+    /// Adds `line`, dropping the oldest lines past `max_lines` or `max_bytes`.
+    fn push(&mut self, line: String, offset_ms: f64, max_bytes: usize) {
+        self.bytes += line.len();
+        self.lines.push_back((line, offset_ms));
+        while self.lines.len() > self.max_lines || self.bytes > max_bytes {
+            let Some((oldest, offset_ms)) = self.lines.pop_front() else { break };
+            self.bytes -= oldest.len();
+            self.omitted += 1;
+            self.first_omitted_ms.get_or_insert(offset_ms);
+        }
+    }
+}
+
+/// Writes `prefix` at the start of every line passing through.
+pub(crate) struct PrefixWriter<W> {
+    inner: W,
+    prefix: Vec<u8>,
+    at_line_start: bool,
+}
+
+impl<W: Write> Write for PrefixWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.prefix.is_empty() {
+            return self.inner.write(buf);
+        }
+        for piece in buf.split_inclusive(|&b| b == b'\n') {
+            if self.at_line_start {
+                self.inner.write_all(&self.prefix)?;
+            }
+            self.inner.write_all(piece)?;
+            self.at_line_start = piece.ends_with(b"\n");
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads `reader` line by line, echoing each line to `echo` as soon as it arrives
+/// so long-running commands stay visible, while accumulating it for telemetry.
+///
+/// With interleaving enabled, the echo and the shared record are updated under
+/// one lock, so the terminal sees both streams in the same order as the log.
+pub(crate) fn capture_stream_lines(
+    reader: impl Read + Send + 'static,
+    echo: impl Write,
+    stream: &'static str,
+    options: &CaptureOptions,
+) -> StreamCapture {
+    if options.capture_raw {
+        return capture_stream_raw(reader, echo, stream, options);
+    }
+    // Exact passthrough echoes raw reads as they arrive; otherwise each line is echoed as it's split off
+    let (tee, mut line_echo) = if options.exact_passthrough {
+        (Some(echo), None)
+    } else {
+        (None, Some(line_echo(echo, stream, options)))
+    };
+    let mut muted = io::sink();
+    let mut echo: &mut dyn Write = match &mut line_echo {
+        Some(echo) => echo,
+        None => &mut muted,
+    };
+    let hasher = options.hash_output.then(Sha256::new);
+    let mut reader = BufReader::with_capacity(READ_BUFFER_BYTES, SourceReader { inner: reader, hasher, tee });
+    let mut capture = LineCapture::new(stream, options);
+
+    loop {
+        // Blocks until the first byte is in, without waiting for a whole line
+        if capture.awaiting_output() && reader.fill_buf().is_ok_and(|buf| !buf.is_empty()) {
+            capture.output_started();
+        }
+
+        // Read raw bytes so invalid UTF-8 can't end the capture early
+        let mut raw = Vec::new();
+        match read_capped_line(&mut reader, &mut raw, options.max_line_bytes, &mut echo) {
+            Ok((0, _)) => break, // EOF
+            Ok((n, dropped)) => capture.push(raw, n, dropped, echo, !reader.buffer().is_empty()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    let _ = echo.flush();
+
+    let sha256 = reader.into_inner().hasher.map(|hasher| format!("{:x}", hasher.finalize()));
+    capture.finish(sha256)
+}
+
+/// The echo for split lines: batched, and prefixed with `echo_prefix`.
+pub(crate) fn line_echo<W: Write>(echo: W, stream: &str, options: &CaptureOptions) -> PrefixWriter<BufWriter<W>> {
+    let prefix = options.echo_prefix.as_ref().map(|prefix| prefix.replace("{stream}", stream).into_bytes());
+    let inner = BufWriter::with_capacity(READ_BUFFER_BYTES, echo);
+    PrefixWriter { inner, prefix: prefix.unwrap_or_default(), at_line_start: true }
+}
+
+/// A stream's lines as they're split off, whatever reads them.
+pub(crate) struct LineCapture<'a> {
+    stream: &'static str,
+    options: &'a CaptureOptions,
+    lines: Vec<String>,
+    line_offsets_ms: Option<Vec<f64>>,
+    total_lines: usize,
+    total_bytes: usize,
+    captured_bytes: usize,
+    truncated: bool,
+    truncated_lines: bool,
+    lossy: bool,
+    first_output_ms: Option<f64>,
+    had_ansi: bool,
+    pattern_matched: bool,
+    tail: Option<TailLines>,
+}
+
+impl<'a> LineCapture<'a> {
+    pub(crate) fn new(stream: &'static str, options: &'a CaptureOptions) -> Self {
+        LineCapture {
+            stream,
+            options,
+            lines: Vec::new(),
+            line_offsets_ms: options.timestamp_lines.then(Vec::new),
+            total_lines: 0,
+            total_bytes: 0,
+            captured_bytes: 0,
+            truncated: false,
+            truncated_lines: false,
+            lossy: false,
+            first_output_ms: None,
+            had_ansi: false,
+            pattern_matched: false,
+            tail: (options.head_lines.is_some() || options.tail_lines.is_some())
+                .then(|| TailLines::new(options.tail_lines.unwrap_or(0))),
+        }
+    }
+
+    /// Whether the stream's first byte is still to come.
+    pub(crate) fn awaiting_output(&self) -> bool {
+        self.first_output_ms.is_none()
+    }
+
+    /// Notes that the first byte just arrived.
+    pub(crate) fn output_started(&mut self) {
+        self.first_output_ms = Some(self.options.started.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    /// Takes a line of `n` bytes read, kept in `raw` short of `dropped` bytes, and echoes
+    /// it. `more_buffered` holds the flush back while further lines are already read.
+    pub(crate) fn push(&mut self, mut raw: Vec<u8>, n: usize, dropped: usize, echo: &mut dyn Write, more_buffered: bool) {
+        let options = self.options;
+        let offset_ms = options.started.elapsed().as_secs_f64() * 1000.0;
+        self.total_lines += 1;
+        self.total_bytes += n;
+        options.bytes_read.fetch_add(n, Ordering::Relaxed);
+        // Remove trailing newline for cleaner storage; a final line without one is replayed without one
+        let newline = raw.ends_with(b"\n");
+        if newline {
+            raw.pop();
+            if raw.ends_with(b"\r") {
+                raw.pop();
+            }
+        }
+        if !self.pattern_matched && options.fail_pattern.as_ref().is_some_and(|pattern| pattern.is_match(&raw)) {
+            self.pattern_matched = true;
+        }
+        let mut interleaved = options.interleaved.as_ref().map(|shared| shared.lock().unwrap());
+        // An overlong line has already been echoed as it was read
+        if dropped == 0 {
+            let _ = echo.write_all(&raw);
+        }
+        if newline {
+            let _ = echo.write_all(b"\n");
+        }
+        // Lines already read go out in one write, flushed before the next read can block.
+        // Interleaved lines are flushed under the lock to keep the terminal in log order.
+        if !more_buffered || interleaved.is_some() {
+            let _ = echo.flush();
+        }
+
+        // Once over the cap, stop retaining but keep passing through
+        let retained = n - dropped;
+        let within_cap = !self.truncated && self.captured_bytes + retained <= options.max_capture_bytes;
+        if let Some(tail) = &mut self.tail {
+            let line = stored_line(raw, dropped, options.strip_ansi, &mut self.lossy, &mut self.had_ansi);
+            let to_head = !tail.started() && self.lines.len() < options.head_lines.unwrap_or(0) && within_cap;
+            // The ring holds the rest of the stream; the head and interleaved record keep the usual cap
+            if to_head || interleaved.is_some() {
+                if within_cap {
+                    self.captured_bytes += retained;
+                } else {
+                    self.truncated = true;
+                }
+            }
+            if let (Some(interleaved), true) = (&mut interleaved, within_cap) {
+                interleaved.push(OutputLine { ts: offset_ms, stream: self.stream.to_string(), line: line.clone() });
+            }
+            if to_head {
+                if let Some(offsets) = &mut self.line_offsets_ms {
+                    offsets.push(offset_ms);
+                }
+                self.lines.push(line);
+            } else {
+                tail.push(line, offset_ms, options.max_capture_bytes);
+            }
+        } else if options.max_lines.is_some_and(|max| self.lines.len() >= max) {
+            self.truncated_lines = true;
+        } else if within_cap {
+            self.captured_bytes += retained;
+            let line = stored_line(raw, dropped, options.strip_ansi, &mut self.lossy, &mut self.had_ansi);
+            if let Some(interleaved) = &mut interleaved {
+                interleaved.push(OutputLine {
+                    ts: offset_ms,
+                    stream: self.stream.to_string(),
+                    line: line.clone(),
+                });
+            }
+            if let Some(offsets) = &mut self.line_offsets_ms {
+                offsets.push(offset_ms);
+            }
+            self.lines.push(line);
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    /// The capture once the stream has ended, with the hash of the bytes read.
+    pub(crate) fn finish(mut self, sha256: Option<String>) -> StreamCapture {
+        if let Some(tail) = self.tail {
+            self.truncated_lines |= tail.omitted > 0;
+            if let (Some(first_omitted_ms), Some(1..)) = (tail.first_omitted_ms, self.options.head_lines) {
+                if let Some(offsets) = &mut self.line_offsets_ms {
+                    offsets.push(first_omitted_ms);
+                }
+                self.lines.push(format!("…[omitted {} lines]", tail.omitted));
+            }
+            for (line, offset_ms) in tail.lines {
+                if let Some(offsets) = &mut self.line_offsets_ms {
+                    offsets.push(offset_ms);
+                }
+                self.lines.push(line);
+            }
+        }
+
+        StreamCapture {
+            lines: self.lines,
+            line_offsets_ms: self.line_offsets_ms,
+            total_lines: self.total_lines,
+            total_bytes: self.total_bytes,
+            truncated: self.truncated,
+            truncated_lines: self.truncated_lines,
+            lossy: self.lossy,
+            first_output_ms: self.first_output_ms,
+            sha256,
+            had_ansi: self.had_ansi,
+            pattern_matched: self.pattern_matched,
+            raw: None,
+        }
+    }
+}
+
+/// Stored form of a line: lossless if it's UTF-8, ANSI stripped if asked, with any cut marked.
+fn stored_line(raw: Vec<u8>, dropped: usize, strip: bool, lossy: &mut bool, had_ansi: &mut bool) -> String {
+    let mut line = match String::from_utf8(raw) {
+        Ok(line) => line,
+        Err(e) => {
+            *lossy = true;
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    };
+    if strip && line.contains('\x1b') {
+        line = strip_ansi(&line);
+        *had_ansi = true;
+    }
+    if dropped > 0 {
+        line.push_str(&format!("…[truncated {} bytes]", dropped));
+    }
+    line
+}
+
+/// Removes ANSI escape sequences: CSI (`ESC [ ... final`, e.g. colors), OSC
+/// (`ESC ] ... BEL` or `ESC ] ... ESC \`, e.g. hyperlinks) and two-byte escapes.
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameter and intermediate bytes run up to a final byte in @..~
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    if c == '\x07' || (escaped && c == '\\') {
+                        break;
+                    }
+                    escaped = c == '\x1b';
+                }
+            }
+            _ => {}
+        }
+    }
+    plain
+}
+
+/// Reads `reader` in large chunks, echoing each as it arrives and keeping the
+/// bytes unchanged up to the capture cap, with no per-line work beyond counting.
+/// Line options don't apply; `echo_prefix` still marks each echoed line.
+fn capture_stream_raw(mut reader: impl Read, echo: impl Write, stream: &str, options: &CaptureOptions) -> StreamCapture {
+    let mut echo = raw_echo(echo, stream, options);
+    let mut capture = RawCapture::new(options);
+    let mut buf = vec![0; READ_BUFFER_BYTES];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => capture.push(&buf[..n], &mut echo),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    capture.finish()
+}
+
+/// The echo for raw chunks, prefixed with `echo_prefix` unless passing through exactly.
+pub(crate) fn raw_echo<W: Write>(echo: W, stream: &str, options: &CaptureOptions) -> PrefixWriter<W> {
+    let prefix = match &options.echo_prefix {
+        Some(prefix) if !options.exact_passthrough => prefix.replace("{stream}", stream).into_bytes(),
+        _ => Vec::new(),
+    };
+    PrefixWriter { inner: echo, prefix, at_line_start: true }
+}
+
+/// A stream's bytes under `capture_raw`, whatever reads them.
+pub(crate) struct RawCapture<'a> {
+    options: &'a CaptureOptions,
+    capture: StreamCapture,
+    raw: Vec<u8>,
+    hasher: Option<Sha256>,
+    last_byte: u8,
+}
+
+impl<'a> RawCapture<'a> {
+    pub(crate) fn new(options: &'a CaptureOptions) -> Self {
+        RawCapture {
+            options,
+            capture: StreamCapture::default(),
+            raw: Vec::new(),
+            hasher: options.hash_output.then(Sha256::new),
+            last_byte: b'\n',
+        }
+    }
+
+    /// Takes and echoes the next non-empty chunk read.
+    pub(crate) fn push(&mut self, chunk: &[u8], echo: &mut impl Write) {
+        let options = self.options;
+        let n = chunk.len();
+        self.capture.first_output_ms.get_or_insert_with(|| options.started.elapsed().as_secs_f64() * 1000.0);
+        let _ = echo.write_all(chunk).and_then(|()| echo.flush());
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+        self.capture.total_bytes += n;
+        self.capture.total_lines += chunk.iter().filter(|&&b| b == b'\n').count();
+        self.last_byte = chunk[n - 1];
+        options.bytes_read.fetch_add(n, Ordering::Relaxed);
+
+        let keep = n.min(options.max_capture_bytes.saturating_sub(self.raw.len()));
+        self.raw.extend_from_slice(&chunk[..keep]);
+        self.capture.truncated |= keep < n;
+    }
+
+    /// The capture once the stream has ended.
+    pub(crate) fn finish(mut self) -> StreamCapture {
+        // A final line without a newline still counts
+        if self.last_byte != b'\n' {
+            self.capture.total_lines += 1;
+        }
+        self.capture.sha256 = self.hasher.map(|hasher| format!("{:x}", hasher.finalize()));
+        self.capture.raw = Some(self.raw);
+        self.capture
+    }
+}
+
+/// Reads one line into `raw` like `read_until(b'\n')`, but keeps at most
+/// `limit` bytes of it. Once past the limit, the kept prefix and everything
+/// after it are written straight to `echo`, so memory stays bounded however
+/// long the line is. Returns the bytes read, newline included, and the number
+/// of content bytes dropped.
+fn read_capped_line(
+    reader: &mut impl BufRead,
+    raw: &mut Vec<u8>,
+    limit: Option<usize>,
+    echo: &mut impl Write,
+) -> io::Result<(usize, usize)> {
+    let Some(limit) = limit else {
+        return reader.read_until(b'\n', raw).map(|n| (n, 0));
+    };
+
+    let mut total = 0;
+    let mut dropped = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok((total, dropped));
+        }
+
+        let (chunk_len, ended) = take_line_chunk(available, raw, limit, &mut dropped, echo);
+        total += chunk_len;
+        reader.consume(chunk_len);
+        if ended {
+            return Ok((total, dropped));
+        }
+    }
+}
+
+/// Takes the next buffered chunk of a line being read into `raw`, up to and
+/// including its newline if it has one. Content past `limit` isn't kept: the
+/// first time the line goes over, the kept prefix is written to `echo`, then
+/// each overflow after it, and the overflow is counted in `dropped`. Returns
+/// the chunk's length, for the reader to consume, and whether it ended the line.
+pub(crate) fn take_line_chunk(
+    available: &[u8],
+    raw: &mut Vec<u8>,
+    limit: usize,
+    dropped: &mut usize,
+    echo: &mut (impl Write + ?Sized),
+) -> (usize, bool) {
+    let newline = available.iter().position(|&b| b == b'\n');
+    let content = &available[..newline.unwrap_or(available.len())];
+    let keep = content.len().min(limit.saturating_sub(raw.len()));
+    raw.extend_from_slice(&content[..keep]);
+
+    let overflow = &content[keep..];
+    if !overflow.is_empty() {
+        if *dropped == 0 {
+            let _ = echo.write_all(raw);
+        }
+        let _ = echo.write_all(overflow);
+        *dropped += overflow.len();
+    }
+    if newline.is_some() {
+        raw.push(b'\n');
+    }
+    (newline.map_or(available.len(), |i| i + 1), newline.is_some())
+}
+
+/// Bytes of the driver's stdin kept while forwarding it.
+#[derive(Default)]
+pub(crate) struct StdinCapture {
+    pub(crate) bytes: Vec<u8>,
+    /// Set once more input arrived than the cap allowed keeping.
+    pub(crate) truncated: bool,
+}
+
+/// Copies the driver's stdin into the child, keeping the first `limit` bytes in `captured`.
+/// On EOF `eof_marker` is sent if given (for a PTY), then the child's stdin is
+/// dropped so a pipe sees end of input.
+pub(crate) fn forward_stdin(
+    mut child_stdin: impl Write,
+    captured: Arc<Mutex<StdinCapture>>,
+    limit: usize,
+    eof_marker: Option<u8>,
+) {
+    let mut stdin = io::stdin().lock();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) => {
+                if let Some(marker) = eof_marker {
+                    let _ = child_stdin.write_all(&[marker]);
+                }
+                break;
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+
+        {
+            let mut captured = captured.lock().unwrap();
+            let room = limit.saturating_sub(captured.bytes.len());
+            captured.bytes.extend_from_slice(&buf[..n.min(room)]);
+            captured.truncated |= n > room;
+        }
+
+        // Child closed its stdin or exited
+        if child_stdin.write_all(&buf[..n]).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_line_bytes: Option<usize>) -> CaptureOptions {
+        CaptureOptions {
+            max_capture_bytes: usize::MAX,
+            max_lines: None,
+            head_lines: None,
+            tail_lines: None,
+            max_line_bytes,
+            started: Instant::now(),
+            timestamp_lines: false,
+            interleaved: None,
+            bytes_read: Arc::default(),
+            hash_output: false,
+            strip_ansi: false,
+            echo_prefix: None,
+            exact_passthrough: false,
+            fail_pattern: None,
+            capture_raw: false,
+        }
+    }
+
+    /// Hands out at most `step` bytes per read, like a pipe drained as it fills.
+    struct Trickle {
+        input: io::Cursor<Vec<u8>>,
+        step: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.step);
+            self.input.read(&mut buf[..len])
+        }
+    }
+
+    /// The lines, total bytes and echo of the line-at-a-time reader the chunked capture replaced.
+    fn split_line_by_line(input: &[u8]) -> (Vec<String>, usize, Vec<u8>) {
+        let mut reader = BufReader::new(input);
+        let (mut lines, mut total_bytes, mut echo) = (Vec::new(), 0, Vec::new());
+        loop {
+            let mut raw = Vec::new();
+            let n = reader.read_until(b'\n', &mut raw).unwrap();
+            if n == 0 {
+                break;
+            }
+            total_bytes += n;
+            let newline = raw.ends_with(b"\n");
+            let line = raw.strip_suffix(b"\n").unwrap_or(&raw);
+            let line = line.strip_suffix(b"\r").filter(|_| newline).unwrap_or(line);
+            echo.extend_from_slice(line);
+            if newline {
+                echo.push(b'\n');
+            }
+            lines.push(String::from_utf8_lossy(line).into_owned());
+        }
+        (lines, total_bytes, echo)
+    }
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let mut many_short = Vec::new();
+        for i in 0..50_000 {
+            many_short.extend_from_slice(format!("line {}\n", i).as_bytes());
+        }
+        // Lines straddling the 64KB read boundary, one of them far longer than a read
+        let mut straddling = vec![b'a'; READ_BUFFER_BYTES - 3];
+        straddling.extend_from_slice(b"\nbcdef\n");
+        straddling.resize(straddling.len() + 3 * READ_BUFFER_BYTES + 17, b'x');
+        straddling.extend_from_slice(b"\r\ntail");
+        vec![
+            Vec::new(),
+            b"\n".to_vec(),
+            b"no newline".to_vec(),
+            b"one\n\ntwo\r\nthree\r\n\r\nlast".to_vec(),
+            b"lone \r inside\rend\r".to_vec(),
+            many_short,
+            straddling,
+        ]
+    }
+
+    #[test]
+    fn chunked_capture_splits_lines_like_line_by_line_reads() {
+        for input in inputs() {
+            let (lines, total_bytes, echoed) = split_line_by_line(&input);
+            // No line limit reads with `read_until`; a limit that's never hit takes the capped path
+            for max_line_bytes in [None, Some(usize::MAX)] {
+                for step in [usize::MAX, 7] {
+                    let mut echo = Vec::new();
+                    let reader = Trickle { input: io::Cursor::new(input.clone()), step };
+                    let capture = capture_stream_lines(reader, &mut echo, "stdout", &options(max_line_bytes));
+                    assert_eq!(capture.lines, lines, "{:?}, {}-byte reads", max_line_bytes, step);
+                    assert_eq!(capture.total_lines, lines.len());
+                    assert_eq!(capture.total_bytes, total_bytes);
+                    assert_eq!(capture.total_bytes, input.len());
+                    assert!(echo == echoed, "echo differs with {:?}, {}-byte reads", max_line_bytes, step);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Driver flags given before a `--` separator, e.g.
+//! `rust-telemetry-driver --timeout 5 --format csv -- make test`. They
+//! override the matching `TELEMETRY_*` variables.
+
+use std::time::Duration;
+
+use crate::event::EventMode;
+use crate::format::OutputFormat;
+use crate::sink::{expand_log_path, LogStream};
+use crate::Config;
+
+/// Recognized flags and whether each takes a value.
+const FLAGS: &[(&str, bool)] = &[
+    ("--log", true),
+    ("--format", true),
+    ("--event-mode", true),
+    ("--timeout", true),
+    ("--tag", true),
+    ("--http-endpoint", true),
+    ("--max-capture-bytes", true),
+    ("--heartbeat-ms", true),
+    ("--pty", false),
+    ("--only-failures", false),
+    ("--quiet", false),
+    ("--capture-stdin", false),
+    ("--no-capture-stdout", false),
+    ("--no-capture-stderr", false),
+];
+
+/// The driver's own flags plus the command to run.
+#[derive(Debug, Clone)]
+pub struct CliArgs<'a> {
+    flags: Vec<(&'a str, Option<&'a str>)>,
+    pub command: &'a [String],
+}
+
+/// Splits the driver's arguments (without the program name). Flags are only
+/// looked for when the first argument is one, and must then be ended by `--`;
+/// otherwise everything is the command, so `rust-telemetry-driver mycmd --timeout`
+/// passes `--timeout` to `mycmd`.
+pub fn parse_args(args: &[String]) -> Result<CliArgs<'_>, String> {
+    if !args.first().is_some_and(|arg| arg.starts_with('-')) {
+        return Ok(CliArgs { flags: Vec::new(), command: args });
+    }
+
+    let mut flags = Vec::new();
+    let mut rest = args.iter().enumerate();
+    while let Some((i, arg)) = rest.next() {
+        if arg == "--" {
+            return Ok(CliArgs { flags, command: &args[i + 1..] });
+        }
+        if !arg.starts_with('-') {
+            return Err(format!("expected -- before the command {:?}", arg));
+        }
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        let Some(&(_, takes_value)) = FLAGS.iter().find(|(flag, _)| *flag == name) else {
+            return Err(format!("unknown flag {}", name));
+        };
+        let value = match (takes_value, inline_value) {
+            (true, Some(value)) => Some(value),
+            (true, None) => Some(rest.next().map(|(_, v)| v.as_str()).ok_or_else(|| format!("{} needs a value", name))?),
+            (false, None) => None,
+            (false, Some(_)) => return Err(format!("{} takes no value", name)),
+        };
+        flags.push((name, value));
+    }
+    Err("driver flags must be followed by -- and the command".to_string())
+}
+
+impl CliArgs<'_> {
+    /// Applies the flags on top of `config`.
+    pub fn apply(&self, config: &mut Config) -> Result<(), String> {
+        for &(name, value) in &self.flags {
+            let value = value.unwrap_or_default();
+            let invalid = || format!("invalid value {:?} for {}", value, name);
+            match name {
+                "--log" => {
+                    config.telemetry_file = expand_log_path(value, &config.session_id);
+                    config.log_stream = LogStream::for_path(&config.telemetry_file);
+                }
+                "--format" => config.format = OutputFormat::parse(value).ok_or_else(invalid)?,
+                "--event-mode" => config.event_mode = EventMode::parse(value).ok_or_else(invalid)?,
+                "--timeout" => {
+                    let seconds: f64 = value.parse().map_err(|_| invalid())?;
+                    config.timeout = Some(Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?);
+                }
+                "--tag" => {
+                    let (key, tag) = value.split_once('=').ok_or_else(invalid)?;
+                    config.tags.insert(key.trim().to_string(), tag.trim().to_string());
+                }
+                "--http-endpoint" => config.http_endpoint = Some(value.to_string()),
+                "--max-capture-bytes" => config.max_capture_bytes = value.parse().map_err(|_| invalid())?,
+                "--heartbeat-ms" => {
+                    let ms: u64 = value.parse().map_err(|_| invalid())?;
+                    config.heartbeat = (ms > 0).then(|| Duration::from_millis(ms));
+                }
+                "--pty" => config.pty = true,
+                "--only-failures" => config.only_failures = true,
+                "--quiet" => config.quiet = true,
+                "--capture-stdin" => config.capture_stdin = true,
+                "--no-capture-stdout" => config.capture_stdout = false,
+                "--no-capture-stderr" => config.capture_stderr = false,
+                _ => unreachable!("flag {} is listed in FLAGS", name),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_before_the_separator_are_the_drivers() {
+        let argv = args(&["--timeout", "5", "--format=csv", "--quiet", "--", "make", "--timeout", "1"]);
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.flags, [("--timeout", Some("5")), ("--format", Some("csv")), ("--quiet", None)]);
+        assert_eq!(parsed.command, args(&["make", "--timeout", "1"]));
+
+        let mut config = Config::default();
+        parsed.apply(&mut config).unwrap();
+        assert_eq!(config.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.format, OutputFormat::Csv);
+        assert!(config.quiet);
+    }
+
+    #[test]
+    fn flags_after_the_command_are_the_commands() {
+        let argv = args(&["make", "--timeout", "5", "--", "x"]);
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.flags.is_empty());
+        assert_eq!(parsed.command, argv.as_slice());
+    }
+
+    #[test]
+    fn malformed_flags_are_rejected() {
+        assert!(parse_args(&args(&["--timeout", "5", "make"])).unwrap_err().contains("expected --"));
+        assert!(parse_args(&args(&["--bogus", "--", "make"])).unwrap_err().contains("unknown flag"));
+        assert!(parse_args(&args(&["--quiet=yes", "--", "make"])).unwrap_err().contains("takes no value"));
+        assert!(parse_args(&args(&["--timeout"])).unwrap_err().contains("needs a value"));
+        assert!(parse_args(&args(&["--quiet"])).unwrap_err().contains("followed by --"));
+
+        let argv = args(&["--timeout", "soon", "--", "make"]);
+        assert!(parse_args(&argv).unwrap().apply(&mut Config::default()).is_err());
+    }
+}
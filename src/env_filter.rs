@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+/// Placeholder written in place of a redacted environment value.
+pub const REDACTED: &str = "<redacted>";
+
+/// Secret-looking names redacted even when `TELEMETRY_ENV_REDACT` is unset.
+pub const DEFAULT_REDACT_PATTERNS: &[&str] = &[
+    "*_TOKEN",
+    "*_SECRET",
+    "*_SECRET_*",
+    "*PASSWORD*",
+    "*_API_KEY",
+    "*_ACCESS_KEY",
+    "*_PRIVATE_KEY",
+    "*_CREDENTIALS",
+];
+
+/// Longest environment value recorded when `TELEMETRY_ENV_MAX_VALUE_BYTES` is unset.
+pub const DEFAULT_ENV_MAX_VALUE_BYTES: usize = 4096;
+/// Most environment variables recorded when `TELEMETRY_ENV_MAX_COUNT` is unset.
+pub const DEFAULT_ENV_MAX_COUNT: usize = 1024;
+
+/// Splits a comma-separated pattern list, dropping empty entries.
+pub(crate) fn parse_pattern_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Case-insensitive glob match supporting `*` (any run) and `?` (any one character).
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    // Environment names are nearly always ASCII, which can be matched in place
+    if pattern.is_ascii() && name.is_ascii() {
+        return wildcard_match(pattern.as_bytes(), name.as_bytes(), b'*', b'?', u8::eq_ignore_ascii_case);
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    wildcard_match(&pattern, &name, '*', '?', char::eq_ignore_ascii_case)
+}
+
+fn wildcard_match<T: Copy + PartialEq>(pattern: &[T], name: &[T], star: T, any: T, eq: impl Fn(&T, &T) -> bool) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name index it was tried against
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(&c) if c == star => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == any || eq(c, &name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == star)
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|p| glob_match(p, name))
+}
+
+/// Drops every variable not matching one of `patterns`.
+pub(crate) fn allow_env(env: &mut HashMap<String, String>, patterns: &[String]) {
+    env.retain(|key, _| matches_any(patterns, key));
+}
+
+/// Replaces the value of every variable matching `patterns` with [`REDACTED`].
+pub(crate) fn redact_env(env: &mut HashMap<String, String>, patterns: &[String]) {
+    for (key, value) in env.iter_mut() {
+        if matches_any(patterns, key) {
+            *value = REDACTED.to_string();
+        }
+    }
+}
+
+/// Cuts values longer than `max_value_bytes` with a `…[truncated N bytes]`
+/// marker and keeps only the first `max_count` variables by name.
+/// Returns whether anything was cut or dropped.
+pub(crate) fn cap_env(env: &mut HashMap<String, String>, max_value_bytes: Option<usize>, max_count: Option<usize>) -> bool {
+    let mut capped = false;
+    if let Some(max_count) = max_count.filter(|&max| env.len() > max) {
+        let mut names: Vec<String> = env.keys().cloned().collect();
+        names.sort();
+        for name in &names[max_count..] {
+            env.remove(name);
+        }
+        capped = true;
+    }
+    if let Some(max_bytes) = max_value_bytes {
+        for value in env.values_mut().filter(|value| value.len() > max_bytes) {
+            let mut cut = max_bytes;
+            while !value.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let dropped = value.len() - cut;
+            value.truncate(cut);
+            value.push_str(&format!("…[truncated {} bytes]", dropped));
+            capped = true;
+        }
+    }
+    capped
+}
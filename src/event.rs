@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+/// A single record written to the telemetry log.
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct TelemetryEvent {
+    pub event_id: String,
+    /// The first 8 hex digits of `event_id`, for quoting in summaries and by hand.
+    pub short_id: String,
+    pub event_type: String,
+    /// Version of the driver that wrote the event ([`crate::DRIVER_VERSION`]).
+    pub driver_version: &'static str,
+    /// Revision of the event layout ([`crate::SCHEMA_VERSION`]).
+    pub schema_version: u32,
+    pub timestamp: f64,
+    /// The same instant in integer nanoseconds, for ordering closely spaced events.
+    /// A `u64` lasts until 2554 and, unlike `u128`, fits MessagePack.
+    pub timestamp_ns: u64,
+    pub pid: i32,
+    pub ppid: i32,
+    /// Machine the driver ran on, for filtering a fleet's logs.
+    pub hostname: Option<String>,
+    /// Docker/containerd id when running in a container (Linux).
+    pub container_id: Option<String>,
+    /// Real and effective user and group ids the command ran under (Unix, start event).
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub euid: Option<u32>,
+    pub egid: Option<u32>,
+    /// File mode creation mask in octal, e.g. `0022` (Unix, start event).
+    pub umask: Option<String>,
+    /// String SID of the token's user (Windows, start event).
+    pub user_sid: Option<String>,
+    /// Whether the driver's own stdio was a terminal (start event).
+    pub stdin_is_tty: Option<bool>,
+    pub stdout_is_tty: Option<bool>,
+    pub stderr_is_tty: Option<bool>,
+    pub session_id: String,
+    pub parent_session_id: Option<String>,
+    /// 1-based run of the command within the session, when `TELEMETRY_RETRIES` is set.
+    pub attempt: Option<u32>,
+    pub command: Vec<String>,
+    /// Canonical path of the launched executable, or the name as given if it couldn't be resolved.
+    pub resolved_executable: Option<String>,
+    /// Whether `-o pipefail` was passed to the shell, when `TELEMETRY_PIPEFAIL` is set (start event).
+    /// `command` is always recorded as given.
+    pub pipefail: Option<bool>,
+    pub cwd: String,
+    /// Branch, commit and whether tracked files were modified, when the cwd is
+    /// in a git checkout and `TELEMETRY_CAPTURE_GIT` is set (start event).
+    pub git_branch: Option<String>,
+    pub git_sha: Option<String>,
+    pub git_dirty: Option<bool>,
+    pub env: HashMap<String, String>,
+    /// Set when `env` values were cut or variables dropped to stay within the env caps.
+    pub env_truncated: Option<bool>,
+    /// Where each variable in `env` came from (start event).
+    pub env_source: Option<HashMap<String, EnvSource>>,
+    pub tags: HashMap<String, String>,
+    /// Changes made to the child's environment (start event).
+    pub child_env_overrides: Option<ChildEnvOverrides>,
+    pub resource_usage: Option<ResourceUsage>,
+    /// The driver's own usage (`RUSAGE_SELF` on Unix), to gauge the instrumentation's cost (stats event).
+    pub driver_resource_usage: Option<ResourceUsage>,
+    /// What `resource_usage` covers: `tree` (the child and its waited-for descendants) or `child`.
+    pub resource_scope: Option<&'static str>,
+    /// Resident set of the running child, sampled for heartbeats.
+    pub rss_kb: Option<i64>,
+    pub duration_ms: Option<u64>,
+    /// Milliseconds from spawn to the first byte on stdout or stderr; `None` if there was no output.
+    pub time_to_first_output_ms: Option<f64>,
+    /// Wall time the driver spent outside the child's run: setup before the spawn plus
+    /// collecting and logging after the exit, up to this event (stats event).
+    pub driver_overhead_ms: Option<f64>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub terminated_by_signal: Option<bool>,
+    /// Whether the kernel wrote a core file when the signal killed the child.
+    pub core_dumped: Option<bool>,
+    /// Plain strings, except lines embedded as parsed JSON under `TELEMETRY_PARSE_JSON_LINES`.
+    pub stdout_lines: Option<Vec<serde_json::Value>>,
+    pub stderr_lines: Option<Vec<String>>,
+    /// Base64 of the stream's bytes as read, in place of the lines (`TELEMETRY_CAPTURE_RAW`, end event).
+    pub stdout_raw: Option<String>,
+    pub stderr_raw: Option<String>,
+    /// Arrival time of each `stdout_lines` entry in ms since spawn (`TELEMETRY_TIMESTAMP_LINES`).
+    pub stdout_line_offsets_ms: Option<Vec<f64>>,
+    /// Arrival time of each `stderr_lines` entry in ms since spawn (`TELEMETRY_TIMESTAMP_LINES`).
+    pub stderr_line_offsets_ms: Option<Vec<f64>>,
+    pub stdin_provided: Option<String>,
+    /// `utf8`, or `base64` when the captured stdin wasn't valid UTF-8.
+    pub stdin_encoding: Option<String>,
+    pub stdin_truncated: Option<bool>,
+    pub stdout_size_bytes: Option<usize>,
+    pub stderr_size_bytes: Option<usize>,
+    /// Hex SHA-256 of the raw stdout bytes (`TELEMETRY_HASH_OUTPUT`, end event).
+    pub stdout_sha256: Option<String>,
+    /// Hex SHA-256 of the raw stderr bytes (`TELEMETRY_HASH_OUTPUT`, end event).
+    pub stderr_sha256: Option<String>,
+    pub truncated: Option<bool>,
+    /// Whether either stream hit `TELEMETRY_MAX_LINES`; the line counts still cover everything.
+    pub truncated_lines: Option<bool>,
+    pub lossy: Option<bool>,
+    /// Whether `TELEMETRY_STRIP_ANSI` removed escape sequences from any stored line (end event).
+    pub had_ansi: Option<bool>,
+    pub error: Option<String>,
+    pub timed_out: Option<bool>,
+    /// The run finished within its timeout but used at least `TELEMETRY_SLOW_THRESHOLD_PCT` of it;
+    /// `None` without a timeout.
+    pub slow_warning: Option<bool>,
+    /// Whether the output matched `TELEMETRY_FAIL_ON_PATTERN`; `None` without a pattern.
+    pub pattern_matched: Option<bool>,
+    pub ancestry: Option<Vec<AncestorProcess>>,
+    pub output: Option<Vec<OutputLine>>,
+    /// Lines of both streams in arrival order (`TELEMETRY_COMBINE_OUTPUT`).
+    pub combined_output: Option<Vec<String>>,
+    pub resource_samples: Option<Vec<ResourceSample>>,
+    /// Most descriptors seen open across `resource_samples` (end event).
+    pub peak_open_fds: Option<usize>,
+    /// Paths the child and its descendants opened successfully, when file tracing is on.
+    pub files_opened: Option<Vec<String>>,
+    /// Descendants of the child and how they exited, when `TELEMETRY_CAPTURE_TREE` is on (Linux x86_64).
+    pub child_processes: Option<Vec<ChildProcess>>,
+    /// Remote `ip:port` addresses the child was seen connected to, when network tracing is on.
+    pub net_connections: Option<Vec<String>>,
+    /// Signal the driver received and forwarded to the child (`process_interrupted` events).
+    pub interrupted_by: Option<i32>,
+    /// Set when output or env was cut to keep the event within `TELEMETRY_MAX_EVENT_BYTES`.
+    pub event_trimmed: Option<bool>,
+    /// What `TELEMETRY_EVENT_FILTER` turned this event into; when set, it is
+    /// logged in place of the fields above.
+    #[serde(skip)]
+    pub filter_output: Option<serde_json::Value>,
+}
+
+/// A failure inside the driver itself, such as an event the sinks couldn't
+/// take or a lost capture thread. Written as a JSON line to stderr rather
+/// than to the sinks, which may be what failed.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct DriverError {
+    /// Always `driver_error`.
+    pub event_type: &'static str,
+    pub driver_version: &'static str,
+    pub timestamp: f64,
+    pub session_id: String,
+    /// `sink_write`, `sink_flush` or `capture`.
+    pub category: &'static str,
+    pub message: String,
+}
+
+/// Summary of a completed child process, returned by [`crate::run`].
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ProcessStats {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub duration_ms: u64,
+    pub time_to_first_output_ms: Option<f64>,
+    /// The child's exit code, or `128 + signal` if it was killed by a signal.
+    /// `-1` only when the platform reported neither.
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub terminated_by_signal: bool,
+    pub core_dumped: bool,
+    pub timed_out: bool,
+    /// A line of output matched `TELEMETRY_FAIL_ON_PATTERN`.
+    pub pattern_matched: bool,
+    pub stdout_lines: usize,
+    pub stderr_lines: usize,
+    pub total_output_bytes: usize,
+}
+
+/// Resource usage of the child as reported by the OS. On Unix this comes from
+/// reaping it with `wait4`, which also counts every descendant it waited for
+/// (scope `tree`); on Windows it is the direct child alone (`child`).
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ResourceUsage {
+    pub user_time_ms: i64,
+    pub system_time_ms: i64,
+    pub max_rss_kb: i64,
+    pub page_faults: i64,
+    pub context_switches: i64,
+}
+
+/// What `TELEMETRY_CHILD_ENV_SET` and `TELEMETRY_CHILD_ENV_UNSET` changed in
+/// the environment the child inherited. Set values are redacted like `env`.
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct ChildEnvOverrides {
+    pub set: HashMap<String, String>,
+    /// Only the names that were actually present.
+    pub unset: Vec<String>,
+}
+
+/// Whether the child got a variable from the driver's own environment or
+/// from the driver itself: the session and trace ids, and `TELEMETRY_CHILD_ENV_SET`.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvSource {
+    Inherited,
+    DriverAdded,
+}
+
+/// A process the child spawned, directly or further down the tree.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ChildProcess {
+    pub pid: i32,
+    /// Command name as of its last exec, from `/proc/<pid>/comm`.
+    pub comm: String,
+    /// Exit code, or `128 + signal` if it was killed by a signal; `None` if it
+    /// was still running, detached from the command, when the command exited.
+    pub exit_code: Option<i32>,
+}
+
+/// One process in the driver's parent chain.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AncestorProcess {
+    pub pid: i32,
+    pub comm: String,
+}
+
+/// A captured line tagged with its stream, for the interleaved `output` field.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct OutputLine {
+    /// Milliseconds since the child was spawned.
+    pub ts: f64,
+    /// `"stdout"` or `"stderr"`.
+    pub stream: String,
+    pub line: String,
+}
+
+/// One reading of the running child's resource consumption.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ResourceSample {
+    /// Milliseconds since the child was spawned.
+    pub ts: f64,
+    pub rss_kb: i64,
+    /// CPU used since the previous sample, as a percentage of one core.
+    pub cpu_pct: f64,
+    /// Descriptors open at the time; `None` if `/proc/<pid>/fd` couldn't be read.
+    pub open_fds: Option<usize>,
+}
+
+/// How many records a run produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventMode {
+    /// Separate `process_start`, `process_end` and `process_stats` events.
+    #[default]
+    Multi,
+    /// One `process_complete` event carrying everything the three would.
+    Single,
+}
+
+impl EventMode {
+    /// Parses a `TELEMETRY_EVENT_MODE` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "multi" => Some(EventMode::Multi),
+            "single" => Some(EventMode::Single),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! `TELEMETRY_EVENT_FILTER`: a shell command each event is piped through
+//! before it is logged, e.g. `jq -c '.tags.git_sha = env.GIT_SHA'`. The filter
+//! reads one snake_case JSON object on stdin and writes the object to log on
+//! stdout. If it fails, runs past `TELEMETRY_EVENT_FILTER_TIMEOUT_MS` or
+//! prints anything but a JSON object, the original event is logged instead.
+//! CSV columns stay the event's own fields: keys the filter adds are left out
+//! of the row, and ones it removes are left empty.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::event::TelemetryEvent;
+use crate::format::{event_json, JsonCase};
+
+/// `event` with the filter's output attached, or unchanged (with a warning) if the filter failed.
+pub(crate) fn filter_event(filter: &str, timeout: Duration, event: &TelemetryEvent) -> TelemetryEvent {
+    match run_filter(filter, timeout, event) {
+        Ok(value) => TelemetryEvent { filter_output: Some(value), ..event.clone() },
+        Err(e) => {
+            eprintln!("⚠️ TELEMETRY_EVENT_FILTER failed on {}: {}; logging it unfiltered", event.event_type, e);
+            event.clone()
+        }
+    }
+}
+
+fn run_filter(filter: &str, timeout: Duration, event: &TelemetryEvent) -> io::Result<Value> {
+    let input = event_json(event, JsonCase::Snake)?;
+    let mut cmd = shell_command(filter);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::inherit());
+    // So a timeout also kills whatever the shell started
+    #[cfg(unix)]
+    crate::process::own_process_group(&mut cmd);
+    let mut child = cmd.spawn()?;
+
+    // Written and read from other threads so a filter that prints before reading everything can't deadlock
+    let mut stdin = child.stdin.take().expect("Failed to get filter stdin");
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take().expect("Failed to get filter stdout");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let (status, timed_out, _) = crate::process::wait_with_timeout(&mut child, Some(timeout), false, None)?;
+    let _ = writer.join();
+    let output = reader.join().map_err(|_| io::Error::other("reading its output failed"))??;
+
+    if timed_out {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, format!("timed out after {}ms", timeout.as_millis())));
+    }
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {}", status)));
+    }
+    match serde_json::from_slice(&output)? {
+        value @ Value::Object(_) => Ok(value),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "output is not a JSON object")),
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
@@ -0,0 +1,173 @@
+//! `TELEMETRY_MAX_EVENT_BYTES`: events whose rendered record would be larger
+//! are cut down to fit instead of failing the write, e.g. on a Kafka message
+//! size limit. Captured output goes first, largest field first, then `env`
+//! values, largest first; ids, the command and its outcome are never touched.
+
+use serde_json::{Map, Value};
+
+use crate::event::TelemetryEvent;
+use crate::format::{event_value, render_event, JsonCase, OutputFormat};
+
+/// Output fields, in the order they are trimmed when equally large. Lists are
+/// halved from the end until they fit; strings are dropped whole. Dropped
+/// fields are left as `null` rather than removed, so CSV rows keep every
+/// column of the header.
+const OUTPUT_FIELDS: &[&str] = &[
+    "stdout_lines",
+    "stderr_lines",
+    "stdout_raw",
+    "stderr_raw",
+    "stdout_line_offsets_ms",
+    "stderr_line_offsets_ms",
+    "output",
+    "combined_output",
+    "stdin_provided",
+];
+
+/// `event` cut down to render within `max_bytes` and marked with
+/// `event_trimmed`, or `None` if it already fits. An event that is still too
+/// big with its output and env gone is returned as far as it got.
+pub(crate) fn trim_event(
+    event: &TelemetryEvent,
+    max_bytes: usize,
+    format: OutputFormat,
+    case: JsonCase,
+) -> Option<TelemetryEvent> {
+    if render_event(event, format, case).ok()?.len() <= max_bytes {
+        return None;
+    }
+    let Ok(Value::Object(mut fields)) = event_value(event, JsonCase::Snake) else {
+        return None;
+    };
+    fields.insert("event_trimmed".to_string(), Value::Bool(true));
+    let mut trimmed = TelemetryEvent { event_trimmed: Some(true), filter_output: None, ..event.clone() };
+    let fits = |fields: &Map<String, Value>, trimmed: &mut TelemetryEvent| {
+        trimmed.filter_output = Some(Value::Object(fields.clone()));
+        render_event(trimmed, format, case).is_ok_and(|record| record.len() <= max_bytes)
+    };
+
+    while !fits(&fields, &mut trimmed) {
+        let Some(name) = largest(OUTPUT_FIELDS.iter().filter_map(|name| Some((*name, fields.get(*name)?)))) else {
+            break;
+        };
+        match fields.get_mut(&name) {
+            Some(Value::Array(items)) if items.len() > 1 => items.truncate(items.len() / 2),
+            _ => {
+                fields.insert(name, Value::Null);
+            }
+        }
+    }
+
+    while !fits(&fields, &mut trimmed) {
+        let Some(Value::Object(env)) = fields.get_mut("env") else {
+            break;
+        };
+        let Some(name) = largest(env.iter().map(|(name, value)| (name.as_str(), value))) else {
+            break;
+        };
+        env.remove(&name);
+        if let Some(Value::Object(sources)) = fields.get_mut("env_source") {
+            sources.remove(&name);
+        }
+    }
+
+    if !fits(&fields, &mut trimmed) {
+        let event_type = &event.event_type;
+        eprintln!("⚠️ {} event is still over TELEMETRY_MAX_EVENT_BYTES ({}) after trimming", event_type, max_bytes);
+    }
+    Some(trimmed)
+}
+
+/// The name of the largest non-empty value, by its compact JSON length.
+fn largest<'a>(candidates: impl Iterator<Item = (&'a str, &'a Value)>) -> Option<String> {
+    candidates
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| (name, json_len(value)))
+        .fold(None, |best: Option<(&str, usize)>, (name, len)| match best {
+            Some((_, best_len)) if best_len >= len => best,
+            _ => Some((name, len)),
+        })
+        .map(|(name, _)| name.to_string())
+}
+
+fn json_len(value: &Value) -> usize {
+    serde_json::to_vec(value).map_or(0, |json| json.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn oversized_event() -> TelemetryEvent {
+        TelemetryEvent {
+            event_id: "6f1c0d2e-58a4-4b8e-9f43-5d7e2c51a0b9".to_string(),
+            event_type: "process_end".to_string(),
+            session_id: "session".to_string(),
+            command: vec!["make".to_string(), "all".to_string()],
+            exit_code: Some(2),
+            env: HashMap::from([
+                ("BIG".to_string(), "x".repeat(20_000)),
+                ("HOME".to_string(), "/home/user".to_string()),
+            ]),
+            stdout_lines: Some((0..5000).map(|i| Value::from(format!("{:0100}", i))).collect()),
+            stderr_lines: Some(vec!["error: build failed".to_string()]),
+            ..TelemetryEvent::default()
+        }
+    }
+
+    fn rendered(event: &TelemetryEvent) -> Value {
+        serde_json::from_slice(&render_event(event, OutputFormat::Jsonl, JsonCase::Snake).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn oversized_event_is_trimmed_below_the_limit() {
+        let event = oversized_event();
+        let trimmed = trim_event(&event, 8192, OutputFormat::Jsonl, JsonCase::Snake).expect("event was over the limit");
+        assert!(render_event(&trimmed, OutputFormat::Jsonl, JsonCase::Snake).unwrap().len() <= 8192);
+
+        let fields = rendered(&trimmed);
+        assert_eq!(fields["event_trimmed"], true);
+        for core in ["event_id", "event_type", "session_id", "command", "exit_code"] {
+            assert_eq!(fields[core], rendered(&event)[core], "{}", core);
+        }
+        // The env alone is over the limit, so all output goes before it's touched
+        assert!(fields["stdout_lines"].is_null() && fields["stderr_lines"].is_null());
+        assert!(fields["env"].get("BIG").is_none());
+        assert_eq!(fields["env"]["HOME"], "/home/user");
+    }
+
+    #[test]
+    fn output_lists_are_halved_before_env_is_touched() {
+        let event = TelemetryEvent { env: HashMap::new(), ..oversized_event() };
+        let trimmed = trim_event(&event, 100_000, OutputFormat::Jsonl, JsonCase::Snake).expect("event was over the limit");
+        let fields = rendered(&trimmed);
+        let stdout = fields["stdout_lines"].as_array().unwrap();
+        assert!(!stdout.is_empty() && stdout.len() < 5000);
+        assert_eq!(stdout[0], format!("{:0100}", 0));
+        assert_eq!(fields["stderr_lines"], serde_json::json!(["error: build failed"]));
+    }
+
+    #[test]
+    fn trimmed_csv_row_keeps_every_header_column() {
+        let event = oversized_event();
+        let trimmed = trim_event(&event, 8192, OutputFormat::Csv, JsonCase::Snake).expect("event was over the limit");
+        let header = crate::format::csv_header(&event, JsonCase::Snake).unwrap();
+        let row = String::from_utf8(render_event(&trimmed, OutputFormat::Csv, JsonCase::Snake).unwrap()).unwrap();
+        assert!(row.len() <= 8192);
+
+        let rows = crate::format::parse_csv(&(header + &row));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].len(), rows[1].len());
+        let column = rows[0].iter().position(|name| name == "stdout_lines").unwrap();
+        assert_eq!(rows[1][column], "");
+    }
+
+    #[test]
+    fn event_within_the_limit_is_left_alone() {
+        let event = TelemetryEvent { event_type: "process_start".to_string(), ..oversized_event() };
+        let event = TelemetryEvent { env: HashMap::new(), stdout_lines: None, ..event };
+        assert!(trim_event(&event, 64 * 1024, OutputFormat::Jsonl, JsonCase::Snake).is_none());
+    }
+}
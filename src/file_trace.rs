@@ -0,0 +1,368 @@
+//! Recording which files the child opens (`TELEMETRY_TRACE_FILES`) and which
+//! processes it spawns (`TELEMETRY_CAPTURE_TREE`), by running it under ptrace:
+//! `open`-family syscalls are inspected, and every descendant is followed
+//! through fork and exec until it exits, or is let go once the command has
+//! exited and its output has closed. Linux x86_64 only.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::sys::ptrace::{self, Options};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+
+use crate::event::{ChildProcess, ResourceUsage};
+use crate::process::resource_usage;
+
+/// Longest path read out of the tracee's memory.
+const MAX_PATH_BYTES: usize = 4096;
+
+/// How often descendants are checked on between the command's exit and its output closing.
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What [`trace_until_exit`] should collect.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TraceOptions {
+    pub(crate) files: bool,
+    pub(crate) tree: bool,
+}
+
+/// What a traced run left behind.
+#[derive(Debug, Default)]
+pub(crate) struct TraceReport {
+    /// Deduplicated paths opened successfully, if `files` was requested.
+    pub(crate) files: Vec<String>,
+    /// Descendants in the order they exited, then any left running, if `tree` was requested.
+    pub(crate) processes: Vec<ChildProcess>,
+    /// What the command used, from reaping it.
+    pub(crate) resource_usage: Option<ResourceUsage>,
+    /// When the command itself was reaped.
+    pub(crate) exited_at: Option<Instant>,
+}
+
+/// Makes the child stop for us at `exec`. If ptrace isn't permitted the child
+/// simply runs untraced and [`trace_until_exit`] reports nothing.
+pub(crate) fn prepare(cmd: &mut Command) {
+    unsafe {
+        cmd.pre_exec(|| {
+            // Failure is deliberately ignored so a locked-down sandbox still runs the command
+            libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+            Ok(())
+        });
+    }
+}
+
+/// Replaces `wait_with_timeout` for a traced child: resumes it and every
+/// process it forks, collecting what `options` asks for, until the child has
+/// exited and `output_closed` says its output has too. Descendants still
+/// running then, such as daemons it started, are detached and left to run.
+/// Must run on the thread that spawned the child, since that thread is its
+/// tracer. Returns the exit status, whether the timeout killed it, and the report.
+pub(crate) fn trace_until_exit(
+    child: &Child,
+    timeout: Option<Duration>,
+    options: TraceOptions,
+    output_closed: &dyn Fn() -> bool,
+) -> io::Result<(ExitStatus, bool, TraceReport)> {
+    let root = Pid::from_raw(child.id() as i32);
+    let timed_out = Arc::new(AtomicBool::new(false));
+    // Every live tracee, for the watchdog to kill; a process is added as soon
+    // as its parent's fork is reported, before it first stops
+    let traced = Arc::new(Mutex::new(HashSet::from([root])));
+    let _watchdog = timeout.map(|timeout| start_watchdog(root, Arc::clone(&traced), timeout, Arc::clone(&timed_out)));
+    let track = |pid: Pid| {
+        traced.lock().unwrap_or_else(PoisonError::into_inner).insert(pid);
+        // Forked after the watchdog went through the set
+        if timed_out.load(Ordering::SeqCst) {
+            let _ = kill(pid, Signal::SIGKILL);
+        }
+    };
+    let untrack = |pid: Pid| {
+        traced.lock().unwrap_or_else(PoisonError::into_inner).remove(&pid);
+    };
+
+    let mut files = BTreeSet::new();
+    let mut processes = Vec::new();
+    let mut root_status = None;
+    let mut resource_usage = None;
+    let mut exited_at = None;
+    let mut attached = false;
+    let mut known = HashSet::from([root]);
+    // Processes stopped inside a syscall, with the path being opened if any
+    let mut in_syscall: HashMap<Pid, Option<String>> = HashMap::new();
+    // Latest command name of each live descendant; threads aren't processes and are left out
+    let mut comms: HashMap<Pid, String> = HashMap::new();
+    // Once set, every tracee is let go at its next stop; those sent a SIGSTOP for it have yet to take it
+    let mut detaching = false;
+    let mut stopping = HashSet::new();
+
+    loop {
+        let remaining: Vec<Pid> = traced.lock().unwrap_or_else(PoisonError::into_inner).iter().copied().collect();
+        if root_status.is_some() && remaining.is_empty() {
+            break;
+        }
+        if root_status.is_some() && !detaching && output_closed() {
+            detaching = true;
+            for &pid in &remaining {
+                if stop_thread(pid) {
+                    stopping.insert(pid);
+                }
+            }
+        }
+        // Between the command's exit and its output closing nothing else may happen, so don't block
+        let flags = if root_status.is_some() && !detaching { libc::WNOHANG } else { 0 };
+        let (status, usage) = match wait_any(flags) {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                thread::sleep(OUTPUT_POLL_INTERVAL);
+                continue;
+            }
+            Err(Errno::ECHILD) => break,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // The tracee to go on, with the signal it's to take
+        let stopped = match status {
+            WaitStatus::Stopped(pid, Signal::SIGTRAP) if pid == root && !attached => {
+                // The stop at exec that PTRACE_TRACEME arranges: start tracing in earnest
+                attached = true;
+                let options = Options::PTRACE_O_TRACESYSGOOD
+                    | Options::PTRACE_O_TRACEFORK
+                    | Options::PTRACE_O_TRACEVFORK
+                    | Options::PTRACE_O_TRACECLONE
+                    | Options::PTRACE_O_TRACEEXEC
+                    | Options::PTRACE_O_EXITKILL;
+                if let Err(e) = ptrace::setoptions(pid, options) {
+                    eprintln!("⚠️ Failed to trace the command: {}", e);
+                }
+                Some((pid, None))
+            }
+            WaitStatus::PtraceSyscall(pid) => {
+                match in_syscall.remove(&pid) {
+                    Some(path) => {
+                        let succeeded = ptrace::getregs(pid).is_ok_and(|regs| (regs.rax as i64) >= 0);
+                        if let (Some(path), true) = (path, succeeded) {
+                            files.insert(path);
+                        }
+                    }
+                    None => {
+                        in_syscall.insert(pid, opened_path(pid));
+                    }
+                }
+                Some((pid, None))
+            }
+            WaitStatus::PtraceEvent(pid, _, event) => {
+                match event {
+                    libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK | libc::PTRACE_EVENT_CLONE => {
+                        if let Ok(new) = ptrace::getevent(pid) {
+                            track(Pid::from_raw(new as i32));
+                        }
+                    }
+                    libc::PTRACE_EVENT_EXEC => {
+                        // A thread that execs takes over its leader's pid
+                        if let Ok(former) = ptrace::getevent(pid).map(|former| Pid::from_raw(former as i32)) {
+                            if former != pid {
+                                known.remove(&former);
+                                untrack(former);
+                                stopping.remove(&former);
+                            }
+                        }
+                        if let (Some(comm), Some(name)) = (comms.get_mut(&pid), read_comm(pid)) {
+                            *comm = name;
+                        }
+                    }
+                    _ => {}
+                }
+                Some((pid, None))
+            }
+            WaitStatus::Stopped(pid, signal) => {
+                // A new process starts with a SIGSTOP we mustn't pass on; any
+                // other signal is the tracee's to receive
+                let is_new = known.insert(pid);
+                if is_new {
+                    track(pid);
+                }
+                if is_new && options.tree && is_process(pid) {
+                    comms.insert(pid, read_comm(pid).unwrap_or_default());
+                }
+                let deliver = if is_new && signal == Signal::SIGSTOP { None } else { Some(signal) };
+                Some((pid, deliver))
+            }
+            WaitStatus::Exited(pid, code) => {
+                known.remove(&pid);
+                untrack(pid);
+                in_syscall.remove(&pid);
+                stopping.remove(&pid);
+                if pid == root {
+                    root_status = Some(ExitStatus::from_raw(code << 8));
+                    resource_usage = Some(usage);
+                    exited_at = Some(Instant::now());
+                } else if let Some(comm) = comms.remove(&pid) {
+                    processes.push(ChildProcess { pid: pid.as_raw(), comm, exit_code: Some(code) });
+                }
+                None
+            }
+            WaitStatus::Signaled(pid, signal, core_dumped) => {
+                known.remove(&pid);
+                untrack(pid);
+                in_syscall.remove(&pid);
+                stopping.remove(&pid);
+                if pid == root {
+                    root_status = Some(ExitStatus::from_raw(signal as i32 | if core_dumped { 0x80 } else { 0 }));
+                    resource_usage = Some(usage);
+                    exited_at = Some(Instant::now());
+                } else if let Some(comm) = comms.remove(&pid) {
+                    let exit_code = Some(128 + signal as i32);
+                    processes.push(ChildProcess { pid: pid.as_raw(), comm, exit_code });
+                }
+                None
+            }
+            _ => None,
+        };
+        let Some((pid, signal)) = stopped else {
+            continue;
+        };
+
+        // Without file tracing there's no need to stop at every syscall
+        if !detaching {
+            let _ = if options.files { ptrace::syscall(pid, signal) } else { ptrace::cont(pid, signal) };
+            continue;
+        }
+        // While detaching, a tracee at any stop is let go, taking its signal along
+        let ours = signal == Some(Signal::SIGSTOP) && stopping.remove(&pid);
+        let _ = ptrace::detach(pid, if ours { None } else { signal });
+        // A SIGSTOP still pending would stop it untraced; a SIGCONT discards it
+        if stopping.remove(&pid) {
+            let _ = kill(pid, Signal::SIGCONT);
+        }
+        known.remove(&pid);
+        untrack(pid);
+        in_syscall.remove(&pid);
+        if let Some(comm) = comms.remove(&pid) {
+            processes.push(ChildProcess { pid: pid.as_raw(), comm, exit_code: None });
+        }
+    }
+
+    if !attached {
+        eprintln!("⚠️ ptrace is not permitted here; no opened files or child processes were recorded");
+    }
+    let status = root_status.ok_or_else(|| io::Error::other("traced command vanished without an exit status"))?;
+    let report = TraceReport { files: files.into_iter().collect(), processes, resource_usage, exited_at };
+    Ok((status, timed_out.load(Ordering::SeqCst), report))
+}
+
+/// `waitpid(-1, __WALL | __WNOTHREAD | flags)` through `wait4`, so an exit
+/// comes with the process's resource usage. `__WNOTHREAD` limits it to this
+/// thread's children and tracees, leaving children other threads spawned to
+/// them. `None` if `WNOHANG` is passed and nothing has happened.
+fn wait_any(flags: libc::c_int) -> Result<Option<(WaitStatus, ResourceUsage)>, Errno> {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = unsafe { libc::wait4(-1, &mut status, libc::__WALL | libc::__WNOTHREAD | flags, &mut usage) };
+    match pid {
+        -1 => Err(Errno::last()),
+        0 => Ok(None),
+        _ => Ok(Some((WaitStatus::from_raw(Pid::from_raw(pid), status)?, resource_usage(&usage)))),
+    }
+}
+
+/// The process `pid` is a thread of, from `/proc`.
+fn thread_group(pid: Pid) -> Option<i32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status.lines().find_map(|line| line.strip_prefix("Tgid:")).and_then(|tgid| tgid.trim().parse().ok())
+}
+
+/// Whether `pid` leads its thread group, i.e. is a process rather than a thread.
+fn is_process(pid: Pid) -> bool {
+    thread_group(pid).is_none_or(|tgid| tgid == pid.as_raw())
+}
+
+/// Sends SIGSTOP to the one thread `pid`, so a tracee running freely comes to
+/// a stop it can be detached at. False if it has already gone.
+fn stop_thread(pid: Pid) -> bool {
+    let Some(tgid) = thread_group(pid) else {
+        return false;
+    };
+    unsafe { libc::syscall(libc::SYS_tgkill, tgid, pid.as_raw(), libc::SIGSTOP) == 0 }
+}
+
+fn read_comm(pid: Pid) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid)).ok().map(|comm| comm.trim_end().to_string())
+}
+
+/// At a syscall-entry stop, the absolute path being opened if the syscall is
+/// `open`, `creat`, `openat` or `openat2`.
+fn opened_path(pid: Pid) -> Option<String> {
+    let regs = ptrace::getregs(pid).ok()?;
+    let (dirfd, address) = match regs.orig_rax as i64 {
+        libc::SYS_open | libc::SYS_creat => (libc::AT_FDCWD, regs.rdi),
+        libc::SYS_openat | libc::SYS_openat2 => (regs.rdi as i32, regs.rsi),
+        _ => return None,
+    };
+    let path = read_c_string(pid, address)?;
+    if path.starts_with('/') {
+        return Some(path);
+    }
+
+    // Relative paths are resolved against the directory they were opened in
+    let base = if dirfd == libc::AT_FDCWD {
+        format!("/proc/{}/cwd", pid)
+    } else {
+        format!("/proc/{}/fd/{}", pid, dirfd)
+    };
+    Some(match fs::read_link(base) {
+        Ok(dir) => dir.join(&path).to_string_lossy().into_owned(),
+        Err(_) => path,
+    })
+}
+
+fn read_c_string(pid: Pid, address: u64) -> Option<String> {
+    let mem = File::open(format!("/proc/{}/mem", pid)).ok()?;
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 256];
+    while bytes.len() < MAX_PATH_BYTES {
+        let n = mem.read_at(&mut chunk, address + bytes.len() as u64).ok()?;
+        if n == 0 {
+            break;
+        }
+        if let Some(end) = chunk[..n].iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+    }
+    None
+}
+
+/// Kills `root`'s process group and every `traced` process once `timeout`
+/// passes, unless the returned sender is dropped first. The set reaches
+/// descendants that left the group; the group, any that escaped tracing.
+fn start_watchdog(
+    root: Pid,
+    traced: Arc<Mutex<HashSet<Pid>>>,
+    timeout: Duration,
+    timed_out: Arc<AtomicBool>,
+) -> mpsc::Sender<()> {
+    let (cancel, cancelled) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+            timed_out.store(true, Ordering::SeqCst);
+            let _ = killpg(root, Signal::SIGKILL);
+            for &pid in traced.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+                let _ = kill(pid, Signal::SIGKILL);
+            }
+        }
+    });
+    cancel
+}
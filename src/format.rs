@@ -0,0 +1,395 @@
+use flate2::read::MultiGzDecoder;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::event::TelemetryEvent;
+
+/// Serialization used for each event written to the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One compact JSON object per line.
+    #[default]
+    Jsonl,
+    /// Indented JSON objects, one after another.
+    JsonPretty,
+    /// One row per event under a header row; nested values are JSON-encoded into their cell.
+    Csv,
+    /// MessagePack maps, each preceded by its length as a 4-byte big-endian integer.
+    /// `rust-telemetry-driver --decode <log>` prints such a log as JSON lines.
+    Msgpack,
+}
+
+impl OutputFormat {
+    /// Parses a `TELEMETRY_FORMAT` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Some(OutputFormat::Jsonl),
+            "json-pretty" | "pretty" => Some(OutputFormat::JsonPretty),
+            "csv" => Some(OutputFormat::Csv),
+            "msgpack" | "messagepack" => Some(OutputFormat::Msgpack),
+            _ => None,
+        }
+    }
+}
+
+/// Spelling of event field names in every sink (`TELEMETRY_JSON_CASE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonCase {
+    /// `event_type`, as the fields are declared.
+    #[default]
+    Snake,
+    /// `eventType`, for JavaScript consumers.
+    Camel,
+}
+
+impl JsonCase {
+    /// Parses a `TELEMETRY_JSON_CASE` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "snake" | "snake_case" => Some(JsonCase::Snake),
+            "camel" | "camelcase" => Some(JsonCase::Camel),
+            _ => None,
+        }
+    }
+}
+
+/// Fields whose keys are user data (variable names, tags, embedded JSON
+/// lines), so renaming stops at them.
+const DATA_FIELDS: &[&str] = &["env", "env_source", "tags", "set", "stdout_lines"];
+
+/// What gets serialized for an event: its fields, or what the event filter made of them.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Record<'a> {
+    Event(&'a TelemetryEvent),
+    Filtered(&'a Value),
+}
+
+fn record(event: &TelemetryEvent) -> Record<'_> {
+    match &event.filter_output {
+        Some(value) => Record::Filtered(value),
+        None => Record::Event(event),
+    }
+}
+
+/// `event` as a JSON value with its field names spelled in `case`.
+pub(crate) fn event_value(event: &TelemetryEvent, case: JsonCase) -> serde_json::Result<Value> {
+    let mut value = serde_json::to_value(record(event))?;
+    if case == JsonCase::Camel {
+        camel_case_keys(&mut value);
+    }
+    Ok(value)
+}
+
+/// `event` as compact JSON with its field names spelled in `case`.
+pub(crate) fn event_json(event: &TelemetryEvent, case: JsonCase) -> serde_json::Result<String> {
+    match case {
+        // Straight from the struct, keeping its field order
+        JsonCase::Snake => serde_json::to_string(&record(event)),
+        JsonCase::Camel => serde_json::to_string(&event_value(event, case)?),
+    }
+}
+
+/// `events` as a compact JSON array, with field names spelled in `case`.
+pub(crate) fn events_json(events: &[TelemetryEvent], case: JsonCase) -> serde_json::Result<String> {
+    match case {
+        JsonCase::Snake => serde_json::to_string(&events.iter().map(record).collect::<Vec<_>>()),
+        JsonCase::Camel => {
+            let values = events.iter().map(|event| event_value(event, case)).collect::<Result<Vec<_>, _>>()?;
+            serde_json::to_string(&values)
+        }
+    }
+}
+
+fn camel_case_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    if !DATA_FIELDS.contains(&key.as_str()) {
+                        camel_case_keys(&mut value);
+                    }
+                    (camel_case(&key), value)
+                })
+                .collect();
+        }
+        Value::Array(items) => items.iter_mut().for_each(camel_case_keys),
+        _ => {}
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+/// Renders `event` as a complete record: text formats include the trailing
+/// newline, msgpack its length prefix.
+pub(crate) fn render_event(event: &TelemetryEvent, format: OutputFormat, case: JsonCase) -> io::Result<Vec<u8>> {
+    match format {
+        OutputFormat::Jsonl => Ok((event_json(event, case)? + "\n").into_bytes()),
+        OutputFormat::JsonPretty if case == JsonCase::Snake => {
+            Ok((serde_json::to_string_pretty(&record(event))? + "\n").into_bytes())
+        }
+        OutputFormat::JsonPretty => Ok((serde_json::to_string_pretty(&event_value(event, case)?)? + "\n").into_bytes()),
+        OutputFormat::Csv => {
+            let cells: Vec<String> = csv_fields(event, case)?
+                .into_iter()
+                .map(|(_, value)| csv_escape(&csv_cell(&value)))
+                .collect();
+            Ok((cells.join(",") + "\n").into_bytes())
+        }
+        OutputFormat::Msgpack => {
+            let body = match case {
+                JsonCase::Snake => rmp_serde::to_vec_named(&record(event)),
+                JsonCase::Camel => rmp_serde::to_vec_named(&event_value(event, case)?),
+            }
+            .map_err(io::Error::other)?;
+            let len = u32::try_from(body.len()).map_err(io::Error::other)?;
+            let mut record = len.to_be_bytes().to_vec();
+            record.extend_from_slice(&body);
+            Ok(record)
+        }
+    }
+}
+
+/// Wraps a log file for reading, transparently decompressing it if gzipped.
+pub(crate) fn open_log(input: impl Read + 'static) -> io::Result<Box<dyn BufRead>> {
+    let mut input = BufReader::new(input);
+    // Gzipped logs hold one member per run, so read them as a multi-member stream
+    if input.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(input))))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+/// Converts a msgpack log, gzipped or not, into JSON lines on `out`.
+pub fn decode_msgpack_log(input: impl Read + 'static, mut out: impl Write) -> io::Result<()> {
+    let mut reader = open_log(input)?;
+
+    let mut len = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut body)?;
+        let event: Value = rmp_serde::from_slice(&body).map_err(io::Error::other)?;
+        writeln!(out, "{}", event)?;
+    }
+}
+
+/// The CSV header row matching [`render_event`]'s column order.
+pub(crate) fn csv_header(event: &TelemetryEvent, case: JsonCase) -> serde_json::Result<String> {
+    let names: Vec<String> = csv_fields(event, case)?
+        .into_iter()
+        .map(|(name, _)| csv_escape(&name))
+        .collect();
+    Ok(names.join(",") + "\n")
+}
+
+/// Top-level fields in a stable (sorted) order, so every row lines up with the header.
+/// The columns are always the event's own fields, even when the event filter's output
+/// stands in for them: its values fill them, and keys it adds are left out.
+fn csv_fields(event: &TelemetryEvent, case: JsonCase) -> serde_json::Result<Vec<(String, Value)>> {
+    let Value::Object(mut values) = event_value(event, case)? else {
+        return Ok(Vec::new());
+    };
+    if event.filter_output.is_none() {
+        return Ok(values.into_iter().collect());
+    }
+    let mut columns = serde_json::to_value(Record::Event(event))?;
+    if case == JsonCase::Camel {
+        camel_case_keys(&mut columns);
+    }
+    let Value::Object(columns) = columns else {
+        return Ok(Vec::new());
+    };
+    Ok(columns.into_iter().map(|(name, _)| {
+        let value = values.remove(&name).unwrap_or(Value::Null);
+        (name, value)
+    }).collect())
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Bool(_) | Value::Number(_) => value.to_string(),
+        // Vectors and maps don't flatten into fixed columns, so keep them as JSON
+        Value::Array(_) | Value::Object(_) => value.to_string(),
+    }
+}
+
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Splits CSV text into rows of unescaped cells, the way a spreadsheet reads it.
+#[cfg(test)]
+pub(crate) fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let (mut rows, mut row, mut cell) = (Vec::new(), Vec::new(), String::new());
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => cell.push(c),
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn sample_event() -> TelemetryEvent {
+        TelemetryEvent {
+            event_id: "6f1c0d2e-58a4-4b8e-9f43-5d7e2c51a0b9".to_string(),
+            short_id: "6f1c0d2e".to_string(),
+            event_type: "process_end".to_string(),
+            driver_version: crate::DRIVER_VERSION,
+            schema_version: crate::SCHEMA_VERSION,
+            timestamp: 1_700_000_000.25,
+            session_id: "session".to_string(),
+            command: vec!["sh".to_string(), "-c".to_string(), "echo \"a, b\"".to_string()],
+            cwd: "/tmp".to_string(),
+            env: HashMap::from([("QUOTED".to_string(), "say \"hi\", twice".to_string())]),
+            exit_code: Some(3),
+            stdout_lines: Some(vec![Value::from("first"), Value::from("line\nbreak")]),
+            ..TelemetryEvent::default()
+        }
+    }
+
+    #[test]
+    fn jsonl_is_the_default() {
+        assert_eq!(OutputFormat::default(), OutputFormat::Jsonl);
+        assert_eq!(OutputFormat::parse("ndjson"), Some(OutputFormat::Jsonl));
+    }
+
+    #[test]
+    fn jsonl_round_trips() {
+        let event = sample_event();
+        let record = render_event(&event, OutputFormat::Jsonl, JsonCase::Snake).unwrap();
+        let text = String::from_utf8(record).unwrap();
+        assert_eq!(text.matches('\n').count(), 1);
+        let parsed: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, event_value(&event, JsonCase::Snake).unwrap());
+    }
+
+    #[test]
+    fn pretty_json_round_trips() {
+        let event = sample_event();
+        let record = render_event(&event, OutputFormat::JsonPretty, JsonCase::Snake).unwrap();
+        let parsed: Value = serde_json::from_slice(&record).unwrap();
+        assert_eq!(parsed, event_value(&event, JsonCase::Snake).unwrap());
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let event = sample_event();
+        let header = csv_header(&event, JsonCase::Snake).unwrap();
+        let row = String::from_utf8(render_event(&event, OutputFormat::Csv, JsonCase::Snake).unwrap()).unwrap();
+        let rows = parse_csv(&(header + &row));
+        assert_eq!(rows.len(), 2);
+
+        let Value::Object(fields) = event_value(&event, JsonCase::Snake).unwrap() else { unreachable!() };
+        assert_eq!(rows[0], fields.keys().cloned().collect::<Vec<_>>());
+        for (cell, value) in rows[1].iter().zip(fields.values()) {
+            let decoded = match value {
+                Value::Null if cell.is_empty() => Value::Null,
+                Value::String(_) => Value::String(cell.clone()),
+                _ => serde_json::from_str(cell).unwrap(),
+            };
+            assert_eq!(&decoded, value);
+        }
+    }
+
+    #[test]
+    fn csv_keeps_the_event_columns_under_a_filter() {
+        let event = sample_event();
+        let Value::Object(mut filtered) = event_value(&event, JsonCase::Snake).unwrap() else { unreachable!() };
+        filtered.insert("added".to_string(), Value::from("by-filter"));
+        filtered.remove("cwd");
+        filtered.insert("exit_code".to_string(), Value::from(0));
+        let filtered = TelemetryEvent { filter_output: Some(Value::Object(filtered)), ..sample_event() };
+
+        let header = csv_header(&event, JsonCase::Snake).unwrap();
+        let row = String::from_utf8(render_event(&filtered, OutputFormat::Csv, JsonCase::Snake).unwrap()).unwrap();
+        assert_eq!(csv_header(&filtered, JsonCase::Snake).unwrap(), header);
+        let rows = parse_csv(&(header + &row));
+        assert_eq!(rows[0].len(), rows[1].len());
+        assert!(!rows[0].contains(&"added".to_string()));
+        let cell = |name: &str| &rows[1][rows[0].iter().position(|column| column == name).unwrap()];
+        assert_eq!(cell("cwd"), "");
+        assert_eq!(cell("exit_code"), "0");
+        assert_eq!(cell("event_type"), "process_end");
+    }
+
+    #[test]
+    fn msgpack_round_trips_through_decode() {
+        let first = sample_event();
+        let second = TelemetryEvent { event_type: "process_stats".to_string(), exit_code: None, ..sample_event() };
+        let mut log = render_event(&first, OutputFormat::Msgpack, JsonCase::Snake).unwrap();
+        let body_len = u32::from_be_bytes(log[..4].try_into().unwrap()) as usize;
+        assert_eq!(log.len(), 4 + body_len);
+        log.extend(render_event(&second, OutputFormat::Msgpack, JsonCase::Snake).unwrap());
+
+        let mut decoded = Vec::new();
+        decode_msgpack_log(io::Cursor::new(log), &mut decoded).unwrap();
+        let events: Vec<Value> = String::from_utf8(decoded)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events, [event_value(&first, JsonCase::Snake).unwrap(), event_value(&second, JsonCase::Snake).unwrap()]);
+    }
+
+    #[test]
+    fn camel_case_renames_fields_but_not_data_keys() {
+        let event = TelemetryEvent {
+            env: HashMap::from([("MY_VAR".to_string(), "1".to_string())]),
+            tags: HashMap::from([("build_id".to_string(), "7".to_string())]),
+            ..sample_event()
+        };
+        let snake = event_value(&event, JsonCase::Snake).unwrap();
+        assert_eq!(snake["event_type"], "process_end");
+        assert!(snake.get("eventType").is_none());
+
+        let camel = event_value(&event, JsonCase::Camel).unwrap();
+        assert_eq!(camel["eventType"], "process_end");
+        assert_eq!(camel["stdoutLines"], snake["stdout_lines"]);
+        assert!(camel.as_object().unwrap().keys().all(|key| !key.contains('_')), "{:?}", camel);
+        assert_eq!(camel["env"]["MY_VAR"], "1");
+        assert_eq!(camel["tags"]["build_id"], "7");
+        assert_eq!(serde_json::from_str::<Value>(&event_json(&event, JsonCase::Camel).unwrap()).unwrap(), camel);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::event::TelemetryEvent;
+use crate::process::current_rss_kb;
+use crate::sink::EventLog;
+use crate::{short_id, unix_timestamp, unix_timestamp_ns, Config};
+
+/// Timer thread emitting `process_heartbeat` events while the child runs.
+/// Each heartbeat is flushed on its own, so progress survives a crashed driver.
+/// Under `only_failures` they are held back instead and handed over at [`stop`](Heartbeat::stop).
+pub(crate) struct Heartbeat {
+    stop: Sender<()>,
+    handle: JoinHandle<Vec<TelemetryEvent>>,
+}
+
+/// Live state a heartbeat reports.
+pub(crate) struct HeartbeatSource {
+    pub(crate) child_pid: u32,
+    pub(crate) started: Instant,
+    pub(crate) stdout_bytes: Arc<AtomicUsize>,
+    pub(crate) stderr_bytes: Arc<AtomicUsize>,
+}
+
+impl Heartbeat {
+    /// Starts beating every `interval`, stamping each heartbeat from `template`.
+    pub(crate) fn start(interval: Duration, config: Config, template: TelemetryEvent, source: HeartbeatSource) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            // One log for every beat, so the sinks are opened once
+            let mut event_log = EventLog::new(&config);
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = SystemTime::now();
+                let event_id = config.next_event_id();
+                let event = TelemetryEvent {
+                    short_id: short_id(&event_id),
+                    event_id,
+                    event_type: "process_heartbeat".to_string(),
+                    timestamp: unix_timestamp(now),
+                    timestamp_ns: unix_timestamp_ns(now),
+                    env: HashMap::new(),
+                    rss_kb: current_rss_kb(source.child_pid),
+                    duration_ms: Some(source.started.elapsed().as_millis() as u64),
+                    stdout_size_bytes: Some(source.stdout_bytes.load(Ordering::Relaxed)),
+                    stderr_size_bytes: Some(source.stderr_bytes.load(Ordering::Relaxed)),
+                    ancestry: None,
+                    ..template.clone()
+                };
+                event_log.log(&event);
+                event_log.flush();
+            }
+            let held = event_log.take_held();
+            event_log.finish();
+            held
+        });
+        Heartbeat { stop, handle }
+    }
+
+    /// Stops the timer, waiting out a heartbeat that is being written, and
+    /// returns the heartbeats held back under `only_failures`.
+    pub(crate) fn stop(self) -> Vec<TelemetryEvent> {
+        let _ = self.stop.send(());
+        self.handle.join().unwrap_or_default()
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use crate::event::TelemetryEvent;
+use crate::format::{events_json, JsonCase};
+
+/// Give up on a collector that doesn't answer within this long so the driver exits promptly.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Splits a `TELEMETRY_HTTP_HEADER` value of the form `Name: value`.
+pub(crate) fn parse_header(value: &str) -> Option<(String, String)> {
+    let (name, value) = value.split_once(':')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), value.trim().to_string()))
+}
+
+/// POSTs `events` to `endpoint` as a single JSON array.
+pub(crate) fn post_events(
+    endpoint: &str,
+    header: Option<&(String, String)>,
+    events: &[TelemetryEvent],
+    case: JsonCase,
+) -> Result<(), String> {
+    let body = events_json(events, case).map_err(|e| e.to_string())?;
+    post_json(endpoint, header, &body)
+}
+
+/// POSTs a JSON `body`; non-2xx responses are reported as errors.
+pub(crate) fn post_json(url: &str, header: Option<&(String, String)>, body: &str) -> Result<(), String> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(HTTP_TIMEOUT))
+        .build()
+        .into();
+    let mut request = agent.post(url).header("Content-Type", "application/json");
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+
+    request.send(body).map(|_| ()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,121 @@
+use std::io;
+
+use crate::event::TelemetryEvent;
+use crate::format::JsonCase;
+
+/// How long the end-of-run flush waits for outstanding deliveries.
+#[cfg(feature = "kafka")]
+const FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[cfg(feature = "kafka")]
+static PRODUCER: std::sync::Mutex<Option<rdkafka::producer::BaseProducer<DeliveryTracker>>> = std::sync::Mutex::new(None);
+
+/// Records how each queued message fared, by the id [`send_event`] gave it.
+#[cfg(feature = "kafka")]
+#[derive(Default)]
+struct DeliveryTracker {
+    delivered: std::sync::Mutex<std::collections::HashSet<usize>>,
+    /// The error of each failed message.
+    failed: std::sync::Mutex<std::collections::HashMap<usize, String>>,
+}
+
+#[cfg(feature = "kafka")]
+impl rdkafka::ClientContext for DeliveryTracker {}
+
+#[cfg(feature = "kafka")]
+impl rdkafka::producer::ProducerContext for DeliveryTracker {
+    type DeliveryOpaque = usize;
+
+    fn delivery(&self, result: &rdkafka::message::DeliveryResult<'_>, id: usize) {
+        use std::sync::PoisonError;
+
+        match result {
+            Ok(_) => {
+                self.delivered.lock().unwrap_or_else(PoisonError::into_inner).insert(id);
+            }
+            Err((e, _)) => {
+                self.failed.lock().unwrap_or_else(PoisonError::into_inner).insert(id, e.to_string());
+            }
+        }
+    }
+}
+
+/// Queues `event` as a JSON message on `topic`, keyed by the session id so a
+/// run's events share a partition and stay in order. One producer is shared
+/// by the whole process; deliveries complete in [`flush`], which is passed the
+/// returned id.
+#[cfg(feature = "kafka")]
+pub(crate) fn send_event(brokers: &str, topic: &str, event: &TelemetryEvent, case: JsonCase) -> io::Result<usize> {
+    use rdkafka::config::ClientConfig;
+    use rdkafka::producer::BaseRecord;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::PoisonError;
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let payload = crate::format::event_json(event, case)?;
+
+    let mut producer = PRODUCER.lock().unwrap_or_else(PoisonError::into_inner);
+    let producer = match &mut *producer {
+        Some(producer) => producer,
+        None => producer.insert(
+            ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("message.timeout.ms", FLUSH_TIMEOUT.as_millis().to_string())
+                .create_with_context(DeliveryTracker::default())
+                .map_err(io::Error::other)?,
+        ),
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    producer
+        .send(BaseRecord::with_opaque_to(topic, id).key(&event.session_id).payload(&payload))
+        .map_err(|(e, _)| io::Error::other(e))?;
+    producer.poll(std::time::Duration::ZERO);
+    Ok(id)
+}
+
+/// Waits for the messages `sent` to be delivered. On failure, returns the
+/// error along with the ids of those that weren't.
+#[cfg(feature = "kafka")]
+pub(crate) fn flush(sent: &[usize]) -> Result<(), (io::Error, Vec<usize>)> {
+    use rdkafka::producer::Producer;
+    use std::sync::PoisonError;
+
+    let producer = PRODUCER.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(producer) = &*producer else {
+        return Ok(());
+    };
+    let flushed = producer.flush(FLUSH_TIMEOUT);
+
+    let tracker = producer.context();
+    let mut delivered = tracker.delivered.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut failed = tracker.failed.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut error = flushed.err().map(|e| e.to_string());
+    let mut undelivered = Vec::new();
+    for id in sent {
+        if delivered.remove(id) {
+            continue;
+        }
+        if let Some(e) = failed.remove(id) {
+            error.get_or_insert(e);
+        }
+        undelivered.push(*id);
+    }
+    match (error, undelivered.is_empty()) {
+        (None, true) => Ok(()),
+        (error, _) => {
+            let error = error.unwrap_or_else(|| "messages still queued after the flush".to_string());
+            Err((io::Error::other(error), undelivered))
+        }
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+pub(crate) fn send_event(_brokers: &str, _topic: &str, _event: &TelemetryEvent, _case: JsonCase) -> io::Result<usize> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "built without the kafka feature"))
+}
+
+#[cfg(not(feature = "kafka"))]
+pub(crate) fn flush(_sent: &[usize]) -> Result<(), (io::Error, Vec<usize>)> {
+    Ok(())
+}
@@ -0,0 +1,1746 @@
+//! Process telemetry capture: runs a command, passes its output through, and
+//! records start/end/stats events as JSON lines.
+//!
+//! The `rust-telemetry-driver` binary is a thin wrapper around [`run`]; embedders
+//! can call it directly with a [`Config`] instead of shelling out, or await
+//! `run_async` on a tokio runtime with the `tokio` feature.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+#[cfg(feature = "tokio")]
+mod async_run;
+mod capture;
+mod cli;
+mod env_filter;
+mod event;
+mod event_filter;
+mod event_trim;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod file_trace;
+mod format;
+mod heartbeat;
+mod http;
+mod kafka;
+mod net_trace;
+mod otlp;
+mod process;
+#[cfg(unix)]
+mod pty;
+mod report;
+mod retry;
+mod sampler;
+mod settings;
+mod signals;
+mod sink;
+mod socket;
+mod syslog;
+
+#[cfg(feature = "tokio")]
+pub use async_run::run_async;
+pub use cli::{parse_args, CliArgs};
+pub use env_filter::{DEFAULT_ENV_MAX_COUNT, DEFAULT_ENV_MAX_VALUE_BYTES, DEFAULT_REDACT_PATTERNS, REDACTED};
+pub use event::{
+    AncestorProcess, ChildEnvOverrides, ChildProcess, DriverError, EnvSource, EventMode, OutputLine, ProcessStats,
+    ResourceSample, ResourceUsage, TelemetryEvent,
+};
+pub use format::{decode_msgpack_log, JsonCase, OutputFormat};
+pub use report::{GroupStats, Report};
+pub use retry::{RetryPolicy, DEFAULT_SINK_BACKOFF, DEFAULT_SINK_RETRIES};
+pub use settings::CONFIG_FILE_ENV;
+pub use sink::{LogStream, DEFAULT_STREAM_PREFIX};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use capture::{capture_stream_lines, forward_stdin, CaptureOptions, StdinCapture, StreamCapture};
+use env_filter::{allow_env, cap_env, parse_pattern_list, redact_env};
+use heartbeat::{Heartbeat, HeartbeatSource};
+use http::parse_header;
+use otlp::{export_span, SpanContext, PARENT_SPAN_ID_ENV, TRACE_ID_ENV};
+use process::{
+    container_id, core_dumped, credentials, driver_resource_usage, exec_command, exit_code_and_signal, get_parent_pid,
+    git_context, hostname, process_ancestry, resolve_executable, wait_with_timeout, Foreground, RESOURCE_SCOPE,
+};
+use net_trace::{NetSampler, DEFAULT_NET_SAMPLE_INTERVAL};
+use sampler::ResourceSampler;
+use signals::SignalForwarder;
+use settings::Settings;
+use sink::{expand_log_path, report_driver_error, EventLog};
+use syslog::{parse_facility, DEFAULT_SYSLOG_FACILITY};
+
+/// Default per-stream cap on retained output, overridable via `TELEMETRY_MAX_CAPTURE_BYTES`.
+pub const DEFAULT_MAX_CAPTURE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Guards the ancestry walk against cycles and pathologically deep trees.
+const MAX_ANCESTRY_DEPTH: usize = 64;
+
+/// Session id of the current run, exported to the child.
+pub const SESSION_ID_ENV: &str = "TELEMETRY_SESSION_ID";
+/// Session id of the enclosing run, exported to the child so a nested driver records its parent.
+pub const PARENT_SESSION_ID_ENV: &str = "TELEMETRY_PARENT_SESSION_ID";
+
+/// Rotated log generations kept when `TELEMETRY_LOG_KEEP` is unset.
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+/// Percentage of the timeout a run may use before it is flagged slow, when
+/// `TELEMETRY_SLOW_THRESHOLD_PCT` is unset.
+pub const DEFAULT_SLOW_THRESHOLD_PCT: f64 = 80.0;
+
+/// How long `TELEMETRY_EVENT_FILTER` gets per event when `TELEMETRY_EVENT_FILTER_TIMEOUT_MS` is unset.
+pub const DEFAULT_EVENT_FILTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Driver exit code when the child is killed for exceeding its timeout, as with GNU timeout.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Crate version stamped on every event as `driver_version`.
+pub const DRIVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Stamped on every event as `schema_version`; bumped whenever a field is
+/// removed or changes meaning, so mixed-version logs can be told apart.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Recorded as `cwd` when the working directory can't be read (e.g. it was deleted).
+const UNKNOWN_CWD: &str = "<unknown>";
+
+/// The child's stdin, whether a pipe or a PTY master.
+type ChildInput = Box<dyn Write + Send>;
+/// One of the child's output streams, whether a pipe or a PTY master.
+type ChildOutput = Box<dyn Read + Send>;
+
+/// When the 🚀 start and ✅/📊 summary banners are printed (`TELEMETRY_BANNER`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BannerMode {
+    #[default]
+    Always,
+    /// Only when the run failed, was signaled or timed out; the start banner is printed late.
+    Auto,
+    Never,
+}
+
+impl BannerMode {
+    /// Parses a `TELEMETRY_BANNER` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(BannerMode::Always),
+            "auto" => Some(BannerMode::Auto),
+            "never" => Some(BannerMode::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for a single driver invocation.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Shared by every event of this run.
+    pub session_id: String,
+    /// Session of the driver invocation this one is nested in.
+    pub parent_session_id: Option<String>,
+    /// Path of the JSONL log events are appended to, with any `TELEMETRY_LOG` template tokens expanded.
+    pub telemetry_file: String,
+    /// Standard stream events are written to in place of `telemetry_file`, from
+    /// `TELEMETRY_SINK=stdout|stderr` or a `TELEMETRY_LOG` of `-`, `/dev/stdout` or `/dev/stderr`.
+    pub log_stream: Option<LogStream>,
+    /// Put before each event line on `log_stream` to tell it apart from the child's output
+    /// (`TELEMETRY_STREAM_PREFIX`; empty for none).
+    pub stream_prefix: String,
+    /// How each event is serialized into the log.
+    pub format: OutputFormat,
+    /// Spelling of event field names, `snake` (default) or `camel`, in every sink.
+    pub json_case: JsonCase,
+    /// Three events per run, or one consolidated event.
+    pub event_mode: EventMode,
+    /// Write the log through a gzip encoder (`TELEMETRY_COMPRESS=gzip`); each run appends one
+    /// gzip member. A log path ending in `.gz` is always compressed.
+    pub gzip: bool,
+    /// Rotate the log before an append would take it past this size.
+    pub max_log_bytes: Option<u64>,
+    /// Rotated generations (`name.1`, `name.2`, ...) to keep.
+    pub log_keep: usize,
+    /// Collector URL; when set, events are POSTed there and only written to
+    /// `telemetry_file` if the request fails.
+    pub http_endpoint: Option<String>,
+    /// Extra `(name, value)` header sent with each POST, e.g. for auth.
+    pub http_header: Option<(String, String)>,
+    /// Unix socket each event is sent to as a JSON line, falling back to `telemetry_file`.
+    pub socket: Option<String>,
+    /// `(brokers, topic)` each event is produced to as JSON, from `TELEMETRY_KAFKA_BROKERS` and
+    /// `TELEMETRY_KAFKA_TOPIC`; needs the `kafka` feature and falls back to `telemetry_file`.
+    pub kafka: Option<(String, String)>,
+    /// Retries for a failed HTTP, Kafka or socket send before falling back to `telemetry_file`.
+    pub sink_retry: RetryPolicy,
+    /// Also send every event to the local syslog.
+    pub syslog: bool,
+    /// Syslog facility code (`TELEMETRY_SYSLOG_FACILITY`, e.g. `local0`); `user` by default.
+    pub syslog_facility: u8,
+    /// Socket of the syslog daemon, if not the platform default (`/dev/log`).
+    pub syslog_socket: Option<String>,
+    /// OTLP/HTTP collector that receives one span per invocation.
+    pub otlp_endpoint: Option<String>,
+    /// Span id of the enclosing driver invocation, inherited via `TELEMETRY_PARENT_SPAN_ID`.
+    pub parent_span_id: Option<String>,
+    /// Trace joined by nested invocations, inherited via `TELEMETRY_TRACE_ID`.
+    pub trace_id: Option<String>,
+    /// Per-stream cap on retained output; bytes past it are still counted and passed through.
+    pub max_capture_bytes: usize,
+    /// Per-stream cap on retained lines (`TELEMETRY_MAX_LINES`); later lines are still counted and passed through.
+    pub max_lines: Option<usize>,
+    /// Keep only each stream's first lines (`TELEMETRY_HEAD_LINES`) and last lines
+    /// (`TELEMETRY_TAIL_LINES`), marking the gap with `…[omitted N lines]`, in place of `max_lines`.
+    pub head_lines: Option<usize>,
+    pub tail_lines: Option<usize>,
+    /// Longest line retained; longer lines are cut with a `…[truncated N bytes]` marker.
+    pub max_line_bytes: Option<usize>,
+    /// Capture stdout; when off it is inherited and the event's stdout fields are `None`.
+    pub capture_stdout: bool,
+    /// Capture stderr; when off it is inherited and the event's stderr fields are `None`.
+    pub capture_stderr: bool,
+    /// Embed stdout lines that are JSON objects or arrays as structured values.
+    pub parse_json_lines: bool,
+    /// Record forwarded stdin, up to `max_capture_bytes`, in `stdin_provided`. A terminal on
+    /// stdin then stays with the driver, so a child that uses the terminal is stopped for it.
+    pub capture_stdin: bool,
+    /// Forward SIGINT, SIGTERM, SIGTSTP and SIGCONT to the child, hand it the terminal's foreground
+    /// and follow it into job control stops. The handlers are process-wide and keep the one child
+    /// they forward to, so this is off unless set; a library caller opts in only when it runs one
+    /// command at a time and the driver is in effect the process. The binary always sets it.
+    pub forward_signals: bool,
+    /// Record the arrival time of every captured line.
+    pub timestamp_lines: bool,
+    /// Also record both streams as one arrival-ordered `output` array.
+    pub interleave_output: bool,
+    /// Record both streams as one arrival-ordered `combined_output` array instead of per-stream arrays.
+    pub combine_output: bool,
+    /// Capture and log output without replaying it to the driver's own stdout/stderr.
+    pub quiet: bool,
+    /// Replay the child's output byte for byte as it is read (`TELEMETRY_EXACT_PASSTHROUGH`);
+    /// `line_prefix` is then ignored.
+    pub exact_passthrough: bool,
+    /// Keep each stream's bytes as read, up to `max_capture_bytes`, and log them base64-encoded in
+    /// `stdout_raw`/`stderr_raw` instead of split lines (`TELEMETRY_CAPTURE_RAW`). Line options such
+    /// as `max_lines`, `strip_ansi`, `fail_on_pattern` and interleaving don't apply.
+    pub capture_raw: bool,
+    /// Put before each line replayed to the driver's stdout/stderr (`TELEMETRY_LINE_PREFIX`), with
+    /// `{session}`, `{cmd}` (the program's basename) and `{stream}` expanded. Stored lines are unprefixed.
+    pub line_prefix: Option<String>,
+    /// Treat the run as failed if a line of output matches (`TELEMETRY_FAIL_ON_PATTERN`).
+    pub fail_on_pattern: Option<regex::bytes::Regex>,
+    /// Driver exit code when `fail_on_pattern` matched but the child exited 0
+    /// (`TELEMETRY_FAIL_PATTERN_EXIT_CODE`, default 1).
+    pub fail_pattern_exit_code: i32,
+    /// When to print the start and summary banners.
+    pub banner: BannerMode,
+    /// Print the end-of-run summary as one JSON object on stderr (`TELEMETRY_SUMMARY_JSON`).
+    pub summary_json: bool,
+    /// Run the child on a pseudo-terminal (Unix only); its combined output is captured as stdout.
+    /// `TELEMETRY_PTY=auto` turns this on when the driver's stdout is a terminal.
+    pub pty: bool,
+    /// Run the child under ptrace and record the files it opens (Linux x86_64 only).
+    pub trace_files: bool,
+    /// Follow the child's descendants under ptrace and record them in `child_processes` (Linux x86_64 only).
+    pub capture_tree: bool,
+    /// Record the remote addresses the child connects to (Linux only, sampled).
+    pub trace_net: bool,
+    /// Emit a `process_heartbeat` event this often while the child runs.
+    pub heartbeat: Option<Duration>,
+    /// Record the child's RSS, CPU and open descriptors this often into `resource_samples` (Linux only).
+    pub sample_interval: Option<Duration>,
+    /// Kill the child once it has run this long.
+    pub timeout: Option<Duration>,
+    /// Share of `timeout`, in percent, past which a run that finished in time is flagged `slow_warning`.
+    pub slow_threshold_pct: f64,
+    /// Record the environment in the start event at all (`TELEMETRY_ENV_CAPTURE=none` disables it).
+    pub capture_env: bool,
+    /// When set, only variables matching these glob patterns are recorded.
+    pub env_allow: Option<Vec<String>>,
+    /// Glob patterns of environment variables whose values are logged as [`REDACTED`].
+    pub env_redact: Vec<String>,
+    /// Longest environment value recorded before it is cut with a marker; `None` for no limit.
+    pub env_max_value_bytes: Option<usize>,
+    /// Most environment variables recorded (the first by name); `None` for no limit.
+    pub env_max_count: Option<usize>,
+    /// Record the parent process chain in the start event (Linux only).
+    pub capture_ancestry: bool,
+    /// Record the branch, commit and dirty state of the git checkout in the start event.
+    pub capture_git: bool,
+    /// Fraction of sessions, in `[0, 1]`, that are captured; see [`Config::is_sampled`].
+    pub sample_rate: f64,
+    /// Run a bash, zsh or ksh command with `-o pipefail`, so a failing pipeline stage
+    /// shows in the recorded exit (`TELEMETRY_PIPEFAIL`). Other commands run unchanged.
+    pub pipefail: bool,
+    /// Re-runs of a command that exits non-zero (`TELEMETRY_RETRIES`).
+    pub retries: u32,
+    /// Pause before each re-run (`TELEMETRY_RETRY_DELAY_MS`).
+    pub retry_delay: Duration,
+    /// Also re-run a command killed by a signal, timeouts included (`TELEMETRY_RETRY_ON_SIGNAL`).
+    pub retry_on_signal: bool,
+    /// Basenames from `TELEMETRY_ALLOWED_COMMANDS`; any other command is run with no telemetry.
+    pub allowed_commands: Option<Vec<String>>,
+    /// Basenames from `TELEMETRY_DENIED_COMMANDS` that are never captured, even if allowed.
+    pub denied_commands: Vec<String>,
+    /// Store captured lines without ANSI escape sequences (`TELEMETRY_STRIP_ANSI`).
+    pub strip_ansi: bool,
+    /// Record a SHA-256 of each raw output stream in the end event (`TELEMETRY_HASH_OUTPUT`).
+    pub hash_output: bool,
+    /// Shell command each event's JSON is piped through before logging (`TELEMETRY_EVENT_FILTER`).
+    pub event_filter: Option<String>,
+    /// How long the filter gets per event before it is killed and the event logged
+    /// unfiltered (`TELEMETRY_EVENT_FILTER_TIMEOUT_MS`).
+    pub event_filter_timeout: Duration,
+    /// Largest record written for an event; bigger ones lose output, then env (`TELEMETRY_MAX_EVENT_BYTES`).
+    pub max_event_bytes: Option<usize>,
+    /// Hold events until the end and keep them only if the child failed, was signaled or timed out.
+    pub only_failures: bool,
+    /// User metadata attached to every event, from `TELEMETRY_TAGS`; never redacted.
+    pub tags: HashMap<String, String>,
+    /// Variables set in the child's environment, from `TELEMETRY_CHILD_ENV_SET` (`k=v,...`).
+    /// Captured runs only; a sampled-out passthrough leaves the environment alone.
+    pub child_env_set: HashMap<String, String>,
+    /// Variables removed from the child's environment, from `TELEMETRY_CHILD_ENV_UNSET` (`k,...`).
+    pub child_env_unset: Vec<String>,
+    /// Derive event ids from the session id and a counter instead of at random, for reproducible logs.
+    pub deterministic_ids: bool,
+    /// Next sequence number for deterministic ids, shared with clones such as the heartbeat's.
+    event_seq: Arc<AtomicU64>,
+}
+
+impl Default for Config {
+    /// Built-in defaults, ignoring the environment.
+    fn default() -> Self {
+        Config::from_settings(&Settings::none())
+    }
+}
+
+impl Config {
+    /// Builds a config from the `TELEMETRY_*` environment variables, layered
+    /// over the TOML file named by `TELEMETRY_CONFIG` if there is one.
+    pub fn from_env() -> Self {
+        let Ok(path) = env::var(CONFIG_FILE_ENV) else {
+            return Config::from_settings(&Settings::env());
+        };
+        match Settings::from_file(&path, true) {
+            Ok(settings) => {
+                let config = Config::from_settings(&settings);
+                settings.warn_unknown(&path);
+                config
+            }
+            Err(e) => {
+                eprintln!("⚠️ Failed to read {} {}: {}; ignoring it", CONFIG_FILE_ENV, path, e);
+                Config::from_settings(&Settings::env())
+            }
+        }
+    }
+
+    /// Builds a config from a TOML config file alone, ignoring the environment.
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        let settings = Settings::from_file(path, false)?;
+        let config = Config::from_settings(&settings);
+        settings.warn_unknown(path);
+        Ok(config)
+    }
+
+    fn from_settings(settings: &Settings) -> Self {
+        // A parent driver exports both variables with the same value; that
+        // session is ours to nest under, not to reuse.
+        let parent_session_id = settings.var(PARENT_SESSION_ID_ENV).ok();
+        let session_id = settings.var(SESSION_ID_ENV)
+            .ok()
+            .filter(|id| parent_session_id.as_ref() != Some(id))
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let telemetry_file = settings.var("TELEMETRY_LOG")
+            .map(|template| expand_log_path(&template, &session_id))
+            .unwrap_or_else(|_| format!("/tmp/rust_telemetry_{}.jsonl",
+                SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())));
+
+        let log_stream = match settings.var("TELEMETRY_SINK") {
+            Ok(value) if value.eq_ignore_ascii_case("file") => None,
+            Ok(value) => LogStream::parse(&value).or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_SINK {:?}, using file", value);
+                None
+            }),
+            Err(_) => LogStream::for_path(&telemetry_file),
+        };
+        let stream_prefix = settings.var("TELEMETRY_STREAM_PREFIX")
+            .unwrap_or_else(|_| DEFAULT_STREAM_PREFIX.to_string());
+
+        let format = match settings.var("TELEMETRY_FORMAT") {
+            Ok(value) => OutputFormat::parse(&value).unwrap_or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_FORMAT {:?}, using jsonl", value);
+                OutputFormat::Jsonl
+            }),
+            Err(_) => OutputFormat::Jsonl,
+        };
+
+        let json_case = match settings.var("TELEMETRY_JSON_CASE") {
+            Ok(value) => JsonCase::parse(&value).unwrap_or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_JSON_CASE {:?}, using snake", value);
+                JsonCase::Snake
+            }),
+            Err(_) => JsonCase::Snake,
+        };
+
+        let event_mode = match settings.var("TELEMETRY_EVENT_MODE") {
+            Ok(value) => EventMode::parse(&value).unwrap_or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_EVENT_MODE {:?}, using multi", value);
+                EventMode::Multi
+            }),
+            Err(_) => EventMode::Multi,
+        };
+
+        let gzip = settings.var("TELEMETRY_COMPRESS").is_ok_and(|v| v.eq_ignore_ascii_case("gzip"));
+
+        let max_log_bytes = settings.var("TELEMETRY_MAX_LOG_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let log_keep = settings.var("TELEMETRY_LOG_KEEP")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LOG_KEEP);
+
+        let http_endpoint = settings.var("TELEMETRY_HTTP_ENDPOINT").ok();
+        let http_header = settings.var("TELEMETRY_HTTP_HEADER")
+            .ok()
+            .and_then(|v| parse_header(&v));
+
+        let socket = settings.var("TELEMETRY_SOCKET").ok().filter(|path| !path.is_empty());
+
+        let kafka = match (settings.var("TELEMETRY_KAFKA_BROKERS"), settings.var("TELEMETRY_KAFKA_TOPIC")) {
+            (Ok(brokers), Ok(topic)) => Some((brokers, topic)),
+            (Ok(_), Err(_)) => {
+                eprintln!("⚠️ TELEMETRY_KAFKA_BROKERS is set without TELEMETRY_KAFKA_TOPIC; not using Kafka");
+                None
+            }
+            _ => None,
+        };
+
+        let sink_retry = RetryPolicy {
+            retries: settings.var("TELEMETRY_SINK_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_SINK_RETRIES),
+            backoff: settings.var("TELEMETRY_SINK_BACKOFF_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map_or(DEFAULT_SINK_BACKOFF, Duration::from_millis),
+        };
+
+        let syslog = settings.flag("TELEMETRY_SYSLOG");
+        let syslog_facility = match settings.var("TELEMETRY_SYSLOG_FACILITY") {
+            Ok(value) => parse_facility(&value).unwrap_or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_SYSLOG_FACILITY {:?}, using user", value);
+                DEFAULT_SYSLOG_FACILITY
+            }),
+            Err(_) => DEFAULT_SYSLOG_FACILITY,
+        };
+        let syslog_socket = settings.var("TELEMETRY_SYSLOG_SOCKET").ok();
+
+        let otlp_endpoint = settings.var("TELEMETRY_OTLP_ENDPOINT").ok();
+        let parent_span_id = settings.var(PARENT_SPAN_ID_ENV).ok();
+        let trace_id = settings.var(TRACE_ID_ENV).ok();
+
+        let max_capture_bytes = settings.var("TELEMETRY_MAX_CAPTURE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CAPTURE_BYTES);
+
+        let max_lines = settings.var("TELEMETRY_MAX_LINES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        let head_lines = settings.var("TELEMETRY_HEAD_LINES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let tail_lines = settings.var("TELEMETRY_TAIL_LINES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        let max_line_bytes = settings.var("TELEMETRY_MAX_LINE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        let capture_stdout = !settings.flag_off("TELEMETRY_CAPTURE_STDOUT");
+        let capture_stderr = !settings.flag_off("TELEMETRY_CAPTURE_STDERR");
+        let parse_json_lines = settings.flag("TELEMETRY_PARSE_JSON_LINES");
+        let capture_stdin = settings.flag("TELEMETRY_CAPTURE_STDIN");
+        let timestamp_lines = settings.flag("TELEMETRY_TIMESTAMP_LINES");
+        let interleave_output = settings.flag("TELEMETRY_INTERLEAVE_OUTPUT");
+        let combine_output = settings.flag("TELEMETRY_COMBINE_OUTPUT");
+        let quiet = settings.flag("TELEMETRY_QUIET");
+        let exact_passthrough = settings.flag("TELEMETRY_EXACT_PASSTHROUGH");
+        let capture_raw = settings.flag("TELEMETRY_CAPTURE_RAW");
+        let line_prefix = settings.var("TELEMETRY_LINE_PREFIX").ok().filter(|prefix| !prefix.is_empty());
+        let fail_on_pattern = settings.var("TELEMETRY_FAIL_ON_PATTERN").ok().and_then(|pattern| {
+            regex::bytes::Regex::new(&pattern)
+                .inspect_err(|e| eprintln!("⚠️ Invalid TELEMETRY_FAIL_ON_PATTERN {:?}: {}", pattern, e))
+                .ok()
+        });
+        let fail_pattern_exit_code = settings.var("TELEMETRY_FAIL_PATTERN_EXIT_CODE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+        let banner = match settings.var("TELEMETRY_BANNER") {
+            Ok(value) => BannerMode::parse(&value).unwrap_or_else(|| {
+                eprintln!("⚠️ Unknown TELEMETRY_BANNER {:?}, using always", value);
+                BannerMode::Always
+            }),
+            Err(_) => BannerMode::Always,
+        };
+        let summary_json = settings.flag("TELEMETRY_SUMMARY_JSON");
+
+        // `auto` gives the child a terminal only when the driver has one to replay it to
+        let pty = settings.flag("TELEMETRY_PTY")
+            || settings.var("TELEMETRY_PTY").is_ok_and(|v| v.eq_ignore_ascii_case("auto")) && io::stdout().is_terminal();
+
+        let trace_files = settings.flag("TELEMETRY_TRACE_FILES");
+        let capture_tree = settings.flag("TELEMETRY_CAPTURE_TREE");
+        let trace_net = settings.flag("TELEMETRY_TRACE_NET");
+
+        let heartbeat = settings.var("TELEMETRY_HEARTBEAT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+
+        let sample_interval = settings.var("TELEMETRY_SAMPLE_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .map(Duration::from_millis);
+
+        let timeout = settings.var("TELEMETRY_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis);
+        let slow_threshold_pct = settings.var("TELEMETRY_SLOW_THRESHOLD_PCT")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|pct| pct.is_finite() && *pct >= 0.0)
+            .unwrap_or(DEFAULT_SLOW_THRESHOLD_PCT);
+
+        let capture_env = !settings.var("TELEMETRY_ENV_CAPTURE")
+            .is_ok_and(|v| v.eq_ignore_ascii_case("none"));
+
+        let env_allow = settings.var("TELEMETRY_ENV_ALLOW")
+            .ok()
+            .map(|v| parse_pattern_list(&v));
+
+        // User patterns extend the built-in list rather than replacing it
+        let mut env_redact: Vec<String> = DEFAULT_REDACT_PATTERNS.iter().map(|p| p.to_string()).collect();
+        if let Ok(value) = settings.var("TELEMETRY_ENV_REDACT") {
+            env_redact.extend(parse_pattern_list(&value));
+        }
+
+        // 0 lifts a cap entirely
+        let env_max_value_bytes = settings.var("TELEMETRY_ENV_MAX_VALUE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Some(DEFAULT_ENV_MAX_VALUE_BYTES), |max| (max > 0).then_some(max));
+        let env_max_count = settings.var("TELEMETRY_ENV_MAX_COUNT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Some(DEFAULT_ENV_MAX_COUNT), |max| (max > 0).then_some(max));
+
+        let capture_ancestry = settings.flag("TELEMETRY_CAPTURE_ANCESTRY");
+        let capture_git = settings.flag("TELEMETRY_CAPTURE_GIT");
+
+        let sample_rate = settings.var("TELEMETRY_SAMPLE_RATE")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|rate| !rate.is_nan())
+            .map_or(1.0, |rate| rate.clamp(0.0, 1.0));
+
+        let pipefail = settings.flag("TELEMETRY_PIPEFAIL");
+        let retries = settings.var("TELEMETRY_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let retry_delay = settings.var("TELEMETRY_RETRY_DELAY_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(Duration::ZERO, Duration::from_millis);
+        let retry_on_signal = settings.flag("TELEMETRY_RETRY_ON_SIGNAL");
+
+        let allowed_commands = settings.var("TELEMETRY_ALLOWED_COMMANDS")
+            .ok()
+            .map(|value| parse_pattern_list(&value));
+        let denied_commands = settings.var("TELEMETRY_DENIED_COMMANDS")
+            .map(|value| parse_pattern_list(&value))
+            .unwrap_or_default();
+
+        let strip_ansi = settings.flag("TELEMETRY_STRIP_ANSI");
+        let hash_output = settings.flag("TELEMETRY_HASH_OUTPUT");
+
+        let event_filter = settings.var("TELEMETRY_EVENT_FILTER").ok().filter(|filter| !filter.is_empty());
+        let event_filter_timeout = settings.var("TELEMETRY_EVENT_FILTER_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(DEFAULT_EVENT_FILTER_TIMEOUT, Duration::from_millis);
+        let max_event_bytes = settings.var("TELEMETRY_MAX_EVENT_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|max| *max > 0);
+
+        let only_failures = settings.flag("TELEMETRY_ONLY_FAILURES");
+
+        let tags = settings.var("TELEMETRY_TAGS")
+            .map(|value| parse_tags(&value))
+            .unwrap_or_default();
+
+        let child_env_set = settings.var("TELEMETRY_CHILD_ENV_SET")
+            .map(|value| parse_tags(&value))
+            .unwrap_or_default();
+        let child_env_unset = settings.var("TELEMETRY_CHILD_ENV_UNSET")
+            .map(|value| parse_pattern_list(&value))
+            .unwrap_or_default();
+
+        let deterministic_ids = settings.flag("TELEMETRY_DETERMINISTIC_IDS");
+
+        Config {
+            session_id,
+            parent_session_id,
+            telemetry_file,
+            log_stream,
+            stream_prefix,
+            format,
+            json_case,
+            event_mode,
+            gzip,
+            max_log_bytes,
+            log_keep,
+            http_endpoint,
+            http_header,
+            socket,
+            kafka,
+            sink_retry,
+            syslog,
+            syslog_facility,
+            syslog_socket,
+            otlp_endpoint,
+            parent_span_id,
+            trace_id,
+            max_capture_bytes,
+            max_lines,
+            head_lines,
+            tail_lines,
+            max_line_bytes,
+            capture_stdout,
+            capture_stderr,
+            parse_json_lines,
+            capture_stdin,
+            timestamp_lines,
+            interleave_output,
+            combine_output,
+            quiet,
+            exact_passthrough,
+            capture_raw,
+            line_prefix,
+            fail_on_pattern,
+            fail_pattern_exit_code,
+            banner,
+            summary_json,
+            pty,
+            trace_files,
+            capture_tree,
+            trace_net,
+            heartbeat,
+            sample_interval,
+            timeout,
+            slow_threshold_pct,
+            capture_env,
+            env_allow,
+            env_redact,
+            env_max_value_bytes,
+            env_max_count,
+            capture_ancestry,
+            capture_git,
+            sample_rate,
+            pipefail,
+            retries,
+            retry_delay,
+            retry_on_signal,
+            allowed_commands,
+            denied_commands,
+            strip_ansi,
+            hash_output,
+            event_filter,
+            event_filter_timeout,
+            max_event_bytes,
+            only_failures,
+            forward_signals: false,
+            tags,
+            child_env_set,
+            child_env_unset,
+            deterministic_ids,
+            event_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// A fresh event id: random by default, or a UUIDv5 over `session_id:seq`
+    /// under `deterministic_ids`, so the same session always yields the same ids.
+    pub(crate) fn next_event_id(&self) -> String {
+        if !self.deterministic_ids {
+            return Uuid::new_v4().to_string();
+        }
+        let seq = self.event_seq.fetch_add(1, Ordering::Relaxed);
+        Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("{}:{}", self.session_id, seq).as_bytes()).to_string()
+    }
+
+    /// Whether the log file is written gzipped, asked for or implied by its name.
+    pub(crate) fn gzips_log(&self) -> bool {
+        self.gzip || self.telemetry_file.ends_with(".gz")
+    }
+
+    /// Where local events go: `stdout`, `stderr` or the log file's path.
+    pub fn log_destination(&self) -> &str {
+        match self.log_stream {
+            Some(stream) => stream.name(),
+            None => &self.telemetry_file,
+        }
+    }
+
+    /// Whether `command` may be captured at all: not in `denied_commands` and,
+    /// if there is an allowlist, in `allowed_commands`. The program's basename
+    /// is compared, with or without an extension like `.exe`.
+    pub fn captures_command(&self, command: &[String]) -> bool {
+        let Some(program) = command.first().map(Path::new) else {
+            return true;
+        };
+        let names = [program.file_name(), program.file_stem()];
+        let listed = |list: &[String]| list.iter().any(|name| names.contains(&Some(OsStr::new(name))));
+        !listed(&self.denied_commands) && self.allowed_commands.as_deref().is_none_or(listed)
+    }
+
+    /// Whether this session falls inside `sample_rate`. The decision is a pure
+    /// function of the session id, so every event of a session agrees and a
+    /// supplied `TELEMETRY_SESSION_ID` always gets the same answer.
+    pub fn is_sampled(&self) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        // Top 53 bits give a uniform value in [0, 1)
+        let position = (fnv1a_64(self.session_id.as_bytes()) >> 11) as f64 / (1u64 << 53) as f64;
+        position < self.sample_rate
+    }
+}
+
+/// 64-bit FNV-1a, used because it's stable across Rust versions unlike `DefaultHasher`.
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Runs commands under telemetry capture with a fixed [`Config`].
+#[derive(Debug, Clone)]
+pub struct TelemetryDriver {
+    config: Config,
+}
+
+impl TelemetryDriver {
+    pub fn new(config: Config) -> Self {
+        TelemetryDriver { config }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Runs `command` (program followed by its arguments), streaming its output
+    /// through and logging `process_start`, `process_end` and `process_stats` events.
+    ///
+    /// A run that exits non-zero is repeated up to `retries` times, each attempt
+    /// logging its own events under the same session; the last attempt's stats
+    /// are returned. Only the first attempt can count on stdin.
+    ///
+    /// Signals reach the child only with `forward_signals`, which installs process-wide
+    /// handlers for the run; without it the caller's own handling is left as it was.
+    ///
+    /// Spawn failures are logged as a `process_spawn_error` event and returned as `Err`.
+    pub fn run(&self, command: &[String]) -> io::Result<ProcessStats> {
+        let mut attempt = 1;
+        loop {
+            let stats = self.run_attempt(command, attempt)?;
+            if !self.retries_after(&stats, attempt) {
+                return Ok(stats);
+            }
+            thread::sleep(self.config.retry_delay);
+            attempt += 1;
+        }
+    }
+
+    /// Whether a run failing as `stats` describes gets another attempt, announcing it if so.
+    fn retries_after(&self, stats: &ProcessStats, attempt: u32) -> bool {
+        let config = &self.config;
+        let retryable = stats.exit_code != 0 && (!stats.terminated_by_signal || config.retry_on_signal);
+        if !retryable || attempt > config.retries {
+            return false;
+        }
+        eprintln!("⚠️ [{}] Attempt {} exited with {}; retrying in {}ms",
+            config.session_id,
+            attempt,
+            stats.exit_code,
+            config.retry_delay.as_millis());
+        true
+    }
+
+    fn run_attempt(&self, command: &[String], attempt: u32) -> io::Result<ProcessStats> {
+        let config = &self.config;
+        let session_id = &config.session_id;
+        let (mut state, mut cmd) = self.begin_attempt(command, attempt)?;
+        let pty_master = if config.pty { attach_pty(&mut cmd) } else { None };
+        #[cfg(unix)]
+        if pty_master.is_none() {
+            process::own_process_group(&mut cmd);
+        }
+        // A terminal on stdin goes to the child, unless the driver reads it for the log
+        let mut foreground = if config.forward_signals && pty_master.is_none() && !config.capture_stdin {
+            Foreground::claim(&mut cmd)
+        } else {
+            None
+        };
+        // A PTY carries both streams, so there is nothing left to inherit
+        state.capture_stdout |= pty_master.is_some();
+        state.capture_stderr |= pty_master.is_some();
+        let tracing = (config.trace_files || config.capture_tree) && prepare_trace(&mut cmd);
+
+        let spawned_at = Instant::now();
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(self.spawn_failed(command, state, e)),
+        };
+        // Releases the parent's copies of any PTY slave
+        drop(cmd);
+        if let Some(foreground) = &mut foreground {
+            foreground.spawned(child.id());
+        }
+        let signals = config.forward_signals.then(|| SignalForwarder::install(child.id()));
+
+        // Get handles for stdin, stdout and stderr. Under a PTY the master
+        // carries both output streams, and stdin ends with a Ctrl-D.
+        // A stream that isn't captured or forwarded is inherited and has no handle.
+        let (child_stdin, stdout, stderr, eof_marker): (Option<ChildInput>, Option<ChildOutput>, Option<ChildOutput>, _) =
+            match pty_master {
+                Some(master) => {
+                    let input = master.try_clone()?;
+                    (Some(Box::new(input)), Some(Box::new(master)), None, pty_eof_marker())
+                }
+                None => (
+                    child.stdin.take().map(|stdin| Box::new(stdin) as ChildInput),
+                    child.stdout.take().map(|stdout| Box::new(stdout) as ChildOutput),
+                    child.stderr.take().map(|stderr| Box::new(stderr) as ChildOutput),
+                    None,
+                ),
+            };
+
+        // Forward stdin. This thread is never joined: it may stay blocked on an
+        // interactive stdin after the child exits, so we only read what it captured.
+        let stdin_captured = Arc::new(Mutex::new(StdinCapture::default()));
+        let stdin_tee = Arc::clone(&stdin_captured);
+        let stdin_limit = if config.capture_stdin { config.max_capture_bytes } else { 0 };
+        if let Some(child_stdin) = child_stdin {
+            thread::spawn(move || {
+                forward_stdin(child_stdin, stdin_tee, stdin_limit, eof_marker);
+            });
+        }
+
+        // Spawn threads to capture stdout and stderr
+        let interleaved = (config.interleave_output || config.combine_output).then(|| Arc::new(Mutex::new(Vec::new())));
+        let stdout_options = self.capture_options(command, spawned_at, interleaved.clone());
+        let stderr_options = CaptureOptions {
+            bytes_read: Arc::new(AtomicUsize::new(0)),
+            ..stdout_options.clone()
+        };
+
+        let sampler = config.sample_interval.map(|interval| ResourceSampler::start(interval, child.id(), spawned_at));
+        let net_sampler = config.trace_net.then(|| {
+            NetSampler::start(config.sample_interval.unwrap_or(DEFAULT_NET_SAMPLE_INTERVAL), child.id())
+        });
+        let heartbeat = config.heartbeat.map(|interval| {
+            let template = TelemetryEvent { env: HashMap::new(), env_source: None, ..state.pre_event.clone() };
+            Heartbeat::start(interval, config.clone(), template, HeartbeatSource {
+                child_pid: child.id(),
+                started: spawned_at,
+                stdout_bytes: Arc::clone(&stdout_options.bytes_read),
+                stderr_bytes: Arc::clone(&stderr_options.bytes_read),
+            })
+        });
+        // Capture threads only run for piped streams, so a fully inherited run spawns none.
+        // Each holds a reference to `output_open` until its stream has closed.
+        let quiet = config.quiet;
+        let output_open = Arc::new(());
+        let stdout_rx = stdout.map(|stdout| {
+            let (stdout_tx, stdout_rx) = mpsc::channel();
+            let open = Arc::clone(&output_open);
+            thread::spawn(move || {
+                let _open = open;
+                let echo = if quiet { Box::new(io::sink()) as Box<dyn Write> } else { Box::new(io::stdout()) };
+                // The receiver is gone if waiting failed; nothing is left to report to
+                let _ = stdout_tx.send(capture_stream_lines(stdout, echo, "stdout", &stdout_options));
+            });
+            stdout_rx
+        });
+        let stderr_rx = stderr.map(|stderr| {
+            let (stderr_tx, stderr_rx) = mpsc::channel();
+            let open = Arc::clone(&output_open);
+            thread::spawn(move || {
+                let _open = open;
+                let echo = if quiet { Box::new(io::sink()) as Box<dyn Write> } else { Box::new(io::stderr()) };
+                let _ = stderr_tx.send(capture_stream_lines(stderr, echo, "stderr", &stderr_options));
+            });
+            stderr_rx
+        });
+
+        // Wait for process to complete
+        let (output, timed_out, files_opened, child_processes, resource_usage, traced_exit) = if tracing {
+            let output_closed = || Arc::strong_count(&output_open) == 1;
+            let (status, timed_out, files, processes, usage, exited_at) = trace_until_exit(&child, config, &output_closed)?;
+            let files = config.trace_files.then_some(files);
+            (status, timed_out, files, config.capture_tree.then_some(processes), usage, exited_at)
+        } else {
+            let (status, timed_out, usage) =
+                wait_with_timeout(&mut child, config.timeout, config.forward_signals, foreground.as_ref())?;
+            (status, timed_out, None, None, usage, None)
+        };
+        // Tracing may have gone on until the command's output closed
+        let exited_at = traced_exit.unwrap_or_else(Instant::now);
+        // The terminal is back with the driver before anything is reported
+        #[cfg(unix)]
+        drop(foreground);
+        let heartbeats = heartbeat.map(Heartbeat::stop).unwrap_or_default();
+        let resource_samples = sampler.map(ResourceSampler::stop);
+        let peak_open_fds = resource_samples
+            .as_ref()
+            .and_then(|samples| samples.iter().filter_map(|sample| sample.open_fds).max());
+        let interrupted_by = signals.as_ref().and_then(SignalForwarder::received);
+        drop(signals);
+        if let Some(signal) = interrupted_by {
+            eprintln!("⚠️ [{}] Interrupted by signal {}", session_id, signal);
+        }
+        let net_connections = net_sampler.map(NetSampler::stop);
+        let end_time = SystemTime::now();
+
+        let (stdin_provided, stdin_encoding, stdin_truncated) = if config.capture_stdin {
+            let captured = stdin_captured.lock().unwrap_or_else(PoisonError::into_inner);
+            let (text, encoding) = match std::str::from_utf8(&captured.bytes) {
+                Ok(text) => (text.to_string(), "utf8"),
+                Err(_) => (BASE64.encode(&captured.bytes), "base64"),
+            };
+            (Some(text), Some(encoding.to_string()), Some(captured.truncated))
+        } else {
+            (None, None, None)
+        };
+
+        // Collect stdout and stderr results
+        let (stdout_capture, stdout_error) = received_capture(stdout_rx, "stdout", session_id);
+        let (stderr_capture, stderr_error) = received_capture(stderr_rx, "stderr", session_id);
+        let capture_error = match (stdout_error, stderr_error) {
+            (Some(out), Some(err)) => Some(format!("{}; {}", out, err)),
+            (out, err) => out.or(err),
+        };
+
+        Ok(self.finish_attempt(command, state, Outcome {
+            status: output,
+            timed_out,
+            spawned_at,
+            exited_at,
+            end_time,
+            stdout_capture,
+            stderr_capture,
+            capture_error,
+            interleaved,
+            stdin_provided,
+            stdin_encoding,
+            stdin_truncated,
+            resource_usage,
+            resource_samples,
+            peak_open_fds,
+            files_opened,
+            child_processes,
+            interrupted_by,
+            net_connections,
+            heartbeats,
+        }))
+    }
+
+    /// Everything ahead of the spawn: the start event is built and logged, and
+    /// the returned command is ready to spawn with its streams piped as configured.
+    fn begin_attempt(&self, command: &[String], attempt: u32) -> io::Result<(Attempt<'_>, Command)> {
+        let config = &self.config;
+        let run_started = Instant::now();
+        let attempt = (config.retries > 0).then_some(attempt);
+        let session_id = &config.session_id;
+
+        if command.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no command given"));
+        }
+
+        let ppid = get_parent_pid();
+        let pipefail = config.pipefail.then(|| match shell_supports_pipefail(&command[0]) {
+            Some(true) => true,
+            Some(false) => {
+                eprintln!("⚠️ TELEMETRY_PIPEFAIL: {} may not support pipefail; recording its exit as is", command[0]);
+                false
+            }
+            None => false,
+        });
+        let resolved_executable = resolve_executable(&command[0], child_path(config).as_deref())
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| command[0].clone());
+
+        // Nested drivers pick these up and report their span under ours
+        let span = config.otlp_endpoint.as_ref().map(|_| {
+            SpanContext::new(session_id, config.trace_id.as_deref(), config.parent_span_id.as_deref())
+        });
+        let mut driver_env = vec![(SESSION_ID_ENV, session_id.clone()), (PARENT_SESSION_ID_ENV, session_id.clone())];
+        if let Some(span) = &span {
+            driver_env.push((TRACE_ID_ENV, span.trace_id.clone()));
+            driver_env.push((PARENT_SPAN_ID_ENV, span.span_id.clone()));
+        }
+        let captured_env = capture_env(config, &driver_env);
+        let child_env_overrides = child_env_overrides(config);
+
+        let start_time = SystemTime::now();
+        if start_time < UNIX_EPOCH {
+            eprintln!("⚠️ System clock is before the Unix epoch; recording timestamps as 0");
+        }
+        let start_timestamp = unix_timestamp(start_time);
+
+        let cwd = match env::current_dir() {
+            Ok(dir) => dir.to_string_lossy().to_string(),
+            Err(e) => {
+                eprintln!("⚠️ Could not read the working directory: {}", e);
+                UNKNOWN_CWD.to_string()
+            }
+        };
+
+        let credentials = credentials();
+        let (hostname, container_id) = (hostname(), container_id());
+        let git = if config.capture_git { git_context() } else { None };
+
+        // What every event of this attempt carries; each event fills in the rest
+        let base_event = TelemetryEvent {
+            driver_version: DRIVER_VERSION,
+            schema_version: SCHEMA_VERSION,
+            pid: std::process::id() as i32,
+            ppid,
+            hostname,
+            container_id,
+            session_id: session_id.clone(),
+            parent_session_id: config.parent_session_id.clone(),
+            attempt,
+            command: command.to_vec(),
+            resolved_executable: Some(resolved_executable),
+            cwd,
+            tags: config.tags.clone(),
+            ..TelemetryEvent::default()
+        };
+
+        // Capture pre-execution state
+        let event_id = config.next_event_id();
+        let pre_event = TelemetryEvent {
+            short_id: short_id(&event_id),
+            event_id,
+            event_type: "process_start".to_string(),
+            timestamp: start_timestamp,
+            timestamp_ns: unix_timestamp_ns(start_time),
+            uid: credentials.uid,
+            gid: credentials.gid,
+            euid: credentials.euid,
+            egid: credentials.egid,
+            umask: credentials.umask,
+            user_sid: credentials.user_sid,
+            stdin_is_tty: Some(io::stdin().is_terminal()),
+            stdout_is_tty: Some(io::stdout().is_terminal()),
+            stderr_is_tty: Some(io::stderr().is_terminal()),
+            pipefail,
+            git_branch: git.as_ref().and_then(|git| git.branch.clone()),
+            git_sha: git.as_ref().and_then(|git| git.sha.clone()),
+            git_dirty: git.as_ref().map(|git| git.dirty),
+            env: captured_env.vars,
+            env_truncated: captured_env.truncated,
+            env_source: captured_env.source,
+            child_env_overrides,
+            ancestry: config.capture_ancestry.then(|| process_ancestry(MAX_ANCESTRY_DEPTH)),
+            ..base_event.clone()
+        };
+
+        let mut event_log = EventLog::new(config);
+        if config.event_mode == EventMode::Multi {
+            event_log.log(&pre_event);
+        }
+        // Heartbeats are meant to outlive a crashed driver, so the start event mustn't wait either
+        if config.heartbeat.is_some() {
+            event_log.flush();
+        }
+
+        if config.banner == BannerMode::Always {
+            eprintln!("🚀 [{}] Executing: {}", session_id, command.join(" "));
+        }
+
+        // Execute command with full stdio capture
+        let mut cmd = Command::new(&command[0]);
+        if pipefail == Some(true) {
+            cmd.args(["-o", "pipefail"]);
+        }
+        cmd.args(&command[1..]);
+        cmd.stdout(if config.capture_stdout { Stdio::piped() } else { Stdio::inherit() });
+        cmd.stderr(if config.capture_stderr { Stdio::piped() } else { Stdio::inherit() });
+        cmd.stdin(Stdio::piped());
+        for name in &config.child_env_unset {
+            cmd.env_remove(name);
+        }
+        cmd.envs(&config.child_env_set);
+        cmd.envs(driver_env);
+
+        let state = Attempt {
+            run_started,
+            start_time,
+            start_timestamp,
+            base_event,
+            pre_event,
+            event_log,
+            span,
+            capture_stdout: config.capture_stdout,
+            capture_stderr: config.capture_stderr,
+        };
+        Ok((state, cmd))
+    }
+
+    /// Logs a failed spawn and hands back its error.
+    fn spawn_failed(&self, command: &[String], state: Attempt<'_>, e: io::Error) -> io::Error {
+        let config = &self.config;
+        let session_id = &config.session_id;
+        let Attempt { pre_event, mut event_log, .. } = state;
+        eprintln!("❌ [{}] Failed to execute {}: {}", session_id, command[0], e);
+
+        let failed_at = SystemTime::now();
+        let event_id = config.next_event_id();
+        let error_event = TelemetryEvent {
+            short_id: short_id(&event_id),
+            event_id,
+            event_type: "process_spawn_error".to_string(),
+            timestamp: unix_timestamp(failed_at),
+            timestamp_ns: unix_timestamp_ns(failed_at),
+            // Single mode never wrote the start event, so keep its env here
+            env: match config.event_mode {
+                EventMode::Multi => HashMap::new(),
+                EventMode::Single => pre_event.env.clone(),
+            },
+            env_source: match config.event_mode {
+                EventMode::Multi => None,
+                EventMode::Single => pre_event.env_source.clone(),
+            },
+            error: Some(e.to_string()),
+            ..pre_event
+        };
+        event_log.log(&error_event);
+        event_log.release_held();
+        event_log.finish();
+        e
+    }
+
+    /// How each stream of an attempt spawned at `spawned_at` is captured.
+    fn capture_options(
+        &self,
+        command: &[String],
+        spawned_at: Instant,
+        interleaved: Option<Arc<Mutex<Vec<OutputLine>>>>,
+    ) -> CaptureOptions {
+        let config = &self.config;
+        let session_id = &config.session_id;
+        CaptureOptions {
+            max_capture_bytes: config.max_capture_bytes,
+            max_lines: config.max_lines,
+            head_lines: config.head_lines,
+            tail_lines: config.tail_lines,
+            max_line_bytes: config.max_line_bytes,
+            started: spawned_at,
+            timestamp_lines: config.timestamp_lines,
+            interleaved,
+            bytes_read: Arc::new(AtomicUsize::new(0)),
+            hash_output: config.hash_output,
+            strip_ansi: config.strip_ansi,
+            fail_pattern: config.fail_on_pattern.clone(),
+            exact_passthrough: config.exact_passthrough,
+            capture_raw: config.capture_raw,
+            echo_prefix: config.line_prefix.as_ref().map(|template| {
+                let program = Path::new(&command[0]).file_name().map_or(command[0].clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                });
+                template.replace("{session}", session_id).replace("{cmd}", &program)
+            }),
+        }
+    }
+
+    /// Builds, logs and reports the end and stats events once the child has exited.
+    fn finish_attempt(&self, command: &[String], state: Attempt<'_>, outcome: Outcome) -> ProcessStats {
+        let config = &self.config;
+        let session_id = &config.session_id;
+        let Attempt {
+            run_started,
+            start_time,
+            start_timestamp,
+            base_event,
+            pre_event,
+            mut event_log,
+            span,
+            capture_stdout,
+            capture_stderr,
+        } = state;
+        let Outcome {
+            status: output,
+            timed_out,
+            spawned_at,
+            exited_at,
+            end_time,
+            stdout_capture,
+            stderr_capture,
+            capture_error,
+            interleaved,
+            stdin_provided,
+            stdin_encoding,
+            stdin_truncated,
+            resource_usage,
+            resource_samples,
+            peak_open_fds,
+            files_opened,
+            child_processes,
+            interrupted_by,
+            net_connections,
+            heartbeats,
+        } = outcome;
+        if timed_out {
+            eprintln!("⏱️ [{}] Killed after exceeding {}ms timeout",
+                session_id,
+                config.timeout.unwrap_or_default().as_millis());
+        }
+        // Wall-clock time is only for timestamps; the duration comes from the
+        // monotonic clock so an NTP step mid-run can't skew it.
+        let duration = exited_at - spawned_at;
+        let slow_warning = config.timeout.map(|timeout| {
+            !timed_out && duration.as_secs_f64() * 100.0 >= timeout.as_secs_f64() * config.slow_threshold_pct
+        });
+        if slow_warning == Some(true) {
+            let timeout = config.timeout.unwrap_or_default();
+            eprintln!("⚠️ [{}] Used {:.0}% of the {}ms timeout",
+                session_id,
+                duration.as_secs_f64() * 100.0 / timeout.as_secs_f64(),
+                timeout.as_millis());
+        }
+        let (stdout_bytes, stderr_bytes) = (stdout_capture.total_bytes, stderr_capture.total_bytes);
+        let truncated = stdout_capture.truncated || stderr_capture.truncated;
+        let truncated_lines = stdout_capture.truncated_lines || stderr_capture.truncated_lines;
+        let lossy = stdout_capture.lossy || stderr_capture.lossy;
+        let had_ansi = config.strip_ansi.then_some(stdout_capture.had_ansi || stderr_capture.had_ansi);
+        let time_to_first_output_ms = match (stdout_capture.first_output_ms, stderr_capture.first_output_ms) {
+            (Some(out), Some(err)) => Some(out.min(err)),
+            (out, err) => out.or(err),
+        };
+        // Both capture threads have finished, so the shared record is complete
+        let interleaved_output = interleaved
+            .map(|shared| std::mem::take(&mut *shared.lock().unwrap_or_else(PoisonError::into_inner)));
+        let combined_output = config.combine_output.then(|| {
+            // Ordered by read time; the shared record is in lock order, which a busy thread can skew
+            let mut lines: Vec<&OutputLine> = interleaved_output.iter().flatten().collect();
+            lines.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+            lines.into_iter().map(|line| line.line.clone()).collect::<Vec<_>>()
+        });
+        let interleaved_output = interleaved_output.filter(|_| config.interleave_output);
+
+        let (exit_code, signal) = exit_code_and_signal(&output);
+
+        // Create process statistics
+        let process_stats = ProcessStats {
+            start_time: start_timestamp,
+            end_time: unix_timestamp(end_time),
+            duration_ms: duration.as_millis() as u64,
+            time_to_first_output_ms,
+            exit_code,
+            signal,
+            terminated_by_signal: signal.is_some(),
+            core_dumped: core_dumped(&output),
+            timed_out,
+            pattern_matched: stdout_capture.pattern_matched || stderr_capture.pattern_matched,
+            stdout_lines: stdout_capture.total_lines,
+            stderr_lines: stderr_capture.total_lines,
+            total_output_bytes: stdout_bytes + stderr_bytes,
+        };
+
+        // Capture post-execution state
+        let event_id = config.next_event_id();
+        let post_event = TelemetryEvent {
+            short_id: short_id(&event_id),
+            event_id,
+            event_type: if interrupted_by.is_some() { "process_interrupted" } else { "process_end" }.to_string(),
+            timestamp: process_stats.end_time,
+            timestamp_ns: unix_timestamp_ns(end_time),
+            resource_usage: resource_usage.clone(),
+            resource_scope: resource_usage.as_ref().map(|_| RESOURCE_SCOPE),
+            duration_ms: Some(process_stats.duration_ms),
+            time_to_first_output_ms: process_stats.time_to_first_output_ms,
+            exit_code: Some(process_stats.exit_code),
+            signal: process_stats.signal,
+            terminated_by_signal: Some(process_stats.terminated_by_signal),
+            core_dumped: Some(process_stats.core_dumped),
+            stdout_lines: (capture_stdout && !config.combine_output && !config.capture_raw)
+                .then(|| stdout_line_values(stdout_capture.lines, config.parse_json_lines)),
+            stderr_lines: (capture_stderr && !config.combine_output && !config.capture_raw).then_some(stderr_capture.lines),
+            stdout_raw: stdout_capture.raw.map(|raw| BASE64.encode(raw)),
+            stderr_raw: stderr_capture.raw.map(|raw| BASE64.encode(raw)),
+            stdout_line_offsets_ms: stdout_capture.line_offsets_ms,
+            stderr_line_offsets_ms: stderr_capture.line_offsets_ms,
+            stdin_provided,
+            stdin_encoding,
+            stdin_truncated,
+            stdout_size_bytes: capture_stdout.then_some(stdout_bytes),
+            stderr_size_bytes: capture_stderr.then_some(stderr_bytes),
+            stdout_sha256: stdout_capture.sha256,
+            stderr_sha256: stderr_capture.sha256,
+            truncated: Some(truncated),
+            truncated_lines: Some(truncated_lines),
+            lossy: Some(lossy),
+            had_ansi,
+            error: capture_error,
+            timed_out: Some(process_stats.timed_out),
+            slow_warning,
+            pattern_matched: config.fail_on_pattern.as_ref().map(|_| process_stats.pattern_matched),
+            output: interleaved_output,
+            combined_output,
+            resource_samples,
+            peak_open_fds,
+            files_opened,
+            child_processes,
+            interrupted_by,
+            net_connections,
+            ..base_event.clone()
+        };
+        // The summary names the event that records the outcome
+        let summary_id = post_event.short_id.clone();
+        event_log.hold(heartbeats);
+
+        match config.event_mode {
+            EventMode::Multi => event_log.log(&post_event),
+            EventMode::Single => event_log.log(&TelemetryEvent {
+                event_type: if interrupted_by.is_some() { "process_interrupted" } else { "process_complete" }.to_string(),
+                uid: pre_event.uid,
+                gid: pre_event.gid,
+                euid: pre_event.euid,
+                egid: pre_event.egid,
+                umask: pre_event.umask,
+                user_sid: pre_event.user_sid,
+                stdin_is_tty: pre_event.stdin_is_tty,
+                stdout_is_tty: pre_event.stdout_is_tty,
+                stderr_is_tty: pre_event.stderr_is_tty,
+                pipefail: pre_event.pipefail,
+                git_branch: pre_event.git_branch,
+                git_sha: pre_event.git_sha,
+                git_dirty: pre_event.git_dirty,
+                env: pre_event.env,
+                env_truncated: pre_event.env_truncated,
+                env_source: pre_event.env_source,
+                child_env_overrides: pre_event.child_env_overrides,
+                ancestry: pre_event.ancestry,
+                ..post_event
+            }),
+        }
+
+        // Log process statistics summary
+        // Everything the driver did outside the child's run, short of flushing this event
+        let driver_overhead = (spawned_at - run_started) + exited_at.elapsed();
+        let event_id = config.next_event_id();
+        let stats_event = TelemetryEvent {
+            short_id: short_id(&event_id),
+            event_id,
+            event_type: "process_stats".to_string(),
+            timestamp: process_stats.end_time,
+            timestamp_ns: unix_timestamp_ns(end_time),
+            env: HashMap::from([
+                ("duration_ms".to_string(), process_stats.duration_ms.to_string()),
+                ("stdout_lines".to_string(), process_stats.stdout_lines.to_string()),
+                ("stderr_lines".to_string(), process_stats.stderr_lines.to_string()),
+                ("total_bytes".to_string(), process_stats.total_output_bytes.to_string()),
+            ]),
+            resource_scope: resource_usage.as_ref().map(|_| RESOURCE_SCOPE),
+            resource_usage,
+            driver_resource_usage: driver_resource_usage(),
+            duration_ms: Some(process_stats.duration_ms),
+            time_to_first_output_ms: process_stats.time_to_first_output_ms,
+            driver_overhead_ms: Some(driver_overhead.as_secs_f64() * 1000.0),
+            exit_code: Some(process_stats.exit_code),
+            signal: process_stats.signal,
+            terminated_by_signal: Some(process_stats.terminated_by_signal),
+            core_dumped: Some(process_stats.core_dumped),
+            stdout_size_bytes: capture_stdout.then_some(stdout_bytes),
+            stderr_size_bytes: capture_stderr.then_some(stderr_bytes),
+            truncated: Some(truncated),
+            truncated_lines: Some(truncated_lines),
+            lossy: Some(lossy),
+            timed_out: Some(process_stats.timed_out),
+            slow_warning,
+            pattern_matched: config.fail_on_pattern.as_ref().map(|_| process_stats.pattern_matched),
+            ..base_event
+        };
+
+        if config.event_mode == EventMode::Multi {
+            event_log.log(&stats_event);
+        }
+        if process_stats.pattern_matched && process_stats.exit_code == 0 {
+            eprintln!("⚠️ [{}] Output matched TELEMETRY_FAIL_ON_PATTERN; treating the run as failed", session_id);
+        }
+        let failed = process_stats.exit_code != 0
+            || process_stats.terminated_by_signal
+            || process_stats.timed_out
+            || process_stats.pattern_matched;
+        if failed {
+            event_log.release_held();
+        }
+        event_log.finish();
+
+        if let (Some(endpoint), Some(span)) = (&config.otlp_endpoint, &span) {
+            if let Err(e) = export_span(endpoint, span, session_id, command, start_time, end_time,
+                &process_stats, stats_event.resource_usage.as_ref()) {
+                eprintln!("⚠️ Failed to export span to {}: {}", endpoint, e);
+            }
+        }
+
+        let destination = config
+            .http_endpoint
+            .as_ref()
+            .or(config.kafka.as_ref().map(|(_, topic)| topic))
+            .or(config.socket.as_ref())
+            .map_or(config.log_destination(), String::as_str);
+        let show_summary = match config.banner {
+            BannerMode::Always => true,
+            BannerMode::Auto => failed,
+            BannerMode::Never => false,
+        };
+        if show_summary && config.summary_json {
+            eprintln!("{}", serde_json::json!({
+                "session_id": session_id,
+                "short_id": summary_id,
+                "duration_ms": process_stats.duration_ms,
+                "exit_code": process_stats.exit_code,
+                "signal": process_stats.signal,
+                "timed_out": process_stats.timed_out,
+                "stdout_lines": process_stats.stdout_lines,
+                "stdout_bytes": stdout_bytes,
+                "stderr_lines": process_stats.stderr_lines,
+                "stderr_bytes": stderr_bytes,
+                "telemetry": destination,
+            }));
+        } else if show_summary {
+            if config.banner == BannerMode::Auto {
+                eprintln!("🚀 [{}] Executing: {}", session_id, command.join(" "));
+            }
+            eprintln!("✅ [{}] Completed in {:.2}ms | Exit: {} | Out: {} lines/{} bytes | Err: {} lines/{} bytes",
+                session_id,
+                process_stats.duration_ms,
+                process_stats.exit_code,
+                process_stats.stdout_lines,
+                stdout_bytes,
+                process_stats.stderr_lines,
+                stderr_bytes);
+            eprintln!("📊 Telemetry: {} (event {})", destination, summary_id);
+        }
+
+        process_stats
+    }
+}
+
+/// An attempt's state from its setup to its report.
+struct Attempt<'a> {
+    run_started: Instant,
+    start_time: SystemTime,
+    start_timestamp: f64,
+    /// The fields every event of the attempt shares.
+    base_event: TelemetryEvent,
+    pre_event: TelemetryEvent,
+    event_log: EventLog<'a>,
+    span: Option<SpanContext>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+}
+
+/// What an attempt's child did, gathered from its spawn until its streams closed.
+struct Outcome {
+    status: ExitStatus,
+    timed_out: bool,
+    spawned_at: Instant,
+    exited_at: Instant,
+    end_time: SystemTime,
+    stdout_capture: StreamCapture,
+    stderr_capture: StreamCapture,
+    capture_error: Option<String>,
+    interleaved: Option<Arc<Mutex<Vec<OutputLine>>>>,
+    stdin_provided: Option<String>,
+    stdin_encoding: Option<String>,
+    stdin_truncated: Option<bool>,
+    resource_usage: Option<ResourceUsage>,
+    resource_samples: Option<Vec<ResourceSample>>,
+    peak_open_fds: Option<usize>,
+    files_opened: Option<Vec<String>>,
+    child_processes: Option<Vec<ChildProcess>>,
+    interrupted_by: Option<i32>,
+    net_connections: Option<Vec<String>>,
+    /// Heartbeats held back under `only_failures`, to be logged before the end event.
+    heartbeats: Vec<TelemetryEvent>,
+}
+
+/// Attaches `cmd` to a new PTY, falling back to pipes if one can't be opened.
+#[cfg(unix)]
+fn attach_pty(cmd: &mut Command) -> Option<std::fs::File> {
+    match pty::attach_pty(cmd) {
+        Ok(master) => Some(master),
+        Err(e) => {
+            eprintln!("⚠️ Failed to allocate a PTY, using pipes: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn attach_pty(_cmd: &mut Command) -> Option<std::fs::File> {
+    eprintln!("⚠️ TELEMETRY_PTY is only supported on Unix, using pipes");
+    None
+}
+
+/// Arms ptrace file tracing for `cmd`; false where it isn't supported.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn prepare_trace(cmd: &mut Command) -> bool {
+    file_trace::prepare(cmd);
+    true
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn prepare_trace(_cmd: &mut Command) -> bool {
+    eprintln!("⚠️ TELEMETRY_TRACE_FILES and TELEMETRY_CAPTURE_TREE are only supported on Linux x86_64");
+    false
+}
+
+/// Exit status, whether the timeout fired, opened files, descendants, usage and
+/// the time of the command's exit, of a traced run.
+type TraceOutcome = (ExitStatus, bool, Vec<String>, Vec<ChildProcess>, Option<ResourceUsage>, Option<Instant>);
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn trace_until_exit(child: &Child, config: &Config, output_closed: &dyn Fn() -> bool) -> io::Result<TraceOutcome> {
+    let options = file_trace::TraceOptions { files: config.trace_files, tree: config.capture_tree };
+    let (status, timed_out, report) = file_trace::trace_until_exit(child, config.timeout, options, output_closed)?;
+    Ok((status, timed_out, report.files, report.processes, report.resource_usage, report.exited_at))
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn trace_until_exit(_child: &Child, _config: &Config, _output_closed: &dyn Fn() -> bool) -> io::Result<TraceOutcome> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "ptrace tracing is not supported on this platform"))
+}
+
+#[cfg(unix)]
+fn pty_eof_marker() -> Option<u8> {
+    Some(pty::PTY_EOF)
+}
+
+#[cfg(not(unix))]
+fn pty_eof_marker() -> Option<u8> {
+    None
+}
+
+/// Whether `program` is a shell taking `-o pipefail`: `Some(false)` for plain
+/// POSIX shells that may lack it, `None` for anything that isn't a known shell.
+fn shell_supports_pipefail(program: &str) -> Option<bool> {
+    let name = Path::new(program).file_stem()?.to_str()?;
+    match name {
+        "bash" | "zsh" | "ksh" | "mksh" => Some(true),
+        "sh" | "dash" | "ash" => Some(false),
+        _ => None,
+    }
+}
+
+/// Captured stdout as event values. With `parse_json` set, lines holding a JSON
+/// object or array are embedded as that value; everything else stays a string.
+fn stdout_line_values(lines: Vec<String>, parse_json: bool) -> Vec<serde_json::Value> {
+    lines
+        .into_iter()
+        .map(|line| {
+            let parsed = parse_json
+                .then(|| serde_json::from_str::<serde_json::Value>(&line).ok())
+                .flatten()
+                .filter(|value| value.is_object() || value.is_array());
+            parsed.unwrap_or(serde_json::Value::String(line))
+        })
+        .collect()
+}
+
+/// Waits for a capture thread's result; an inherited stream has no thread and
+/// an empty capture. A thread that panicked yields an empty capture and the
+/// error to record, so the run is still reported.
+fn received_capture(
+    rx: Option<mpsc::Receiver<StreamCapture>>,
+    stream: &str,
+    session_id: &str,
+) -> (StreamCapture, Option<String>) {
+    match rx.map(|rx| rx.recv()) {
+        None => (StreamCapture::default(), None),
+        Some(Ok(capture)) => (capture, None),
+        Some(Err(_)) => {
+            eprintln!("⚠️ [{}] Lost the {} capture: its thread stopped without a result", session_id, stream);
+            report_driver_error(session_id, "capture", format!("{} capture thread stopped without a result", stream));
+            (StreamCapture::default(), Some(format!("{} capture failed", stream)))
+        }
+    }
+}
+
+/// The [`TelemetryEvent::short_id`] for `event_id`.
+pub(crate) fn short_id(event_id: &str) -> String {
+    event_id.chars().filter(char::is_ascii_hexdigit).take(8).collect()
+}
+
+/// Seconds since the Unix epoch, or 0 for a clock set before it.
+pub(crate) fn unix_timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// [`unix_timestamp`] in whole nanoseconds.
+pub(crate) fn unix_timestamp_ns(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// Parses `TELEMETRY_TAGS`, either `k=v,k2=v2` or a JSON object. Non-string
+/// JSON values are kept in their JSON form.
+fn parse_tags(value: &str) -> HashMap<String, String> {
+    if value.trim_start().starts_with('{') {
+        return match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(value) {
+            Ok(object) => object
+                .into_iter()
+                .map(|(k, v)| match v {
+                    serde_json::Value::String(s) => (k, s),
+                    other => (k, other.to_string()),
+                })
+                .collect(),
+            Err(e) => {
+                eprintln!("⚠️ Ignoring TELEMETRY_TAGS, invalid JSON: {}", e);
+                HashMap::new()
+            }
+        };
+    }
+
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// The recorded environment and what was learned while recording it.
+struct CapturedEnv {
+    vars: HashMap<String, String>,
+    truncated: Option<bool>,
+    source: Option<HashMap<String, EnvSource>>,
+}
+
+/// The `PATH` the child gets, which is what spawning it searches: the
+/// driver's own unless `TELEMETRY_CHILD_ENV_SET` or `_UNSET` changes it.
+fn child_path(config: &Config) -> Option<OsString> {
+    match config.child_env_set.get("PATH") {
+        Some(path) => Some(path.into()),
+        None if config.child_env_unset.iter().any(|name| name == "PATH") => None,
+        None => env::var_os("PATH"),
+    }
+}
+
+/// Records the environment the child gets: the driver's own with its
+/// overrides and `driver_env` applied, then filtered, redacted and capped.
+fn capture_env(config: &Config, driver_env: &[(&str, String)]) -> CapturedEnv {
+    if !config.capture_env {
+        return CapturedEnv { vars: HashMap::new(), truncated: None, source: None };
+    }
+
+    let mut captured: HashMap<String, String> = env::vars().collect();
+    for name in &config.child_env_unset {
+        captured.remove(name);
+    }
+    captured.extend(config.child_env_set.clone());
+    captured.extend(driver_env.iter().map(|(name, value)| (name.to_string(), value.clone())));
+    if let Some(allow) = &config.env_allow {
+        allow_env(&mut captured, allow);
+    }
+    redact_env(&mut captured, &config.env_redact);
+    let capped = cap_env(&mut captured, config.env_max_value_bytes, config.env_max_count);
+    let source = captured
+        .keys()
+        .map(|name| {
+            let added = config.child_env_set.contains_key(name) || driver_env.iter().any(|(driver, _)| driver == name);
+            (name.clone(), if added { EnvSource::DriverAdded } else { EnvSource::Inherited })
+        })
+        .collect();
+    CapturedEnv { vars: captured, truncated: Some(capped), source: Some(source) }
+}
+
+/// The applied environment changes, or `None` when nothing was changed.
+fn child_env_overrides(config: &Config) -> Option<ChildEnvOverrides> {
+    // Setting a variable after unsetting it wins, as it does for the Command
+    let unset: Vec<String> = config
+        .child_env_unset
+        .iter()
+        .filter(|name| env::var_os(name).is_some() && !config.child_env_set.contains_key(*name))
+        .cloned()
+        .collect();
+    if unset.is_empty() && config.child_env_set.is_empty() {
+        return None;
+    }
+    let mut set = config.child_env_set.clone();
+    redact_env(&mut set, &config.env_redact);
+    Some(ChildEnvOverrides { set, unset })
+}
+
+/// True when `TELEMETRY_DISABLE` asks for the command to run without any capture.
+pub fn telemetry_disabled() -> bool {
+    Settings::env().flag("TELEMETRY_DISABLE")
+}
+
+/// Runs `command` with inherited stdio and no telemetry at all. On Unix the
+/// driver execs the command directly, so this only returns if that fails.
+pub fn passthrough(command: &[String]) -> io::Result<i32> {
+    if command.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no command given"));
+    }
+    exec_command(command)
+}
+
+/// Runs `command` under telemetry capture; see [`TelemetryDriver::run`].
+pub fn run(command: &[String], config: &Config) -> io::Result<ProcessStats> {
+    TelemetryDriver::new(config.clone()).run(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_capture_thread_yields_an_empty_capture_and_an_error() {
+        let (tx, rx) = mpsc::channel::<StreamCapture>();
+        let thread = std::thread::spawn(move || {
+            let _tx = tx;
+            panic!("capture thread failed");
+        });
+        assert!(thread.join().is_err());
+
+        let (capture, error) = received_capture(Some(rx), "stdout", "session");
+        assert!(capture.lines.is_empty());
+        assert_eq!(error.as_deref(), Some("stdout capture failed"));
+    }
+
+    #[test]
+    fn inherited_stream_has_no_capture_error() {
+        let (capture, error) = received_capture(None, "stderr", "session");
+        assert!(capture.lines.is_empty());
+        assert!(error.is_none());
+    }
+}
@@ -0,0 +1,140 @@
+//! Recording the remote addresses the child connects to (`TELEMETRY_TRACE_NET`),
+//! by periodically matching its socket fds against `/proc/<pid>/net/tcp{,6}`.
+//! Linux only; connections opened and closed between two samples are missed.
+
+use std::collections::BTreeSet;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Sampling interval when `TELEMETRY_SAMPLE_INTERVAL_MS` isn't set.
+pub(crate) const DEFAULT_NET_SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+pub(crate) struct NetSampler {
+    stop: Sender<()>,
+    handle: JoinHandle<BTreeSet<String>>,
+}
+
+impl NetSampler {
+    pub(crate) fn start(interval: Duration, child_pid: u32) -> Self {
+        #[cfg(not(target_os = "linux"))]
+        eprintln!("⚠️ TELEMETRY_TRACE_NET is only supported on Linux");
+
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut connections = BTreeSet::new();
+            loop {
+                connections.extend(connected_addresses(child_pid));
+                if !matches!(stopped.recv_timeout(interval), Err(RecvTimeoutError::Timeout)) {
+                    return connections;
+                }
+            }
+        });
+        NetSampler { stop, handle }
+    }
+
+    /// Stops sampling and returns every remote address seen, deduplicated.
+    pub(crate) fn stop(self) -> Vec<String> {
+        let _ = self.stop.send(());
+        self.handle.join().unwrap_or_default().into_iter().collect()
+    }
+}
+
+/// Remote `ip:port` of each connected TCP socket held by `pid` or its descendants.
+#[cfg(target_os = "linux")]
+fn connected_addresses(pid: u32) -> Vec<String> {
+    use std::collections::HashSet;
+    use std::fs;
+
+    let mut inodes = HashSet::new();
+    let mut pending = vec![pid];
+    while let Some(pid) = pending.pop() {
+        if let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) {
+            for fd in fds.flatten() {
+                let Ok(target) = fs::read_link(fd.path()) else { continue };
+                let target = target.to_string_lossy();
+                if let Some(inode) = target.strip_prefix("socket:[").and_then(|t| t.strip_suffix(']')) {
+                    inodes.insert(inode.to_string());
+                }
+            }
+        }
+        pending.extend(child_pids(pid));
+    }
+    if inodes.is_empty() {
+        return Vec::new();
+    }
+
+    let mut addresses = Vec::new();
+    for table in ["tcp", "tcp6"] {
+        let Ok(contents) = fs::read_to_string(format!("/proc/{}/net/{}", pid, table)) else { continue };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // sl, local_address, rem_address, st, tx:rx, tr:when, retrnsmt, uid, timeout, inode
+            let (Some(remote), Some(inode)) = (fields.get(2), fields.get(9)) else { continue };
+            if inodes.contains(*inode) {
+                addresses.extend(parse_proc_address(remote));
+            }
+        }
+    }
+    addresses
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connected_addresses(_pid: u32) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn child_pids(pid: u32) -> Vec<u32> {
+    let Ok(tasks) = std::fs::read_dir(format!("/proc/{}/task", pid)) else {
+        return Vec::new();
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| std::fs::read_to_string(task.path().join("children")).ok())
+        .flat_map(|children| children.split_whitespace().filter_map(|p| p.parse().ok()).collect::<Vec<_>>())
+        .collect()
+}
+
+/// Decodes a `/proc/net/tcp{,6}` address like `0100007F:1F90`: the IP is
+/// hex in 32-bit little-endian words, the port plain hex. Unconnected
+/// (all-zero) remotes are skipped.
+#[cfg(target_os = "linux")]
+fn parse_proc_address(field: &str) -> Option<String> {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    let (ip, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words: Vec<u32> = (0..ip.len() / 8)
+        .map(|i| u32::from_str_radix(&ip[i * 8..i * 8 + 8], 16).map(u32::from_be))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let ip = match words.as_slice() {
+        [word] => IpAddr::V4(Ipv4Addr::from(word.to_be_bytes())),
+        [a, b, c, d] => {
+            let mut bytes = [0u8; 16];
+            for (chunk, word) in bytes.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(&word.to_be_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(bytes))
+        }
+        _ => return None,
+    };
+    if ip.is_unspecified() {
+        return None;
+    }
+    Some(SocketAddr::new(ip, port).to_string())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_addresses_decode_to_socket_addresses() {
+        assert_eq!(parse_proc_address("0100007F:1F90").as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(parse_proc_address("00000000000000000000000001000000:0050").as_deref(), Some("[::1]:80"));
+        assert_eq!(parse_proc_address("00000000:0000"), None);
+        assert_eq!(parse_proc_address("garbage"), None);
+    }
+}
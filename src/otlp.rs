@@ -0,0 +1,148 @@
+//! Minimal OTLP/HTTP JSON exporter that reports each driver invocation as one span.
+
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::event::{ProcessStats, ResourceUsage};
+use crate::http::post_json;
+
+/// Exported to the child so a nested driver joins the same trace.
+pub const TRACE_ID_ENV: &str = "TELEMETRY_TRACE_ID";
+/// Exported to the child so a nested driver's span becomes a child of ours.
+pub const PARENT_SPAN_ID_ENV: &str = "TELEMETRY_PARENT_SPAN_ID";
+
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Trace position of the current invocation.
+#[derive(Debug, Clone)]
+pub(crate) struct SpanContext {
+    /// 32 hex chars.
+    pub(crate) trace_id: String,
+    /// 16 hex chars.
+    pub(crate) span_id: String,
+    pub(crate) parent_span_id: Option<String>,
+}
+
+impl SpanContext {
+    /// Joins an inherited trace if there is one, otherwise roots a new trace at `session_id`.
+    pub(crate) fn new(session_id: &str, inherited_trace_id: Option<&str>, parent_span_id: Option<&str>) -> Self {
+        let trace_id = inherited_trace_id
+            .filter(|id| is_hex_id(id, 32))
+            .map(str::to_string)
+            .unwrap_or_else(|| trace_id_for_session(session_id));
+        let span_id = Uuid::new_v4().simple().to_string()[..16].to_string();
+        let parent_span_id = parent_span_id
+            .filter(|id| is_hex_id(id, 16))
+            .map(str::to_string);
+
+        SpanContext { trace_id, span_id, parent_span_id }
+    }
+}
+
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// UUID session ids map directly onto the 16-byte trace id; anything else is hashed into one.
+fn trace_id_for_session(session_id: &str) -> String {
+    let uuid = Uuid::parse_str(session_id)
+        .unwrap_or_else(|_| Uuid::new_v5(&Uuid::NAMESPACE_OID, session_id.as_bytes()));
+    uuid.simple().to_string()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+fn string_attr(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+// OTLP/JSON encodes 64-bit integers as strings
+fn int_attr(key: &str, value: impl ToString) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn bool_attr(key: &str, value: bool) -> Value {
+    json!({ "key": key, "value": { "boolValue": value } })
+}
+
+/// Sends the span for a finished command to an OTLP/HTTP collector.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn export_span(
+    endpoint: &str,
+    span: &SpanContext,
+    session_id: &str,
+    command: &[String],
+    start_time: SystemTime,
+    end_time: SystemTime,
+    stats: &ProcessStats,
+    resource_usage: Option<&ResourceUsage>,
+) -> Result<(), String> {
+    let mut attributes = vec![
+        string_attr("process.command", &command[0]),
+        string_attr("process.command_line", &command.join(" ")),
+        string_attr("telemetry.session_id", session_id),
+        int_attr("process.exit_code", stats.exit_code),
+        int_attr("process.duration_ms", stats.duration_ms),
+        int_attr("process.stdout_lines", stats.stdout_lines),
+        int_attr("process.stderr_lines", stats.stderr_lines),
+        int_attr("process.output_bytes", stats.total_output_bytes),
+        bool_attr("process.timed_out", stats.timed_out),
+    ];
+    if let Some(signal) = stats.signal {
+        attributes.push(int_attr("process.signal", signal));
+    }
+    if let Some(usage) = resource_usage {
+        attributes.push(int_attr("process.user_time_ms", usage.user_time_ms));
+        attributes.push(int_attr("process.system_time_ms", usage.system_time_ms));
+        attributes.push(int_attr("process.max_rss_kb", usage.max_rss_kb));
+        attributes.push(int_attr("process.page_faults", usage.page_faults));
+        attributes.push(int_attr("process.context_switches", usage.context_switches));
+    }
+
+    let failed = stats.exit_code != 0 || stats.timed_out;
+    let mut span_json = json!({
+        "traceId": span.trace_id,
+        "spanId": span.span_id,
+        "name": command.join(" "),
+        "kind": SPAN_KIND_INTERNAL,
+        "startTimeUnixNano": unix_nanos(start_time),
+        "endTimeUnixNano": unix_nanos(end_time),
+        "attributes": attributes,
+        "status": { "code": if failed { STATUS_CODE_ERROR } else { STATUS_CODE_OK } },
+    });
+    if let Some(parent) = &span.parent_span_id {
+        span_json["parentSpanId"] = json!(parent);
+    }
+
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [string_attr("service.name", env!("CARGO_PKG_NAME"))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": [span_json],
+            }],
+        }],
+    });
+
+    post_json(&traces_url(endpoint), None, &body.to_string())
+}
+
+/// Accepts either the collector base URL or the full `/v1/traces` path.
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
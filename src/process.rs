@@ -0,0 +1,743 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::event::{AncestorProcess, ResourceUsage};
+
+/// How often a child running under `TELEMETRY_TIMEOUT_MS` is polled for exit.
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Puts the child in a process group of its own, so a timeout or a forwarded
+/// signal reaches the grandchildren that would otherwise keep its pipes open.
+/// Not for a PTY child, which already leads a new session.
+#[cfg(unix)]
+pub(crate) fn own_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+/// The terminal on the driver's stdin, while the driver's process group is in
+/// its foreground. The child's group, which is in the background as far as the
+/// terminal is concerned, is handed the foreground for the run so that reading
+/// or configuring the terminal doesn't stop it with SIGTTIN or SIGTTOU; the
+/// driver's group gets it back on drop.
+#[cfg(unix)]
+pub(crate) struct Foreground {
+    driver_group: libc::pid_t,
+    child_group: libc::pid_t,
+}
+
+#[cfg(unix)]
+impl Foreground {
+    /// Makes `cmd`, which must get its own process group, take the foreground
+    /// as it starts, if the driver holds it now. The child reads the terminal
+    /// itself, since a read by the driver in the background would stop the driver.
+    pub(crate) fn claim(cmd: &mut Command) -> Option<Self> {
+        use std::os::unix::process::CommandExt;
+
+        let driver_group = unsafe { libc::getpgrp() };
+        // -1 when stdin isn't a terminal
+        if unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) } != driver_group {
+            return None;
+        }
+        cmd.stdin(Stdio::inherit());
+        // Taken in the child too, so it can't reach the terminal before the driver hands it over
+        unsafe {
+            cmd.pre_exec(|| {
+                libc::setpgid(0, 0);
+                set_foreground(libc::getpid());
+                Ok(())
+            });
+        }
+        Some(Foreground { driver_group, child_group: 0 })
+    }
+
+    /// Notes the spawned child, whose group now has the foreground.
+    pub(crate) fn spawned(&mut self, child_pid: u32) {
+        self.child_group = child_pid as libc::pid_t;
+        self.resume();
+    }
+
+    /// Hands the foreground back to the child's group, if the driver's holds it.
+    pub(crate) fn resume(&self) {
+        if unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) } == self.driver_group {
+            set_foreground(self.child_group);
+        }
+    }
+
+    /// Takes the foreground back for the driver's group, if the child's still holds it.
+    pub(crate) fn take_back(&self) {
+        if self.child_group > 0 && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) } == self.child_group {
+            set_foreground(self.driver_group);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        self.take_back();
+    }
+}
+
+/// Makes `group` the foreground process group of the terminal on stdin.
+/// SIGTTOU is ignored around it: a background group asking is otherwise stopped.
+#[cfg(unix)]
+fn set_foreground(group: libc::pid_t) {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, group);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Stands in for the Unix terminal handoff; a Windows console has no foreground group.
+#[cfg(not(unix))]
+pub(crate) struct Foreground;
+
+#[cfg(not(unix))]
+impl Foreground {
+    pub(crate) fn claim(_cmd: &mut Command) -> Option<Self> {
+        None
+    }
+
+    pub(crate) fn spawned(&mut self, _child_pid: u32) {}
+}
+
+/// Sends SIGKILL to the process group `pid` leads. False if there is none.
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) -> bool {
+    // killpg(0) would take the driver's own group with it
+    pid > 0 && unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } == 0
+}
+
+/// Waits for the child, killing its process group once `timeout` elapses. Returns the exit
+/// status, whether the child was killed for timing out, and what it used. With `follow_stops`,
+/// a job control stop of the child is followed by the driver; see
+/// [`follow_stop`](crate::signals::follow_stop).
+#[cfg(unix)]
+pub(crate) fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    follow_stops: bool,
+    foreground: Option<&Foreground>,
+) -> io::Result<(ExitStatus, bool, Option<ResourceUsage>)> {
+    let pid = child.id() as libc::pid_t;
+    let Some(timeout) = timeout else {
+        let (status, usage) = reap(pid, follow_stops, foreground)?;
+        return Ok((status, false, Some(usage)));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some((status, usage)) = wait_exit(pid, libc::WNOHANG, follow_stops, foreground)? {
+            return Ok((status, false, Some(usage)));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            // The child may have exited in the meantime; reaping it still works
+            if !kill_process_group(child.id()) {
+                let _ = child.kill();
+            }
+            let (status, usage) = reap(pid, follow_stops, None)?;
+            return Ok((status, true, Some(usage)));
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    _follow_stops: bool,
+    _foreground: Option<&Foreground>,
+) -> io::Result<(ExitStatus, bool, Option<ResourceUsage>)> {
+    let Some(timeout) = timeout else {
+        let status = child.wait()?;
+        return Ok((status, false, get_resource_usage(child)));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false, get_resource_usage(child)));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            // The child may have exited in the meantime; wait() still reaps it
+            let _ = child.kill();
+            let status = child.wait()?;
+            return Ok((status, true, get_resource_usage(child)));
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Blocks until `pid` exits and reaps it; see [`wait4`].
+#[cfg(unix)]
+fn reap(pid: libc::pid_t, follow_stops: bool, foreground: Option<&Foreground>) -> io::Result<(ExitStatus, ResourceUsage)> {
+    wait_exit(pid, 0, follow_stops, foreground)?.ok_or_else(|| io::Error::other("wait4 returned without a status"))
+}
+
+/// [`wait4`] for an exit, handling any stop on the way: a stopped child would
+/// otherwise leave the driver blocked with no sign of why. Under job control,
+/// i.e. with a terminal on stdin, the driver stops along with it if `follow_stops`.
+#[cfg(unix)]
+fn wait_exit(
+    pid: libc::pid_t,
+    flags: libc::c_int,
+    follow_stops: bool,
+    foreground: Option<&Foreground>,
+) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    use std::io::IsTerminal;
+    use std::os::unix::process::ExitStatusExt;
+
+    loop {
+        match wait4(pid, flags | libc::WUNTRACED)? {
+            Some((status, _)) if status.stopped_signal().is_some() => {
+                let signal = status.stopped_signal().unwrap_or_default();
+                let job_control = matches!(signal, libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU);
+                if job_control && follow_stops && io::stdin().is_terminal() {
+                    crate::signals::follow_stop(pid as u32, foreground);
+                } else {
+                    eprintln!("⚠️ The command was stopped by signal {}; waiting for it to be continued", signal);
+                }
+            }
+            found => return Ok(found),
+        }
+    }
+}
+
+/// Reaps `pid` with `wait4`, so the usage is that process's own (plus whatever
+/// it waited for) rather than `RUSAGE_CHILDREN`'s total over every child the
+/// driver ever reaped. `None` if `WNOHANG` is passed and it is still running.
+#[cfg(unix)]
+fn wait4(pid: libc::pid_t, flags: libc::c_int) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, flags, &mut usage) } {
+            0 => return Ok(None),
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            _ => return Ok(Some((ExitStatus::from_raw(status), resource_usage(&usage)))),
+        }
+    }
+}
+
+// libc field widths vary by platform, so the casts are only redundant on some targets
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn timeval_to_ms(tv: libc::timeval) -> i64 {
+    tv.tv_sec as i64 * 1000 + tv.tv_usec as i64 / 1000
+}
+
+/// What `resource_usage` covers, recorded as `resource_scope`.
+#[cfg(unix)]
+pub(crate) const RESOURCE_SCOPE: &str = "tree";
+#[cfg(not(unix))]
+pub(crate) const RESOURCE_SCOPE: &str = "child";
+
+/// The driver's own resource usage so far, excluding its children.
+#[cfg(unix)]
+pub(crate) fn driver_resource_usage() -> Option<ResourceUsage> {
+    rusage(libc::RUSAGE_SELF)
+}
+
+#[cfg(unix)]
+fn rusage(who: libc::c_int) -> Option<ResourceUsage> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(who, &mut usage) } != 0 {
+        return None;
+    }
+    Some(resource_usage(&usage))
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn resource_usage(usage: &libc::rusage) -> ResourceUsage {
+    // ru_maxrss is reported in bytes on macOS and kilobytes elsewhere
+    #[cfg(target_os = "macos")]
+    let max_rss_kb = usage.ru_maxrss as i64 / 1024;
+    #[cfg(not(target_os = "macos"))]
+    let max_rss_kb = usage.ru_maxrss as i64;
+
+    ResourceUsage {
+        user_time_ms: timeval_to_ms(usage.ru_utime),
+        system_time_ms: timeval_to_ms(usage.ru_stime),
+        max_rss_kb,
+        page_faults: usage.ru_minflt as i64 + usage.ru_majflt as i64,
+        context_switches: usage.ru_nvcsw as i64 + usage.ru_nivcsw as i64,
+    }
+}
+
+/// Times and memory of the exited child, read through its still-open process handle.
+/// Windows doesn't count context switches per process, so that field stays 0.
+#[cfg(windows)]
+fn get_resource_usage(child: &Child) -> Option<ResourceUsage> {
+    use std::os::windows::io::AsRawHandle;
+    process_usage(child.as_raw_handle())
+}
+
+/// The driver's own resource usage so far, excluding its children.
+#[cfg(windows)]
+pub(crate) fn driver_resource_usage() -> Option<ResourceUsage> {
+    process_usage(unsafe { windows_sys::Win32::System::Threading::GetCurrentProcess() })
+}
+
+#[cfg(windows)]
+fn process_usage(handle: windows_sys::Win32::Foundation::HANDLE) -> Option<ResourceUsage> {
+    use windows_sys::Win32::Foundation::FILETIME;
+    use windows_sys::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+    use windows_sys::Win32::System::Threading::GetProcessTimes;
+
+    let (mut creation, mut exit, mut kernel, mut user) =
+        (FILETIME::default(), FILETIME::default(), FILETIME::default(), FILETIME::default());
+    if unsafe { GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) } == 0 {
+        return None;
+    }
+
+    let mut counters = PROCESS_MEMORY_COUNTERS {
+        cb: std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        ..Default::default()
+    };
+    if unsafe { GetProcessMemoryInfo(handle, &mut counters, counters.cb) } == 0 {
+        counters = PROCESS_MEMORY_COUNTERS::default();
+    }
+
+    Some(ResourceUsage {
+        user_time_ms: filetime_to_ms(user),
+        system_time_ms: filetime_to_ms(kernel),
+        max_rss_kb: (counters.PeakWorkingSetSize / 1024) as i64,
+        page_faults: i64::from(counters.PageFaultCount),
+        context_switches: 0,
+    })
+}
+
+/// FILETIME durations count 100ns intervals.
+#[cfg(windows)]
+fn filetime_to_ms(time: windows_sys::Win32::Foundation::FILETIME) -> i64 {
+    let intervals = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+    (intervals / 10_000) as i64
+}
+
+#[cfg(not(any(unix, windows)))]
+fn get_resource_usage(_child: &Child) -> Option<ResourceUsage> {
+    None
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn driver_resource_usage() -> Option<ResourceUsage> {
+    None
+}
+
+/// Exit code and terminating signal of a finished child.
+///
+/// Shell convention: a signal-killed child is reported as 128 + signum.
+/// -1 is reserved for a status that carries neither code nor signal.
+pub(crate) fn exit_code_and_signal(status: &ExitStatus) -> (i32, Option<i32>) {
+    let signal = exit_signal(status);
+    let exit_code = match (status.code(), signal) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        (None, None) => -1,
+    };
+    (exit_code, signal)
+}
+
+/// Signal that terminated the child, if it didn't exit normally.
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Whether the child's death produced a core dump (`WCOREDUMP`).
+#[cfg(unix)]
+pub(crate) fn core_dumped(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.core_dumped()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn core_dumped(_status: &ExitStatus) -> bool {
+    false
+}
+
+/// PID of the process that launched the driver, so nested invocations can be stitched together.
+#[cfg(unix)]
+pub(crate) fn get_parent_pid() -> i32 {
+    unsafe { libc::getppid() }
+}
+
+#[cfg(windows)]
+pub(crate) fn get_parent_pid() -> i32 {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32First, Process32Next, PROCESSENTRY32, TH32CS_SNAPPROCESS,
+    };
+
+    let own_pid = std::process::id();
+    let mut ppid = 0;
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return 0;
+        }
+        let mut entry: PROCESSENTRY32 = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32>() as u32;
+        let mut found = Process32First(snapshot, &mut entry) != 0;
+        while found {
+            if entry.th32ProcessID == own_pid {
+                ppid = entry.th32ParentProcessID as i32;
+                break;
+            }
+            found = Process32Next(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+    }
+    ppid
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn get_parent_pid() -> i32 {
+    0
+}
+
+/// Name of the machine the driver runs on.
+#[cfg(unix)]
+pub(crate) fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[cfg(windows)]
+pub(crate) fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn hostname() -> Option<String> {
+    None
+}
+
+/// Docker/containerd id of the container the driver runs in: a 64-hex-digit
+/// path segment in `/proc/self/cgroup`, or under `containers/` in
+/// `/proc/self/mountinfo` for cgroup v2 namespaces that hide the path.
+#[cfg(target_os = "linux")]
+pub(crate) fn container_id() -> Option<String> {
+    let is_id = |segment: &&str| segment.len() == 64 && segment.bytes().all(|b| b.is_ascii_hexdigit());
+
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    let from_cgroup = cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.split(['/', '-', '.', ':']))
+        .find(is_id);
+    if let Some(id) = from_cgroup {
+        return Some(id.to_string());
+    }
+
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    mountinfo
+        .split_whitespace()
+        .filter_map(|field| field.split_once("containers/").map(|(_, rest)| rest))
+        .filter_map(|rest| rest.split('/').next())
+        .find(is_id)
+        .map(str::to_string)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn container_id() -> Option<String> {
+    None
+}
+
+/// State of the git checkout the driver runs in.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GitContext {
+    /// `None` on a detached HEAD.
+    pub(crate) branch: Option<String>,
+    /// `None` before the first commit.
+    pub(crate) sha: Option<String>,
+    /// Whether tracked files differ from HEAD; untracked files don't count.
+    pub(crate) dirty: bool,
+}
+
+/// The git checkout containing the working directory, or `None` outside one
+/// or without `git`. Looked up on the first call only, so every run in the
+/// process reports the same repository.
+pub(crate) fn git_context() -> Option<GitContext> {
+    static CONTEXT: OnceLock<Option<GitContext>> = OnceLock::new();
+
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    CONTEXT
+        .get_or_init(|| {
+            let status = git(&["status", "--porcelain", "--untracked-files=no"])?;
+            Some(GitContext {
+                branch: git(&["symbolic-ref", "--short", "-q", "HEAD"]).filter(|branch| !branch.is_empty()),
+                sha: git(&["rev-parse", "--verify", "-q", "HEAD"]).filter(|sha| !sha.is_empty()),
+                dirty: !status.is_empty(),
+            })
+        })
+        .clone()
+}
+
+/// Identity the driver, and so the child, runs under.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Credentials {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) euid: Option<u32>,
+    pub(crate) egid: Option<u32>,
+    pub(crate) umask: Option<String>,
+    pub(crate) user_sid: Option<String>,
+}
+
+#[cfg(unix)]
+pub(crate) fn credentials() -> Credentials {
+    unsafe {
+        Credentials {
+            uid: Some(libc::getuid()),
+            gid: Some(libc::getgid()),
+            euid: Some(libc::geteuid()),
+            egid: Some(libc::getegid()),
+            umask: Some(format!("{:04o}", current_umask())),
+            user_sid: None,
+        }
+    }
+}
+
+/// Read from procfs, since `umask(2)` can only report the mask by replacing it.
+#[cfg(target_os = "linux")]
+fn current_umask() -> u32 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("Umask:"))?;
+            u32::from_str_radix(line["Umask:".len()..].trim(), 8).ok()
+        })
+        .unwrap_or_else(swap_umask)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn current_umask() -> u32 {
+    swap_umask()
+}
+
+/// Sets a throwaway mask and puts the old one straight back.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn swap_umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0o022);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn credentials() -> Credentials {
+    use windows_sys::Win32::Foundation::{CloseHandle, LocalFree};
+    use windows_sys::Win32::Security::Authorization::ConvertSidToStringSidW;
+    use windows_sys::Win32::Security::{GetTokenInformation, TokenUser, TOKEN_QUERY, TOKEN_USER};
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return Credentials::default();
+        }
+        // u64 words keep the buffer aligned for TOKEN_USER
+        let mut buf = [0u64; 64];
+        let mut len = 0u32;
+        let size = std::mem::size_of_val(&buf) as u32;
+        let ok = GetTokenInformation(token, TokenUser, buf.as_mut_ptr().cast(), size, &mut len);
+        CloseHandle(token);
+        if ok == 0 {
+            return Credentials::default();
+        }
+        let user = &*(buf.as_ptr() as *const TOKEN_USER);
+        let mut sid = std::ptr::null_mut();
+        if ConvertSidToStringSidW(user.User.Sid, &mut sid) == 0 {
+            return Credentials::default();
+        }
+        let sid_len = (0..).take_while(|&i| *sid.add(i) != 0).count();
+        let user_sid = String::from_utf16_lossy(std::slice::from_raw_parts(sid, sid_len));
+        LocalFree(sid.cast());
+        Credentials { user_sid: Some(user_sid), ..Credentials::default() }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn credentials() -> Credentials {
+    Credentials::default()
+}
+
+/// Walks `/proc/<pid>/stat` from the driver's parent towards PID 1, stopping
+/// early at `max_depth` or at the first process we can't read.
+#[cfg(target_os = "linux")]
+pub(crate) fn process_ancestry(max_depth: usize) -> Vec<AncestorProcess> {
+    let mut ancestry = Vec::new();
+    let mut pid = get_parent_pid();
+
+    while pid > 0 && ancestry.len() < max_depth {
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            break;
+        };
+        let Some((comm, ppid)) = parse_proc_stat(&stat) else {
+            break;
+        };
+        ancestry.push(AncestorProcess { pid, comm });
+        pid = ppid;
+    }
+
+    ancestry
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn process_ancestry(_max_depth: usize) -> Vec<AncestorProcess> {
+    Vec::new()
+}
+
+/// Current resident set of `pid` from `/proc/<pid>/statm`, or `None` once it has exited.
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn current_rss_kb(pid: u32) -> Option<i64> {
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let resident_pages: i64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
+    Some(resident_pages * page_size / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current_rss_kb(_pid: u32) -> Option<i64> {
+    None
+}
+
+/// User plus system CPU time of `pid` in clock ticks, from `/proc/<pid>/stat`.
+#[cfg(target_os = "linux")]
+pub(crate) fn cpu_ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // After comm, utime and stime are the 12th and 13th fields
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn cpu_ticks(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Descriptors `pid` has open, from `/proc/<pid>/fd`; `None` once it has exited
+/// or if the directory isn't readable.
+#[cfg(target_os = "linux")]
+pub(crate) fn open_fd_count(pid: u32) -> Option<usize> {
+    Some(std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn open_fd_count(_pid: u32) -> Option<usize> {
+    None
+}
+
+/// Clock ticks per second, the unit of [`cpu_ticks`].
+#[cfg(target_os = "linux")]
+pub(crate) fn ticks_per_second() -> f64 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) as f64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn ticks_per_second() -> f64 {
+    100.0
+}
+
+/// Extracts `(comm, ppid)` from a `/proc/<pid>/stat` line. `comm` may itself
+/// contain spaces and parentheses, so it runs up to the last `)`.
+#[cfg(target_os = "linux")]
+fn parse_proc_stat(stat: &str) -> Option<(String, i32)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat.get(open + 1..close)?.to_string();
+    // After comm: state, then ppid
+    let ppid = stat.get(close + 1..)?.split_whitespace().nth(1)?.parse().ok()?;
+    Some((comm, ppid))
+}
+
+/// Replaces the driver with `command`, keeping its stdio. Only returns on failure.
+#[cfg(unix)]
+pub(crate) fn exec_command(command: &[String]) -> io::Result<i32> {
+    use std::os::unix::process::CommandExt;
+    Err(Command::new(&command[0]).args(&command[1..]).exec())
+}
+
+/// Runs `command` with inherited stdio and returns its exit code.
+#[cfg(not(unix))]
+pub(crate) fn exec_command(command: &[String]) -> io::Result<i32> {
+    let status = Command::new(&command[0]).args(&command[1..]).status()?;
+    Ok(exit_code_and_signal(&status).0)
+}
+
+/// Resolves `program` the way spawning it would: names containing a path
+/// separator are taken as paths, bare names are searched for on `path`, the
+/// `PATH` the child gets. The result is canonicalized so symlinked shims show
+/// their real target.
+pub(crate) fn resolve_executable(program: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        return Path::new(program).canonicalize().ok();
+    }
+
+    env::split_paths(path?)
+        .flat_map(|dir| executable_candidates(&dir, program))
+        .find(|candidate| is_executable(candidate))
+        .and_then(|found| found.canonicalize().ok())
+}
+
+#[cfg(unix)]
+fn executable_candidates(dir: &Path, program: &str) -> Vec<PathBuf> {
+    vec![dir.join(program)]
+}
+
+/// On Windows a bare name may omit its extension, so try each of `PATHEXT`.
+#[cfg(not(unix))]
+fn executable_candidates(dir: &Path, program: &str) -> Vec<PathBuf> {
+    let mut candidates = vec![dir.join(program)];
+    let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    for ext in extensions.split(';').filter(|e| !e.is_empty()) {
+        candidates.push(dir.join(format!("{}{}", program, ext)));
+    }
+    candidates
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,39 @@
+//! Running the child attached to a pseudo-terminal (`TELEMETRY_PTY`), so
+//! programs that check `isatty` keep their interactive formatting.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+/// Sent when the driver's own stdin ends: a terminal in canonical mode has no
+/// other way to signal EOF to the child (Ctrl-D).
+pub(crate) const PTY_EOF: u8 = 0x04;
+
+/// Attaches all three of `cmd`'s stdio streams to the slave side of a new PTY
+/// and returns the master side, which carries the combined output and accepts input.
+///
+/// The caller must drop `cmd` after spawning so its copies of the slave close;
+/// otherwise reads from the master never see the child hang up.
+pub(crate) fn attach_pty(cmd: &mut Command) -> io::Result<File> {
+    let pty = nix::pty::openpty(None, None).map_err(io::Error::from)?;
+    cmd.stdin(Stdio::from(pty.slave.try_clone()?));
+    cmd.stdout(Stdio::from(pty.slave.try_clone()?));
+    cmd.stderr(Stdio::from(pty.slave));
+
+    // Make the PTY the child's controlling terminal so job control and
+    // terminal signals behave as in a real session
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    Ok(File::from(pty.master))
+}
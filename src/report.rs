@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+use serde_json::Value;
+
+use crate::format::open_log;
+
+/// Rows shown per group in the printed report.
+const REPORT_TOP_N: usize = 10;
+
+/// Aggregates over the commands recorded in a JSONL log.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub total_commands: usize,
+    pub failures: usize,
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub total_bytes: u64,
+    /// Grouped by `command[0]`, most total time first.
+    pub by_program: Vec<GroupStats>,
+    /// Grouped by `session_id`, most total time first.
+    pub by_session: Vec<GroupStats>,
+}
+
+/// Totals for one program or session.
+#[derive(Debug, Clone, Default)]
+pub struct GroupStats {
+    pub key: String,
+    pub commands: usize,
+    pub failures: usize,
+    pub total_ms: u64,
+}
+
+impl Report {
+    /// Builds a report from a JSONL log, gzipped or not. Each `process_end`,
+    /// `process_complete`, `process_interrupted` or `process_spawn_error` event
+    /// counts as one command;
+    /// lines that aren't JSON are skipped.
+    pub fn from_reader(input: impl Read + 'static) -> io::Result<Self> {
+        let mut report = Report::default();
+        let mut durations = Vec::new();
+        let mut programs: HashMap<String, GroupStats> = HashMap::new();
+        let mut sessions: HashMap<String, GroupStats> = HashMap::new();
+
+        for line in open_log(input)?.lines() {
+            let Ok(event) = serde_json::from_str::<Value>(&line?) else {
+                continue;
+            };
+            let event_type = event["event_type"].as_str().unwrap_or_default();
+            if !matches!(event_type, "process_end" | "process_complete" | "process_interrupted" | "process_spawn_error") {
+                continue;
+            }
+
+            let failed = event["exit_code"].as_i64().is_none_or(|code| code != 0)
+                || event["timed_out"].as_bool().unwrap_or(false);
+            let duration_ms = event["duration_ms"].as_u64();
+            let bytes = event["stdout_size_bytes"].as_u64().unwrap_or(0)
+                + event["stderr_size_bytes"].as_u64().unwrap_or(0);
+
+            report.total_commands += 1;
+            report.failures += usize::from(failed);
+            report.total_bytes += bytes;
+            durations.extend(duration_ms);
+
+            let program = event["command"][0].as_str().unwrap_or("<none>").to_string();
+            let session = event["session_id"].as_str().unwrap_or("<none>").to_string();
+            for (groups, key) in [(&mut programs, program), (&mut sessions, session)] {
+                let group = groups.entry(key.clone()).or_insert_with(|| GroupStats { key, ..Default::default() });
+                group.commands += 1;
+                group.failures += usize::from(failed);
+                group.total_ms += duration_ms.unwrap_or(0);
+            }
+        }
+
+        durations.sort_unstable();
+        report.p50_ms = percentile(&durations, 50);
+        report.p95_ms = percentile(&durations, 95);
+        report.by_program = sorted_by_time(programs);
+        report.by_session = sorted_by_time(sessions);
+        Ok(report)
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.total_commands == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.total_commands as f64
+        }
+    }
+
+    /// Prints the report in human-readable form.
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        let ms = |value: Option<u64>| value.map_or("-".to_string(), |v| format!("{}ms", v));
+        writeln!(out, "Commands: {} ({} failed, {:.1}%)",
+            self.total_commands,
+            self.failures,
+            self.failure_rate() * 100.0)?;
+        writeln!(out, "Duration: p50 {} | p95 {}", ms(self.p50_ms), ms(self.p95_ms))?;
+        writeln!(out, "Captured: {} bytes", self.total_bytes)?;
+
+        for (title, groups) in [("Top commands by time", &self.by_program), ("Top sessions by time", &self.by_session)] {
+            writeln!(out, "\n{}:", title)?;
+            for group in groups.iter().take(REPORT_TOP_N) {
+                writeln!(out, "  {:<40} {:>6} runs {:>6} failed {:>10}ms",
+                    group.key,
+                    group.commands,
+                    group.failures,
+                    group.total_ms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Nearest-rank percentile of already sorted values.
+fn percentile(sorted: &[u64], pct: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+fn sorted_by_time(groups: HashMap<String, GroupStats>) -> Vec<GroupStats> {
+    let mut groups: Vec<GroupStats> = groups.into_values().collect();
+    groups.sort_by(|a, b| b.total_ms.cmp(&a.total_ms).then_with(|| a.key.cmp(&b.key)));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A synthetic log: two sessions, one of each command-ending event type, and lines to skip.
+    fn log() -> io::Cursor<String> {
+        use serde_json::json;
+
+        let lines = [
+            json!({"event_type": "process_start", "session_id": "s1", "command": ["make"]}).to_string(),
+            json!({"event_type": "process_end", "session_id": "s1", "command": ["make", "all"], "exit_code": 0,
+                "duration_ms": 100, "stdout_size_bytes": 10, "stderr_size_bytes": 5})
+            .to_string(),
+            json!({"event_type": "process_stats", "session_id": "s1", "command": ["make"], "duration_ms": 100})
+                .to_string(),
+            json!({"event_type": "process_end", "session_id": "s1", "command": ["make", "test"], "exit_code": 2,
+                "duration_ms": 300})
+            .to_string(),
+            "not json".to_string(),
+            json!({"event_type": "process_interrupted", "session_id": "s1", "command": ["cargo", "build"],
+                "exit_code": 0, "duration_ms": 50})
+            .to_string(),
+            json!({"event_type": "process_complete", "session_id": "s2", "command": ["cargo", "test"], "exit_code": 0,
+                "duration_ms": 20, "stdout_size_bytes": 7})
+            .to_string(),
+            json!({"event_type": "process_spawn_error", "session_id": "s2", "command": ["gcc"]}).to_string(),
+        ];
+        io::Cursor::new(lines.join("\n"))
+    }
+
+    fn group(key: &str, commands: usize, failures: usize, total_ms: u64) -> (String, usize, usize, u64) {
+        (key.to_string(), commands, failures, total_ms)
+    }
+
+    fn groups(stats: &[GroupStats]) -> Vec<(String, usize, usize, u64)> {
+        stats.iter().map(|g| (g.key.clone(), g.commands, g.failures, g.total_ms)).collect()
+    }
+
+    #[test]
+    fn aggregates_one_row_per_command() {
+        let report = Report::from_reader(log()).unwrap();
+        assert_eq!(report.total_commands, 5);
+        assert_eq!(report.failures, 2);
+        assert_eq!(report.failure_rate(), 0.4);
+        assert_eq!((report.p50_ms, report.p95_ms), (Some(50), Some(300)));
+        assert_eq!(report.total_bytes, 22);
+        assert_eq!(groups(&report.by_program), [group("make", 2, 1, 400), group("cargo", 2, 0, 70), group("gcc", 1, 1, 0)]);
+        assert_eq!(groups(&report.by_session), [group("s1", 3, 1, 450), group("s2", 2, 1, 20)]);
+    }
+
+    #[test]
+    fn empty_log_has_no_percentiles() {
+        let report = Report::from_reader(io::empty()).unwrap();
+        assert_eq!(report.total_commands, 0);
+        assert_eq!(report.failure_rate(), 0.0);
+        assert_eq!((report.p50_ms, report.p95_ms), (None, None));
+
+        let mut printed = Vec::new();
+        report.write_to(&mut printed).unwrap();
+        assert!(String::from_utf8(printed).unwrap().contains("p50 - | p95 -"));
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        assert_eq!(percentile(&[7], 50), Some(7));
+        assert_eq!(percentile(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 50), Some(5));
+        assert_eq!(percentile(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 95), Some(10));
+    }
+}
@@ -0,0 +1,73 @@
+use std::thread;
+use std::time::Duration;
+
+/// Retries used for network sinks when `TELEMETRY_SINK_RETRIES` is unset.
+pub const DEFAULT_SINK_RETRIES: u32 = 2;
+/// First backoff used when `TELEMETRY_SINK_BACKOFF_MS` is unset.
+pub const DEFAULT_SINK_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often, and how patiently, a failed network send is retried before the
+/// event falls back to the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first.
+    pub retries: u32,
+    /// Wait before the first retry, doubled before each one after it.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { retries: DEFAULT_SINK_RETRIES, backoff: DEFAULT_SINK_BACKOFF }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `attempt` until it succeeds or the retries run out, returning the last error.
+    pub(crate) fn run<T, E>(&self, mut attempt: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut backoff = self.backoff;
+        let mut retries_left = self.retries;
+        loop {
+            match attempt() {
+                Err(_) if retries_left > 0 => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    retries_left -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn retries_until_success_with_doubling_backoff() {
+        let policy = RetryPolicy { retries: 3, backoff: Duration::from_millis(20) };
+        let mut attempts = 0;
+        let started = Instant::now();
+        let result = policy.run(|| {
+            attempts += 1;
+            if attempts < 3 { Err(attempts) } else { Ok(attempts) }
+        });
+        assert_eq!(result, Ok(3));
+        // 20ms then 40ms
+        assert!(started.elapsed() >= Duration::from_millis(60), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn gives_up_with_the_last_error() {
+        let policy = RetryPolicy { retries: 2, backoff: Duration::ZERO };
+        let mut attempts = 0;
+        let result: Result<(), u32> = policy.run(|| {
+            attempts += 1;
+            Err(attempts)
+        });
+        assert_eq!(result, Err(3));
+    }
+}
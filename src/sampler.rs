@@ -0,0 +1,50 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::event::ResourceSample;
+use crate::process::{cpu_ticks, current_rss_kb, open_fd_count, ticks_per_second};
+
+/// Thread reading the child's RSS, CPU time and open descriptors at a fixed interval (Linux only;
+/// elsewhere no samples are taken).
+pub(crate) struct ResourceSampler {
+    stop: Sender<()>,
+    handle: JoinHandle<Vec<ResourceSample>>,
+}
+
+impl ResourceSampler {
+    pub(crate) fn start(interval: Duration, child_pid: u32, started: Instant) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let ticks_per_second = ticks_per_second();
+            let mut samples = Vec::new();
+            let mut previous = (Instant::now(), cpu_ticks(child_pid).unwrap_or(0));
+
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // Either read fails once the child has exited and been reaped; before
+                // that, an exited child's memory reads as zero
+                let (Some(rss_kb @ 1..), Some(ticks)) = (current_rss_kb(child_pid), cpu_ticks(child_pid)) else {
+                    break;
+                };
+                let now = Instant::now();
+                let elapsed = now.duration_since(previous.0).as_secs_f64();
+                let cpu_seconds = ticks.saturating_sub(previous.1) as f64 / ticks_per_second;
+                samples.push(ResourceSample {
+                    ts: now.duration_since(started).as_secs_f64() * 1000.0,
+                    rss_kb,
+                    cpu_pct: if elapsed > 0.0 { cpu_seconds / elapsed * 100.0 } else { 0.0 },
+                    open_fds: open_fd_count(child_pid),
+                });
+                previous = (now, ticks);
+            }
+            samples
+        });
+        ResourceSampler { stop, handle }
+    }
+
+    /// Stops sampling and returns what was collected.
+    pub(crate) fn stop(self) -> Vec<ResourceSample> {
+        let _ = self.stop.send(());
+        self.handle.join().unwrap_or_default()
+    }
+}
@@ -0,0 +1,99 @@
+//! Where `Config` settings come from: the `TELEMETRY_*` environment, a TOML
+//! config file, or neither. A file key is the variable's name without the
+//! `TELEMETRY_` prefix, lowercased:
+//!
+//! ```toml
+//! format = "csv"
+//! timeout_ms = 5000
+//! env_redact = ["*_PASSWORD", "INTERNAL_*"]
+//! tags = { team = "infra" }
+//! ```
+//!
+//! Environment variables win over the file.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env::{self, VarError};
+use std::fs;
+use std::io;
+
+/// Path of the config file, from the environment only.
+pub const CONFIG_FILE_ENV: &str = "TELEMETRY_CONFIG";
+
+const PREFIX: &str = "TELEMETRY_";
+
+/// Settings lookups for building a [`Config`](crate::Config).
+pub(crate) struct Settings {
+    use_env: bool,
+    /// File values keyed by the environment variable they stand in for.
+    file: HashMap<String, String>,
+    /// Names looked up so far, to spot misspelled file keys.
+    seen: RefCell<HashSet<String>>,
+}
+
+impl Settings {
+    /// Built-in defaults only.
+    pub(crate) fn none() -> Self {
+        Settings { use_env: false, file: HashMap::new(), seen: RefCell::default() }
+    }
+
+    /// The environment alone.
+    pub(crate) fn env() -> Self {
+        Settings { use_env: true, ..Settings::none() }
+    }
+
+    /// Loads `path`, optionally with the environment layered on top.
+    pub(crate) fn from_file(path: &str, use_env: bool) -> io::Result<Self> {
+        let table: toml::Table = fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let file = table
+            .into_iter()
+            .map(|(key, value)| (format!("{}{}", PREFIX, key.to_ascii_uppercase()), setting_string(value)))
+            .collect();
+        Ok(Settings { use_env, file, seen: RefCell::default() })
+    }
+
+    /// Same contract as [`env::var`], with the file as the fallback.
+    pub(crate) fn var(&self, name: &str) -> Result<String, VarError> {
+        self.seen.borrow_mut().insert(name.to_string());
+        if self.use_env {
+            match env::var(name) {
+                Err(VarError::NotPresent) => {}
+                found => return found,
+            }
+        }
+        self.file.get(name).cloned().ok_or(VarError::NotPresent)
+    }
+
+    /// True when a boolean switch is set to `1`, `true` or `yes`.
+    pub(crate) fn flag(&self, name: &str) -> bool {
+        self.var(name).is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+    }
+
+    /// True when a default-on switch is set to `0`, `false` or `no`.
+    pub(crate) fn flag_off(&self, name: &str) -> bool {
+        self.var(name).is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "0" | "false" | "no"))
+    }
+
+    /// Warns about file keys no setting asked for.
+    pub(crate) fn warn_unknown(&self, path: &str) {
+        let seen = self.seen.borrow();
+        let mut unknown: Vec<&String> = self.file.keys().filter(|name| !seen.contains(*name)).collect();
+        unknown.sort();
+        for name in unknown {
+            eprintln!("⚠️ Unknown setting {:?} in {}", name[PREFIX.len()..].to_ascii_lowercase(), path);
+        }
+    }
+}
+
+/// A file value in the form the matching variable takes: lists are
+/// comma-joined and tables become JSON objects, as `TELEMETRY_TAGS` accepts.
+fn setting_string(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Array(items) => items.into_iter().map(setting_string).collect::<Vec<_>>().join(","),
+        toml::Value::Table(table) => serde_json::to_string(&table).unwrap_or_default(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,160 @@
+//! Forwarding SIGINT and SIGTERM received by the driver to the child, so a
+//! cancelled driver still reaps its child and logs what it captured. Only
+//! with [`Config::forward_signals`](crate::Config::forward_signals), since the
+//! handlers are the whole process's; the binary sets it, library callers opt in.
+//!
+//! Process groups: the child leads a process group of its own (a new session
+//! under a PTY), so a timeout can kill everything it started. Every signal is
+//! forwarded to the child's whole group; the group is killed if the child
+//! outlives the grace period. With a terminal on stdin the child's group is
+//! also given the terminal's foreground, so Ctrl-C and Ctrl-Z reach it
+//! directly, and the driver follows it into a job control stop.
+
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a child gets to exit after a forwarded signal before it is killed.
+const INTERRUPT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// How often the escalation thread checks whether a signal has arrived.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Child the handler forwards to; 0 when no forwarder is installed.
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+/// Last signal the handler received; 0 for none.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+/// Whether the handler passed a signal on to the child.
+static FORWARDED: AtomicBool = AtomicBool::new(false);
+/// Set while the driver is stopped along with the child, which it continues itself once resumed.
+#[cfg(unix)]
+static FOLLOWING_STOP: AtomicBool = AtomicBool::new(false);
+
+/// Installed for the lifetime of one child. The SIGINT and SIGTERM handlers
+/// are one-shot (`SA_RESETHAND`), so a second Ctrl-C terminates the driver as
+/// usual; SIGTSTP and SIGCONT are passed on for as long as the child runs.
+pub(crate) struct SignalForwarder {
+    stop: Sender<()>,
+    escalation: Option<JoinHandle<()>>,
+    #[cfg(unix)]
+    previous: Vec<(nix::sys::signal::Signal, nix::sys::signal::SigAction)>,
+}
+
+impl SignalForwarder {
+    /// `child_pid` must lead its own process group; see [`own_process_group`](crate::process::own_process_group).
+    pub(crate) fn install(child_pid: u32) -> Self {
+        CHILD_PID.store(child_pid as i32, Ordering::SeqCst);
+        RECEIVED.store(0, Ordering::SeqCst);
+        FORWARDED.store(false, Ordering::SeqCst);
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let escalation = thread::spawn(move || loop {
+            match stopped.recv_timeout(INTERRUPT_POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) if FORWARDED.load(Ordering::SeqCst) => {
+                    if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERRUPT_GRACE_PERIOD) {
+                        kill_child();
+                    }
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        });
+
+        SignalForwarder {
+            stop,
+            escalation: Some(escalation),
+            #[cfg(unix)]
+            previous: install_handlers(),
+        }
+    }
+
+    /// The signal the driver received while the child ran, if any.
+    pub(crate) fn received(&self) -> Option<i32> {
+        Some(RECEIVED.load(Ordering::SeqCst)).filter(|&sig| sig != 0)
+    }
+}
+
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for (signal, action) in self.previous.drain(..) {
+            let _ = unsafe { nix::sys::signal::sigaction(signal, &action) };
+        }
+        CHILD_PID.store(0, Ordering::SeqCst);
+        let _ = self.stop.send(());
+        if let Some(escalation) = self.escalation.take() {
+            let _ = escalation.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int, _info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    // Only async-signal-safe work here: atomics and killpg(2)
+    RECEIVED.store(signal, Ordering::SeqCst);
+
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe { libc::killpg(pid, signal) };
+        FORWARDED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Passes a stop or continue on to the child's group without counting it as
+/// an interruption. A continue is held back while the driver follows a stop,
+/// so the child only resumes once it has the terminal again.
+#[cfg(unix)]
+extern "C" fn forward_job_signal(signal: libc::c_int) {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 && !(signal == libc::SIGCONT && FOLLOWING_STOP.load(Ordering::SeqCst)) {
+        unsafe { libc::killpg(pid, signal) };
+    }
+}
+
+#[cfg(unix)]
+fn install_handlers() -> Vec<(nix::sys::signal::Signal, nix::sys::signal::SigAction)> {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+    let interrupt = SigAction::new(
+        SigHandler::SigAction(forward_signal),
+        SaFlags::SA_SIGINFO | SaFlags::SA_RESETHAND | SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    let job = SigAction::new(SigHandler::Handler(forward_job_signal), SaFlags::SA_RESTART, SigSet::empty());
+    [(Signal::SIGINT, &interrupt), (Signal::SIGTERM, &interrupt), (Signal::SIGTSTP, &job), (Signal::SIGCONT, &job)]
+        .into_iter()
+        .filter_map(|(signal, action)| unsafe { sigaction(signal, action) }.ok().map(|previous| (signal, previous)))
+        .collect()
+}
+
+/// Follows the child into a job control stop (Ctrl-Z, or using the terminal from the
+/// background), so the shell sees the job stop and can resume it: the terminal is taken
+/// back, the driver stops, and once the driver is continued the terminal goes back to
+/// the child and its group is continued too.
+#[cfg(unix)]
+pub(crate) fn follow_stop(child_pid: u32, foreground: Option<&crate::process::Foreground>) {
+    FOLLOWING_STOP.store(true, Ordering::SeqCst);
+    if let Some(foreground) = foreground {
+        foreground.take_back();
+    }
+    // SIGSTOP, since SIGTSTP may be the forwarder's to catch
+    unsafe { libc::raise(libc::SIGSTOP) };
+    if let Some(foreground) = foreground {
+        foreground.resume();
+    }
+    unsafe { libc::killpg(child_pid as libc::pid_t, libc::SIGCONT) };
+    FOLLOWING_STOP.store(false, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn kill_child() {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe { libc::killpg(pid, libc::SIGKILL) };
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_child() {}
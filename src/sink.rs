@@ -0,0 +1,774 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::Utc;
+
+use crate::event::{DriverError, TelemetryEvent};
+use crate::event_filter::filter_event;
+use crate::event_trim::trim_event;
+use crate::format::{csv_header, render_event, JsonCase, OutputFormat};
+use crate::http::post_events;
+use crate::kafka;
+use crate::process::hostname;
+use crate::retry::RetryPolicy;
+use crate::socket::send_event;
+use crate::syslog;
+use crate::{unix_timestamp, Config, DRIVER_VERSION};
+
+enum SinkBuffer {
+    Plain(Vec<u8>),
+    Gzip(GzEncoder<Vec<u8>>),
+}
+
+impl SinkBuffer {
+    fn new(gzip: bool) -> Self {
+        if gzip {
+            SinkBuffer::Gzip(GzEncoder::new(Vec::new(), Compression::default()))
+        } else {
+            SinkBuffer::Plain(Vec::new())
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            SinkBuffer::Plain(w) => w,
+            SinkBuffer::Gzip(w) => w,
+        }
+    }
+
+    fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            SinkBuffer::Plain(bytes) => Ok(bytes),
+            SinkBuffer::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// The telemetry log file, opened once per run.
+///
+/// Events are rendered into memory and appended with a single `write_all`
+/// while holding an exclusive advisory lock on the file (`flock` on Unix,
+/// `LockFileEx` on Windows). Drivers sharing a log therefore never interleave
+/// partial lines: each run's events land as one contiguous block.
+pub(crate) struct TelemetrySink {
+    file: File,
+    path: String,
+    max_log_bytes: Option<u64>,
+    log_keep: usize,
+    buffer: SinkBuffer,
+    format: OutputFormat,
+    json_case: JsonCase,
+    gzip: bool,
+    /// Header row for a CSV log, written only if the file is still empty at flush time.
+    csv_header: Option<String>,
+}
+
+impl TelemetrySink {
+    /// Opens `config.telemetry_file` for appending, so concurrent drivers don't clobber each other.
+    pub(crate) fn open(config: &Config) -> io::Result<Self> {
+        if let Some(parent) = Path::new(&config.telemetry_file).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(&config.telemetry_file)?;
+        Ok(TelemetrySink {
+            file,
+            path: config.telemetry_file.clone(),
+            max_log_bytes: config.max_log_bytes,
+            log_keep: config.log_keep,
+            buffer: SinkBuffer::new(config.gzips_log()),
+            format: config.format,
+            json_case: config.json_case,
+            gzip: config.gzips_log(),
+            csv_header: None,
+        })
+    }
+
+    pub(crate) fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        if self.format == OutputFormat::Csv && self.csv_header.is_none() {
+            self.csv_header = Some(csv_header(event, self.json_case).map_err(io::Error::other)?);
+        }
+
+        let record = render_event(event, self.format, self.json_case)?;
+        self.buffer.writer().write_all(&record)
+    }
+
+    /// Appends everything buffered so far under the file lock, rotating first
+    /// if the append would push the log past its size limit.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        let body = std::mem::replace(&mut self.buffer, SinkBuffer::Plain(Vec::new())).into_bytes()?;
+
+        self.lock_current()?;
+        let result = self.append_locked(&body);
+        let _ = self.file.unlock();
+        result
+    }
+
+    /// Locks the file, reopening it first if another driver rotated it away
+    /// while we were waiting for the lock.
+    fn lock_current(&mut self) -> io::Result<()> {
+        loop {
+            self.file.lock()?;
+            if is_same_file(&self.file, &self.path) {
+                return Ok(());
+            }
+            let _ = self.file.unlock();
+            self.file = open_append(&self.path)?;
+        }
+    }
+
+    fn append_locked(&mut self, body: &[u8]) -> io::Result<()> {
+        if let Some(max_bytes) = self.max_log_bytes {
+            let len = self.file.metadata()?.len();
+            if len > 0 && len + body.len() as u64 > max_bytes {
+                rotate(&self.path, self.log_keep)?;
+                // Keep the old (now rotated) file locked until the new one is ours
+                let file = open_append(&self.path)?;
+                file.lock()?;
+                let _ = std::mem::replace(&mut self.file, file).unlock();
+            }
+        }
+
+        // Checked under the lock so only the first writer of a new log adds the header
+        if let Some(header) = &self.csv_header {
+            if self.file.metadata()?.len() == 0 {
+                let mut header_buffer = SinkBuffer::new(self.gzip);
+                header_buffer.writer().write_all(header.as_bytes())?;
+                (&self.file).write_all(&header_buffer.into_bytes()?)?;
+            }
+        }
+        // Each run appends its own gzip member; readers treat them as one stream
+        (&self.file).write_all(body)
+    }
+}
+
+/// Substitutes `{pid}`, `{session}`, `{date}` (UTC, `YYYY-MM-DD`) and `{hostname}` in a log path.
+pub(crate) fn expand_log_path(template: &str, session_id: &str) -> String {
+    if !template.contains('{') {
+        return template.to_string();
+    }
+    template
+        .replace("{pid}", &std::process::id().to_string())
+        .replace("{session}", session_id)
+        .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string())
+        .replace("{hostname}", &hostname().unwrap_or_else(|| "unknown".to_string()))
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Shifts `path` to `path.1`, `path.1` to `path.2` and so on, dropping
+/// generations beyond `keep`.
+fn rotate(path: &str, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    let _ = fs::remove_file(format!("{}.{}", path, keep));
+    for generation in (1..keep).rev() {
+        let _ = fs::rename(format!("{}.{}", path, generation), format!("{}.{}", path, generation + 1));
+    }
+    fs::rename(path, format!("{}.1", path))
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// Windows refuses to rename a file another process has open, so the handle can't go stale
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, _path: &str) -> bool {
+    true
+}
+
+/// Marks event lines on a standard stream, where the child's replayed output also goes.
+pub const DEFAULT_STREAM_PREFIX: &str = "@telemetry ";
+
+/// A standard stream for events, for containers with nowhere to write a log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    /// Parses a `TELEMETRY_SINK` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "stdout" => Some(LogStream::Stdout),
+            "stderr" => Some(LogStream::Stderr),
+            _ => None,
+        }
+    }
+
+    /// The stream a log path stands for: `-` or `/dev/stdout`, or `/dev/stderr`.
+    pub fn for_path(path: &str) -> Option<Self> {
+        match path {
+            "-" | "/dev/stdout" => Some(LogStream::Stdout),
+            "/dev/stderr" => Some(LogStream::Stderr),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// A destination for events. Failures are reported, not handled: wrapping a
+/// sink in [`RetrySink`] or [`FallbackSink`] decides what happens next.
+pub(crate) trait Sink: Send {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()>;
+
+    /// Delivers anything buffered so far; the sink stays usable afterwards.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Events accepted by `write_event` that a failed `flush` couldn't deliver.
+    fn take_undelivered(&mut self) -> Vec<TelemetryEvent> {
+        Vec::new()
+    }
+}
+
+/// The telemetry log file, opened on first use so a run that only talks to the
+/// network never touches it.
+pub(crate) struct FileSink<'a> {
+    config: &'a Config,
+    file: Option<TelemetrySink>,
+}
+
+impl<'a> FileSink<'a> {
+    pub(crate) fn new(config: &'a Config) -> Self {
+        FileSink { config, file: None }
+    }
+}
+
+impl Sink for FileSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(TelemetrySink::open(self.config)?),
+        };
+        file.write_event(event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.take().map_or(Ok(()), TelemetrySink::finish)
+    }
+}
+
+/// Writes events to stdout or stderr, each line behind `config.stream_prefix`
+/// (msgpack records excepted). Events are buffered and written in one locked
+/// `write_all` per flush, so they never split a line of the child's output
+/// that is echoed to the same stream. Gzip doesn't apply here.
+pub(crate) struct StreamSink<'a> {
+    stream: LogStream,
+    config: &'a Config,
+    buffer: Vec<u8>,
+    wrote_header: bool,
+}
+
+impl<'a> StreamSink<'a> {
+    pub(crate) fn new(stream: LogStream, config: &'a Config) -> Self {
+        StreamSink { stream, config, buffer: Vec::new(), wrote_header: false }
+    }
+
+    fn push_prefixed(&mut self, text: &[u8]) {
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            self.buffer.extend_from_slice(self.config.stream_prefix.as_bytes());
+            self.buffer.extend_from_slice(line);
+        }
+    }
+}
+
+impl Sink for StreamSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        let (format, case) = (self.config.format, self.config.json_case);
+        if format == OutputFormat::Csv && !self.wrote_header {
+            let header = csv_header(event, case).map_err(io::Error::other)?;
+            self.push_prefixed(header.as_bytes());
+            self.wrote_header = true;
+        }
+
+        let record = render_event(event, format, case)?;
+        if format == OutputFormat::Msgpack {
+            self.buffer.extend_from_slice(&record);
+        } else {
+            self.push_prefixed(&record);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let body = std::mem::take(&mut self.buffer);
+        match self.stream {
+            LogStream::Stdout => {
+                let mut out = io::stdout().lock();
+                out.write_all(&body).and_then(|()| out.flush())
+            }
+            LogStream::Stderr => io::stderr().lock().write_all(&body),
+        }
+    }
+}
+
+/// The stream `config` names, else the log file.
+fn local_sink(config: &Config) -> Box<dyn Sink + '_> {
+    match config.log_stream {
+        Some(stream) => Box::new(StreamSink::new(stream, config)),
+        None => Box::new(FileSink::new(config)),
+    }
+}
+
+/// POSTs the events buffered since the last flush as one batch.
+pub(crate) struct HttpSink<'a> {
+    endpoint: &'a str,
+    header: Option<&'a (String, String)>,
+    case: JsonCase,
+    pending: Vec<TelemetryEvent>,
+}
+
+impl Sink for HttpSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        self.pending.push(event.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        post_events(self.endpoint, self.header, &self.pending, self.case).map_err(io::Error::other)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn take_undelivered(&mut self) -> Vec<TelemetryEvent> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Sends each event as a JSON line to a Unix socket.
+pub(crate) struct SocketSink<'a> {
+    path: &'a str,
+    case: JsonCase,
+}
+
+impl Sink for SocketSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        send_event(self.path, event, self.case)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Produces each event to a Kafka topic. Events are kept until a flush
+/// confirms their delivery; the next flush produces failed ones again.
+pub(crate) struct KafkaSink<'a> {
+    brokers: &'a str,
+    topic: &'a str,
+    case: JsonCase,
+    /// Produced events awaiting confirmation, by message id.
+    pending: Vec<(usize, TelemetryEvent)>,
+    /// Events whose delivery failed, to be produced again.
+    failed: Vec<TelemetryEvent>,
+}
+
+impl Sink for KafkaSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        let id = kafka::send_event(self.brokers, self.topic, event, self.case)?;
+        self.pending.push((id, event.clone()));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let retried = std::mem::take(&mut self.failed);
+        for (i, event) in retried.iter().enumerate() {
+            match kafka::send_event(self.brokers, self.topic, event, self.case) {
+                Ok(id) => self.pending.push((id, event.clone())),
+                Err(e) => {
+                    self.failed = retried[i..].to_vec();
+                    return Err(e);
+                }
+            }
+        }
+
+        let sent: Vec<usize> = self.pending.iter().map(|(id, _)| *id).collect();
+        let result = kafka::flush(&sent);
+        let pending = std::mem::take(&mut self.pending);
+        result.map_err(|(e, undelivered)| {
+            self.failed = pending
+                .into_iter()
+                .filter(|(id, _)| undelivered.contains(id))
+                .map(|(_, event)| event)
+                .collect();
+            e
+        })
+    }
+
+    /// Failed events, and those produced since the last flush, which no flush will now confirm.
+    fn take_undelivered(&mut self) -> Vec<TelemetryEvent> {
+        let mut undelivered = std::mem::take(&mut self.failed);
+        undelivered.extend(self.pending.drain(..).map(|(_, event)| event));
+        undelivered
+    }
+}
+
+/// Sends each event to the local syslog.
+pub(crate) struct SyslogSink<'a> {
+    config: &'a Config,
+}
+
+impl Sink for SyslogSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        let config = self.config;
+        syslog::send_event(event, config.syslog_facility, config.syslog_socket.as_deref(), config.json_case)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every event to each of its sinks in turn; one failing doesn't keep
+/// the event from the others. The first error is returned.
+pub(crate) struct FanOutSink<'a>(pub(crate) Vec<Box<dyn Sink + 'a>>);
+
+impl Sink for FanOutSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        self.0.iter_mut().map(|sink| sink.write_event(event)).fold(Ok(()), Result::and)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.iter_mut().map(|sink| sink.flush()).fold(Ok(()), Result::and)
+    }
+}
+
+/// Retries the wrapped sink's failed writes and flushes under a [`RetryPolicy`].
+pub(crate) struct RetrySink<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S: Sink> Sink for RetrySink<S> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        self.policy.run(|| self.inner.write_event(event))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.policy.run(|| self.inner.flush())
+    }
+
+    fn take_undelivered(&mut self) -> Vec<TelemetryEvent> {
+        self.inner.take_undelivered()
+    }
+}
+
+/// Uses `primary` until it fails, then writes that event, any undelivered
+/// ones, and everything after to the local log instead.
+pub(crate) struct FallbackSink<'a> {
+    primary: Box<dyn Sink + 'a>,
+    /// How the primary is named in the warning.
+    name: String,
+    fallback: Box<dyn Sink + 'a>,
+    fallback_name: &'a str,
+    failed: bool,
+}
+
+impl<'a> FallbackSink<'a> {
+    pub(crate) fn new(primary: impl Sink + 'a, name: String, config: &'a Config) -> Self {
+        FallbackSink {
+            primary: Box::new(primary),
+            name,
+            fallback: local_sink(config),
+            fallback_name: config.log_destination(),
+            failed: false,
+        }
+    }
+
+    fn fail_over(&mut self, error: io::Error) -> io::Result<()> {
+        eprintln!("⚠️ Failed to send telemetry to {}: {}; writing to {}", self.name, error, self.fallback_name);
+        self.failed = true;
+        self.primary
+            .take_undelivered()
+            .iter()
+            .map(|event| self.fallback.write_event(event))
+            .fold(Ok(()), Result::and)
+    }
+}
+
+impl Sink for FallbackSink<'_> {
+    fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+        if !self.failed {
+            match self.primary.write_event(event) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.fail_over(e)?,
+            }
+        }
+        self.fallback.write_event(event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.failed {
+            if let Err(e) = self.primary.flush() {
+                self.fail_over(e)?;
+            }
+        }
+        self.fallback.flush()
+    }
+}
+
+/// The sinks `config` asks for: at most one of HTTP, Kafka or the socket
+/// (in that order of preference, each retried and backed by the local log),
+/// else the local log, plus syslog alongside.
+fn sink_for(config: &Config) -> Box<dyn Sink + '_> {
+    let (case, policy) = (config.json_case, config.sink_retry);
+    let primary: Box<dyn Sink> = if let Some(endpoint) = &config.http_endpoint {
+        let http = HttpSink { endpoint, header: config.http_header.as_ref(), case, pending: Vec::new() };
+        Box::new(FallbackSink::new(RetrySink { inner: http, policy }, endpoint.clone(), config))
+    } else if let Some((brokers, topic)) = &config.kafka {
+        let kafka = KafkaSink { brokers, topic, case, pending: Vec::new(), failed: Vec::new() };
+        Box::new(FallbackSink::new(RetrySink { inner: kafka, policy }, format!("Kafka topic {}", topic), config))
+    } else if let Some(path) = &config.socket {
+        let socket = SocketSink { path, case };
+        Box::new(FallbackSink::new(RetrySink { inner: socket, policy }, path.clone(), config))
+    } else {
+        local_sink(config)
+    };
+
+    if config.syslog {
+        Box::new(FanOutSink(vec![Box::new(SyslogSink { config }), primary]))
+    } else {
+        primary
+    }
+}
+
+/// Routes a run's events to the sinks its config selects, holding them back
+/// under `only_failures`. Write errors are not the run's problem; they are
+/// reported as [`DriverError`]s and the run carries on.
+pub(crate) struct EventLog<'a> {
+    config: &'a Config,
+    /// Events kept back under `only_failures` until the outcome is known.
+    held: Vec<TelemetryEvent>,
+    sink: Box<dyn Sink + 'a>,
+}
+
+impl<'a> EventLog<'a> {
+    pub(crate) fn new(config: &'a Config) -> Self {
+        EventLog { config, held: Vec::new(), sink: sink_for(config) }
+    }
+
+    pub(crate) fn log(&mut self, event: &TelemetryEvent) {
+        let timeout = self.config.event_filter_timeout;
+        let filtered = self.config.event_filter.as_ref().map(|filter| filter_event(filter, timeout, event));
+        let event = filtered.as_ref().unwrap_or(event);
+        let trimmed = self.config.max_event_bytes.and_then(|max| {
+            trim_event(event, max, self.config.format, self.config.json_case)
+        });
+        let event = trimmed.as_ref().unwrap_or(event);
+        if self.config.only_failures {
+            self.held.push(event.clone());
+        } else {
+            self.write(event);
+        }
+    }
+
+    /// Passes held events on to the sinks; anything still held at `finish` is dropped.
+    pub(crate) fn release_held(&mut self) {
+        for event in std::mem::take(&mut self.held) {
+            self.write(&event);
+        }
+    }
+
+    /// Hands over the events held so far, leaving none behind.
+    pub(crate) fn take_held(&mut self) -> Vec<TelemetryEvent> {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Holds events another log already filtered, as if they had been logged here.
+    pub(crate) fn hold(&mut self, events: Vec<TelemetryEvent>) {
+        self.held.extend(events);
+    }
+
+    fn write(&mut self, event: &TelemetryEvent) {
+        if let Err(e) = self.sink.write_event(event) {
+            let message = format!("{} event not logged: {}", event.event_type, e);
+            report_driver_error(&self.config.session_id, "sink_write", message);
+        }
+    }
+
+    /// Delivers everything logged so far, falling back to the file for
+    /// anything a network sink couldn't take.
+    pub(crate) fn finish(mut self) {
+        self.flush();
+    }
+
+    /// Like `finish`, but the log stays usable; the next event opens a fresh batch.
+    pub(crate) fn flush(&mut self) {
+        if let Err(e) = self.sink.flush() {
+            report_driver_error(&self.config.session_id, "sink_flush", e.to_string());
+        }
+    }
+}
+
+/// Writes a [`DriverError`] to stderr as one JSON line.
+pub(crate) fn report_driver_error(session_id: &str, category: &'static str, message: String) {
+    let error = DriverError {
+        event_type: "driver_error",
+        driver_version: DRIVER_VERSION,
+        timestamp: unix_timestamp(SystemTime::now()),
+        session_id: session_id.to_string(),
+        category,
+        message,
+    };
+    if let Ok(json) = serde_json::to_string(&error) {
+        let _ = writeln!(io::stderr().lock(), "{}", json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// What a [`MemorySink`] was given, shared with the test that owns it.
+    #[derive(Default)]
+    struct Received {
+        written: Vec<String>,
+        flushed: Vec<String>,
+    }
+
+    /// Keeps event types in memory, optionally failing every write.
+    struct MemorySink {
+        received: Arc<Mutex<Received>>,
+        pending: Vec<String>,
+        failing: bool,
+    }
+
+    impl MemorySink {
+        fn new(failing: bool) -> (Self, Arc<Mutex<Received>>) {
+            let received = Arc::default();
+            (MemorySink { received: Arc::clone(&received), pending: Vec::new(), failing }, received)
+        }
+    }
+
+    impl Sink for MemorySink {
+        fn write_event(&mut self, event: &TelemetryEvent) -> io::Result<()> {
+            if self.failing {
+                return Err(io::Error::other("memory sink is failing"));
+            }
+            self.received.lock().unwrap().written.push(event.event_type.clone());
+            self.pending.push(event.event_type.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.received.lock().unwrap().flushed.append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    fn scratch_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("telemetry-sink-{}-{}.jsonl", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn file_config(path: &std::path::Path) -> Config {
+        Config { telemetry_file: path.to_string_lossy().into_owned(), ..Config::default() }
+    }
+
+    fn event(event_type: &str) -> TelemetryEvent {
+        TelemetryEvent { event_type: event_type.to_string(), ..TelemetryEvent::default() }
+    }
+
+    fn logged_types(path: &std::path::Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["event_type"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    /// Stands in for counting syscalls: the log is opened by the first event,
+    /// and nothing reaches it until the flush appends every event at once.
+    #[test]
+    fn file_sink_opens_once_and_appends_once_per_flush() {
+        let path = scratch_log("held");
+        let config = file_config(&path);
+        let mut sink = FileSink::new(&config);
+        assert!(!path.exists());
+        sink.write_event(&event("first")).unwrap();
+        sink.write_event(&event("second")).unwrap();
+        sink.write_event(&event("third")).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        sink.flush().unwrap();
+
+        assert_eq!(logged_types(&path), ["first", "second", "third"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_sinks_sharing_a_log_append_without_clobbering() {
+        let path = scratch_log("shared");
+        let config = file_config(&path);
+        let (mut first, mut second) = (FileSink::new(&config), FileSink::new(&config));
+        first.write_event(&event("a1")).unwrap();
+        second.write_event(&event("b1")).unwrap();
+        first.write_event(&event("a2")).unwrap();
+        second.write_event(&event("b2")).unwrap();
+        first.flush().unwrap();
+        second.flush().unwrap();
+
+        let mut types = logged_types(&path);
+        types.sort();
+        assert_eq!(types, ["a1", "a2", "b1", "b2"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fan_out_writes_and_flushes_every_sink() {
+        let (first, first_received) = MemorySink::new(false);
+        let (second, second_received) = MemorySink::new(false);
+        let mut sink = FanOutSink(vec![Box::new(first), Box::new(second)]);
+        sink.write_event(&event("process_start")).unwrap();
+        sink.write_event(&event("process_end")).unwrap();
+        for received in [&first_received, &second_received] {
+            let received = received.lock().unwrap();
+            assert_eq!(received.written, ["process_start", "process_end"]);
+            assert!(received.flushed.is_empty());
+        }
+
+        sink.flush().unwrap();
+        for received in [&first_received, &second_received] {
+            assert_eq!(received.lock().unwrap().flushed, ["process_start", "process_end"]);
+        }
+    }
+
+    #[test]
+    fn fan_out_keeps_writing_past_a_failing_sink() {
+        let (failing, _) = MemorySink::new(true);
+        let (working, received) = MemorySink::new(false);
+        let mut sink = FanOutSink(vec![Box::new(failing), Box::new(working)]);
+        let error = sink.write_event(&event("process_start")).unwrap_err();
+        assert_eq!(error.to_string(), "memory sink is failing");
+        sink.flush().unwrap();
+        assert_eq!(received.lock().unwrap().flushed, ["process_start"]);
+    }
+}
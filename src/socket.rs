@@ -0,0 +1,68 @@
+use std::io;
+
+use crate::event::TelemetryEvent;
+use crate::format::JsonCase;
+
+/// Sends `event` as a JSON line to the collector listening on the Unix socket
+/// at `path`. One connection is shared by the whole process and re-established
+/// after a failed send.
+#[cfg(unix)]
+pub(crate) fn send_event(path: &str, event: &TelemetryEvent, case: JsonCase) -> io::Result<()> {
+    use std::sync::{Mutex, PoisonError};
+
+    use crate::format::event_json;
+
+    static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+
+    let mut line = event_json(event, case)?.into_bytes();
+    line.push(b'\n');
+
+    let mut connection = CONNECTION.lock().unwrap_or_else(PoisonError::into_inner);
+    let conn = match &mut *connection {
+        Some(conn) => conn,
+        None => connection.insert(Connection::open(path)?),
+    };
+    let result = conn.send(&line);
+    if result.is_err() {
+        *connection = None;
+    }
+    result
+}
+
+#[cfg(not(unix))]
+pub(crate) fn send_event(_path: &str, _event: &TelemetryEvent, _case: JsonCase) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not available on this platform"))
+}
+
+#[cfg(unix)]
+enum Connection {
+    Stream(std::os::unix::net::UnixStream),
+    Datagram(std::os::unix::net::UnixDatagram),
+}
+
+#[cfg(unix)]
+impl Connection {
+    /// Connects as a stream, falling back to datagrams if that's what the socket is.
+    fn open(path: &str) -> io::Result<Self> {
+        use std::os::unix::net::{UnixDatagram, UnixStream};
+
+        match UnixStream::connect(path) {
+            Ok(stream) => Ok(Connection::Stream(stream)),
+            Err(e) if e.raw_os_error() == Some(libc::EPROTOTYPE) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Connection::Datagram(socket))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn send(&mut self, line: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+
+        match self {
+            Connection::Stream(stream) => stream.write_all(line),
+            Connection::Datagram(socket) => socket.send(line).map(|_| ()),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Copying events to the local syslog daemon (`TELEMETRY_SYSLOG`), as an
+//! extra sink alongside the file or collector.
+
+use std::io;
+
+use crate::event::TelemetryEvent;
+use crate::format::{event_json, event_value, JsonCase};
+
+/// Default facility, `user`.
+pub(crate) const DEFAULT_SYSLOG_FACILITY: u8 = 1;
+
+/// Messages are kept under this size; larger events are sent without their captured output.
+const SYSLOG_MAX_MESSAGE_BYTES: usize = 8 * 1024;
+
+const SEVERITY_ERR: u8 = 3;
+const SEVERITY_WARNING: u8 = 4;
+const SEVERITY_INFO: u8 = 6;
+
+/// Maps a facility name such as `daemon` or `local3` to its code.
+pub(crate) fn parse_facility(name: &str) -> Option<u8> {
+    let code = match name.to_ascii_lowercase().as_str() {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        other => 16 + other.strip_prefix("local")?.parse::<u8>().ok().filter(|n| *n <= 7)?,
+    };
+    Some(code)
+}
+
+/// Failures, signals and timeouts are errors, a plain nonzero exit a warning,
+/// everything else informational.
+fn severity(event: &TelemetryEvent) -> u8 {
+    if event.error.is_some() || event.terminated_by_signal == Some(true) || event.timed_out == Some(true) {
+        SEVERITY_ERR
+    } else if event.exit_code.is_some_and(|code| code != 0) {
+        SEVERITY_WARNING
+    } else {
+        SEVERITY_INFO
+    }
+}
+
+/// Fields dropped from an event too big for one syslog message.
+const TRIMMED_FIELDS: &[&str] = &[
+    "stdout_lines",
+    "stderr_lines",
+    "stdout_raw",
+    "stderr_raw",
+    "stdout_line_offsets_ms",
+    "stderr_line_offsets_ms",
+    "stdin_provided",
+    "output",
+    "combined_output",
+    "env",
+    "env_source",
+];
+
+/// Sends `event` as JSON to syslog, tagged with the command's name as the ident.
+/// `socket` overrides the platform's default syslog socket.
+pub(crate) fn send_event(event: &TelemetryEvent, facility: u8, socket: Option<&str>, case: JsonCase) -> io::Result<()> {
+    let mut body = event_json(event, case)?;
+    if body.len() > SYSLOG_MAX_MESSAGE_BYTES {
+        // Trimmed as the JSON that would be sent, so a filtered event loses the same fields
+        let mut value = event_value(event, JsonCase::Snake)?;
+        if let Some(fields) = value.as_object_mut() {
+            for name in TRIMMED_FIELDS {
+                fields.remove(*name);
+            }
+        }
+        let trimmed = TelemetryEvent { filter_output: Some(value), ..event.clone() };
+        body = event_json(&trimmed, case)?;
+    }
+
+    let ident = event
+        .command
+        .first()
+        .map(|program| program.rsplit(['/', '\\']).next().unwrap_or(program))
+        .unwrap_or("rust-telemetry-driver");
+    let message = format!("<{}>{} {}[{}]: {}",
+        facility * 8 + severity(event),
+        chrono::Local::now().format("%b %e %H:%M:%S"),
+        ident,
+        event.pid,
+        body);
+    send_message(message.as_bytes(), socket)
+}
+
+/// Writes one datagram to the local syslog socket, connecting on first use.
+#[cfg(unix)]
+fn send_message(message: &[u8], path: Option<&str>) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::{Mutex, PoisonError};
+
+    /// Where the local daemon listens on Linux and macOS respectively.
+    const SYSLOG_SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+    static SOCKET: Mutex<Option<UnixDatagram>> = Mutex::new(None);
+
+    let mut socket = SOCKET.lock().unwrap_or_else(PoisonError::into_inner);
+    let conn = match &mut *socket {
+        Some(conn) => conn,
+        None => {
+            let conn = UnixDatagram::unbound()?;
+            let connected = match path {
+                Some(path) => conn.connect(path),
+                None => SYSLOG_SOCKETS
+                    .iter()
+                    .map(|path| conn.connect(path))
+                    .find(Result::is_ok)
+                    .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, "no syslog socket found"))),
+            };
+            connected?;
+            socket.insert(conn)
+        }
+    };
+    let result = conn.send(message).map(|_| ());
+    if result.is_err() {
+        *socket = None;
+    }
+    result
+}
+
+#[cfg(not(unix))]
+fn send_message(_message: &[u8], _path: Option<&str>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "syslog is only available on Unix"))
+}
@@ -0,0 +1,65 @@
+//! `run_async` wrapping several commands at once on one runtime.
+#![cfg(all(feature = "tokio", unix))]
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use rust_telemetry_driver::{BannerMode, Config, TelemetryDriver};
+use serde_json::Value;
+
+/// How long each command sleeps; the runs together must take well under `RUNS` times this.
+const SLEEP: Duration = Duration::from_millis(500);
+const RUNS: usize = 4;
+
+fn log_path(run: usize) -> PathBuf {
+    std::env::temp_dir().join(format!("telemetry-async-{}-{}.jsonl", std::process::id(), run))
+}
+
+fn read_events(path: &PathBuf) -> Vec<Value> {
+    let log = fs::read_to_string(path).expect("run wrote its log");
+    log.lines().map(|line| serde_json::from_str(line).expect("event is JSON")).collect()
+}
+
+#[tokio::test]
+async fn concurrent_runs_each_log_their_own_events() {
+    let started = Instant::now();
+    let runs: Vec<_> = (0..RUNS)
+        .map(|run| {
+            let mut config = Config::default();
+            config.telemetry_file = log_path(run).to_string_lossy().into_owned();
+            config.banner = BannerMode::Never;
+            config.quiet = true;
+            let script = format!("echo out-{run}; echo err-{run} >&2; sleep {}", SLEEP.as_secs_f64());
+            let command = vec!["sh".to_string(), "-c".to_string(), script];
+            tokio::spawn(async move { TelemetryDriver::new(config).run_async(&command).await })
+        })
+        .collect();
+
+    let mut stats = Vec::new();
+    for run in runs {
+        stats.push(run.await.expect("run didn't panic").expect("run succeeded"));
+    }
+    assert!(started.elapsed() < SLEEP * RUNS as u32, "runs were serialized: {:?}", started.elapsed());
+
+    let mut sessions = HashSet::new();
+    for (run, stats) in stats.iter().enumerate() {
+        assert_eq!(stats.exit_code, 0);
+        assert_eq!((stats.stdout_lines, stats.stderr_lines), (1, 1));
+
+        let path = log_path(run);
+        let events = read_events(&path);
+        fs::remove_file(&path).unwrap();
+        let types: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+        assert!(events.iter().all(|event| event["session_id"] == events[0]["session_id"]));
+        sessions.insert(events[0]["session_id"].as_str().unwrap().to_string());
+
+        let end = &events[1];
+        assert_eq!(end["exit_code"], 0);
+        assert_eq!(end["stdout_lines"], serde_json::json!([format!("out-{run}")]));
+        assert_eq!(end["stderr_lines"], serde_json::json!([format!("err-{run}")]));
+    }
+    assert_eq!(sessions.len(), RUNS);
+}
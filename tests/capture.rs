@@ -0,0 +1,395 @@
+//! How the child's stdin, stdout and stderr are passed through and captured.
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{BufRead, BufReader};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use common::driver;
+
+#[test]
+fn stdin_reaches_the_child_and_is_recorded() {
+    let run = driver().env("TELEMETRY_CAPTURE_STDIN", "1").stdin("hello from stdin\n").run(&["cat"]);
+    assert!(run.status.success());
+    assert_eq!(run.stdout_text(), "hello from stdin\n");
+    let end = run.event("process_end");
+    assert_eq!(end["stdin_provided"], "hello from stdin\n");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["hello from stdin"]));
+}
+
+#[test]
+fn output_is_passed_through_while_the_child_runs() {
+    let (mut child, _scratch, _log) = driver().spawn(&["sh", "-c", "echo first; sleep 1; echo second"]);
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "first\n");
+    assert!(child.try_wait().unwrap().is_none(), "the first line only arrived once the child exited");
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn capture_cap_truncates_but_keeps_the_byte_total() {
+    // 2000 lines of 100 bytes against a 10KB cap
+    let run = driver()
+        .env("TELEMETRY_MAX_CAPTURE_BYTES", "10000")
+        .run(&["sh", "-c", "i=0; while [ $i -lt 2000 ]; do printf '%099d\\n' $i; i=$((i+1)); done"]);
+    assert_eq!(run.stdout.len(), 200_000);
+    let end = run.event("process_end");
+    assert_eq!(end["truncated"], true);
+    assert_eq!(end["stdout_size_bytes"], 200_000);
+    let stored: usize = end["stdout_lines"].as_array().unwrap().iter().map(|line| line.as_str().unwrap().len() + 1).sum();
+    assert!(stored <= 10_000, "{} bytes stored", stored);
+}
+
+#[test]
+fn binary_output_passes_through_byte_for_byte() {
+    let blob: Vec<u8> = (0..4).flat_map(|_| 0..=255u8).collect();
+    let driver = driver();
+    let path = driver.scratch().path("blob.bin");
+    std::fs::write(&path, &blob).unwrap();
+    let run = driver.run(&["cat", path.to_str().unwrap()]);
+    assert_eq!(run.stdout, blob);
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_size_bytes"], blob.len());
+    assert_eq!(end["lossy"], true);
+}
+
+#[test]
+fn disabled_driver_is_a_transparent_passthrough() {
+    let script = "printf 'out\\nno newline'; printf '\\377err' >&2; exit 3";
+    let direct = std::process::Command::new("sh").args(["-c", script]).output().unwrap();
+    let run = driver().env("TELEMETRY_DISABLE", "1").run(&["sh", "-c", script]);
+    assert_eq!(run.stdout, direct.stdout);
+    assert_eq!(run.stderr, direct.stderr);
+    assert_eq!(run.status.code(), direct.status.code());
+    assert!(!run.log.exists());
+}
+
+#[test]
+fn interleaved_output_keeps_the_order_across_streams() {
+    let script = "for i in 1 2 3; do echo out-$i; sleep 0.05; echo err-$i >&2; sleep 0.05; done";
+    let run = driver().env("TELEMETRY_INTERLEAVE_OUTPUT", "1").run(&["sh", "-c", script]);
+    let output = run.event("process_end")["output"].as_array().unwrap().clone();
+    let lines: Vec<_> =
+        output.iter().map(|line| (line["stream"].as_str().unwrap(), line["line"].as_str().unwrap())).collect();
+    assert_eq!(lines, [
+        ("stdout", "out-1"),
+        ("stderr", "err-1"),
+        ("stdout", "out-2"),
+        ("stderr", "err-2"),
+        ("stdout", "out-3"),
+        ("stderr", "err-3"),
+    ]);
+    let timestamps: Vec<f64> = output.iter().map(|line| line["ts"].as_f64().unwrap()).collect();
+    assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", timestamps);
+}
+
+#[test]
+fn line_offsets_follow_the_sleeps_between_lines() {
+    let script = "echo a; sleep 0.2; echo b; sleep 0.4; echo c";
+    let run = driver().env("TELEMETRY_TIMESTAMP_LINES", "1").run(&["sh", "-c", script]);
+    let offsets: Vec<f64> = run.event("process_end")["stdout_line_offsets_ms"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|offset| offset.as_f64().unwrap())
+        .collect();
+    assert_eq!(offsets.len(), 3);
+    let gaps = [offsets[1] - offsets[0], offsets[2] - offsets[1]];
+    assert!((150.0..600.0).contains(&gaps[0]), "{:?}", offsets);
+    assert!((350.0..900.0).contains(&gaps[1]), "{:?}", offsets);
+}
+
+#[test]
+fn pty_child_sees_a_terminal() {
+    let check = "[ -t 0 ] && [ -t 1 ] && [ -t 2 ] && echo terminal || echo no-terminal";
+    let piped = driver().run(&["sh", "-c", check]);
+    assert_eq!(piped.stdout_text(), "no-terminal\n");
+    let pty = driver().env("TELEMETRY_PTY", "1").run(&["sh", "-c", check]);
+    assert!(pty.stdout_text().starts_with("terminal"), "{:?}", pty.stdout_text());
+    assert_eq!(pty.event("process_end")["stdout_lines"][0], "terminal");
+}
+
+#[test]
+fn binary_stdin_is_forwarded_and_recorded_as_base64() {
+    let input: Vec<u8> = (0..=255u8).collect();
+    let run = driver().env("TELEMETRY_CAPTURE_STDIN", "1").stdin(input.clone()).run(&["cat"]);
+    assert_eq!(run.stdout, input);
+    let end = run.event("process_end");
+    assert_eq!(end["stdin_encoding"], "base64");
+    assert_eq!(BASE64.decode(end["stdin_provided"].as_str().unwrap()).unwrap(), input);
+    assert_eq!(end["stdin_truncated"], false);
+}
+
+#[test]
+fn recorded_stdin_is_capped_but_forwarded_whole() {
+    let input = "x".repeat(10_000);
+    let run = driver()
+        .env("TELEMETRY_CAPTURE_STDIN", "1")
+        .env("TELEMETRY_MAX_CAPTURE_BYTES", "1000")
+        .stdin(input.clone())
+        .run(&["wc", "-c"]);
+    assert_eq!(run.stdout_text().trim(), "10000");
+    let end = run.event("process_end");
+    assert_eq!(end["stdin_provided"].as_str().unwrap().len(), 1000);
+    assert_eq!(end["stdin_truncated"], true);
+}
+
+#[test]
+fn stdin_is_only_recorded_when_asked_for() {
+    let run = driver().stdin("private input\n").run(&["cat"]);
+    assert_eq!(run.stdout_text(), "private input\n");
+    assert!(run.event("process_end")["stdin_provided"].is_null());
+}
+
+#[test]
+fn one_huge_line_is_truncated_to_the_line_cap() {
+    let driver_rss = |run: &common::Run| run.event("process_stats")["driver_resource_usage"]["max_rss_kb"].as_i64().unwrap();
+    let baseline = driver_rss(&driver().run(&["echo", "hi"]));
+
+    // A single 10MB line against a 1KB cap, followed by a short one
+    let script = "head -c 10000000 /dev/zero | tr '\\0' a; echo; echo after";
+    let run = driver().env("TELEMETRY_MAX_LINE_BYTES", "1024").run(&["sh", "-c", script]);
+    assert_eq!(run.stdout.len(), 10_000_007);
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_size_bytes"], 10_000_007);
+    let lines = end["stdout_lines"].as_array().unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], format!("{}…[truncated {} bytes]", "a".repeat(1024), 10_000_000 - 1024));
+    assert_eq!(lines[1], "after");
+    // Holding the whole line would have added its 10MB
+    assert!(driver_rss(&run) < baseline + 4 * 1024, "{}KB against {}KB", driver_rss(&run), baseline);
+}
+
+#[test]
+fn uncaptured_stdout_is_inherited_and_left_out_of_the_event() {
+    let run = driver().env("TELEMETRY_CAPTURE_STDOUT", "0").run(&["sh", "-c", "echo out; echo err >&2"]);
+    assert_eq!(run.stdout_text(), "out\n");
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::Value::Null);
+    assert_eq!(end["stdout_size_bytes"], serde_json::Value::Null);
+    assert_eq!(end["stderr_lines"], serde_json::json!(["err"]));
+    assert_eq!(end["stderr_size_bytes"], 4);
+}
+
+#[test]
+fn json_stdout_lines_are_embedded_as_values() {
+    let script = r#"echo '{"step":1,"ok":true}'; echo plain text; echo '[1, 2]'; echo '{broken'"#;
+    let run = driver().env("TELEMETRY_PARSE_JSON_LINES", "1").run(&["sh", "-c", script]);
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!([{"step": 1, "ok": true}, "plain text", [1, 2], "{broken"]));
+
+    let unparsed = driver().run(&["sh", "-c", script]);
+    assert_eq!(unparsed.event("process_end")["stdout_lines"][0], r#"{"step":1,"ok":true}"#);
+}
+
+#[test]
+fn combined_output_is_one_ordered_array() {
+    let script = "for i in 1 2; do echo out-$i; sleep 0.05; echo err-$i >&2; sleep 0.05; done";
+    let run = driver().env("TELEMETRY_COMBINE_OUTPUT", "1").run(&["sh", "-c", script]);
+    let end = run.event("process_end");
+    assert_eq!(end["combined_output"], serde_json::json!(["out-1", "err-1", "out-2", "err-2"]));
+    assert!(end["stdout_lines"].is_null() && end["stderr_lines"].is_null());
+    assert!(end["output"].is_null());
+    // Both streams still pass through on their own
+    assert_eq!(run.stdout_text(), "out-1\nout-2\n");
+    assert_eq!(run.stderr_text(), "err-1\nerr-2\n");
+}
+
+#[test]
+fn latency_to_first_output_follows_a_leading_sleep() {
+    let run = driver().run(&["sh", "-c", "sleep 0.3; echo late >&2; echo later"]);
+    let latency = run.event("process_end")["time_to_first_output_ms"].as_f64().unwrap();
+    assert!((250.0..1500.0).contains(&latency), "{}", latency);
+    assert_eq!(run.event("process_stats")["time_to_first_output_ms"], latency);
+
+    let silent = driver().run(&["sleep", "0.1"]);
+    assert!(silent.event("process_end")["time_to_first_output_ms"].is_null());
+}
+
+#[test]
+fn quiet_mode_logs_output_without_replaying_it() {
+    let run = driver().env("TELEMETRY_QUIET", "1").run(&["sh", "-c", "echo out; echo err >&2; exit 3"]);
+    assert_eq!(run.code(), 3);
+    assert!(run.stdout.is_empty(), "{:?}", run.stdout_text());
+    assert!(run.stderr.is_empty(), "{:?}", run.stderr_text());
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["out"]));
+    assert_eq!(end["stderr_lines"], serde_json::json!(["err"]));
+}
+
+#[test]
+fn output_hashes_are_stable_across_runs_and_capture_settings() {
+    let hashes = |cap: &str| {
+        let run = driver()
+            .env("TELEMETRY_HASH_OUTPUT", "1")
+            .env("TELEMETRY_MAX_CAPTURE_BYTES", cap)
+            .run(&["sh", "-c", "printf 'hello\\nworld\\n'"]);
+        let end = run.event("process_end");
+        (end["stdout_sha256"].clone(), end["stderr_sha256"].clone())
+    };
+    let (stdout, stderr) = hashes("1000000");
+    assert_eq!(stdout, "4a1e67f2fe1d1cc7b31d0ca2ec441da4778203a036a77da10344c85e24ff0f92");
+    // Nothing at all on stderr
+    assert_eq!(stderr, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hashes("1000000"), (stdout.clone(), stderr.clone()));
+    assert_eq!(hashes("3"), (stdout, stderr));
+}
+
+#[test]
+fn ansi_sequences_are_stripped_from_stored_lines_only() {
+    let script = "printf '\\033[1;31mred\\033[0m plain\\n\\033[2Kcleared\\n'";
+    let run = driver().env("TELEMETRY_STRIP_ANSI", "1").env("TELEMETRY_HASH_OUTPUT", "1").run(&["sh", "-c", script]);
+    // Replayed and hashed as the child wrote it
+    assert_eq!(run.stdout, b"\x1b[1;31mred\x1b[0m plain\n\x1b[2Kcleared\n");
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["red plain", "cleared"]));
+    assert_eq!(end["had_ansi"], true);
+
+    let plain = driver().env("TELEMETRY_STRIP_ANSI", "1").run(&["echo", "plain"]);
+    assert_eq!(plain.event("process_end")["had_ansi"], false);
+
+    let kept = driver().env("TELEMETRY_HASH_OUTPUT", "1").run(&["sh", "-c", script]);
+    assert_eq!(kept.event("process_end")["stdout_lines"][0], "\u{1b}[1;31mred\u{1b}[0m plain");
+    assert_eq!(kept.event("process_end")["stdout_sha256"], end["stdout_sha256"]);
+}
+
+#[test]
+fn line_cap_bounds_storage_but_not_counts() {
+    let run = driver().env("TELEMETRY_MAX_LINES", "10").run(&["seq", "1000"]);
+    assert_eq!(run.stdout_text().lines().count(), 1000);
+    let end = run.event("process_end");
+    let stored: Vec<_> = end["stdout_lines"].as_array().unwrap().iter().map(|line| line.as_str().unwrap()).collect();
+    assert_eq!(stored, (1..=10).map(|n| n.to_string()).collect::<Vec<_>>());
+    assert_eq!(end["truncated_lines"], true);
+    assert_eq!(end["stdout_size_bytes"], run.stdout.len());
+    assert_eq!(run.event("process_stats")["env"]["stdout_lines"], "1000");
+}
+
+#[test]
+fn line_prefix_marks_replayed_lines_only() {
+    let run = driver()
+        .env("TELEMETRY_LINE_PREFIX", "[{session} {cmd} {stream}] ")
+        .env("TELEMETRY_SESSION_ID", "pre")
+        .run(&["/bin/sh", "-c", "echo one; echo two; echo bad >&2"]);
+    assert_eq!(run.stdout_text(), "[pre sh stdout] one\n[pre sh stdout] two\n");
+    assert_eq!(run.stderr_text(), "[pre sh stderr] bad\n");
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["one", "two"]));
+    assert_eq!(end["stderr_lines"], serde_json::json!(["bad"]));
+}
+
+#[test]
+fn tty_flags_describe_the_drivers_own_stdio() {
+    let piped = driver().run(&["true"]);
+    let start = piped.event("process_start");
+    for flag in ["stdin_is_tty", "stdout_is_tty", "stderr_is_tty"] {
+        assert_eq!(start[flag], false, "{}", flag);
+    }
+
+    // A driver run on the outer one's pseudo-terminal sees a terminal everywhere
+    let nested = driver().env("TELEMETRY_PTY", "1").run(&[common::DRIVER, "true"]);
+    let inner = nested
+        .events
+        .iter()
+        .find(|event| event["event_type"] == "process_start" && event["command"][0] == "true")
+        .unwrap();
+    for flag in ["stdin_is_tty", "stdout_is_tty", "stderr_is_tty"] {
+        assert_eq!(inner[flag], true, "{}", flag);
+    }
+}
+
+#[test]
+fn last_line_without_a_newline_is_replayed_without_one() {
+    let run = driver().run(&["sh", "-c", "printf 'first\\nno newline'; printf 'err tail' >&2"]);
+    assert_eq!(run.stdout, b"first\nno newline");
+    assert_eq!(run.stderr, b"err tail");
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["first", "no newline"]));
+    assert_eq!(end["stdout_size_bytes"], 16);
+    assert_eq!(end["stderr_lines"], serde_json::json!(["err tail"]));
+}
+
+#[test]
+fn exact_passthrough_matches_the_command_run_directly() {
+    // CRLF, invalid UTF-8, an over-long line and no final newline
+    let script = "printf 'crlf\\r\\n\\377\\376 bytes\\n'; head -c 3000 /dev/zero | tr '\\0' x; printf '\\nend'";
+    let direct = std::process::Command::new("sh").args(["-c", script]).output().unwrap().stdout;
+    let run = driver()
+        .env("TELEMETRY_EXACT_PASSTHROUGH", "1")
+        .env("TELEMETRY_MAX_LINE_BYTES", "100")
+        .env("TELEMETRY_LINE_PREFIX", "[{stream}] ")
+        .run(&["sh", "-c", script]);
+    assert_eq!(run.stdout, direct);
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_size_bytes"], direct.len());
+    assert_eq!(end["stdout_lines"].as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn tail_lines_keeps_the_last_lines_of_each_stream() {
+    let script = "seq 1000; seq 5 >&2";
+    let run = driver().env("TELEMETRY_TAIL_LINES", "10").run(&["sh", "-c", script]);
+    assert_eq!(run.stdout_text().lines().count(), 1000);
+    let end = run.event("process_end");
+    let expected: Vec<String> = (991..=1000).map(|n| n.to_string()).collect();
+    assert_eq!(end["stdout_lines"], serde_json::json!(expected));
+    // A stream shorter than the ring is kept whole
+    assert_eq!(end["stderr_lines"], serde_json::json!(["1", "2", "3", "4", "5"]));
+    assert_eq!(end["stdout_size_bytes"], run.stdout.len());
+    assert_eq!(end["truncated_lines"], true);
+    assert_eq!(run.event("process_stats")["env"]["stdout_lines"], "1000");
+}
+
+#[test]
+fn head_and_tail_lines_surround_an_omission_marker() {
+    let run = driver()
+        .env("TELEMETRY_HEAD_LINES", "3")
+        .env("TELEMETRY_TAIL_LINES", "2")
+        .env("TELEMETRY_TIMESTAMP_LINES", "1")
+        .run(&["seq", "100"]);
+    let end = run.event("process_end");
+    assert_eq!(end["stdout_lines"], serde_json::json!(["1", "2", "3", "…[omitted 95 lines]", "99", "100"]));
+    assert_eq!(end["stdout_line_offsets_ms"].as_array().unwrap().len(), 6);
+    assert_eq!(end["stdout_size_bytes"], run.stdout.len());
+    assert_eq!(end["truncated_lines"], true);
+
+    // Nothing to omit
+    let short = driver().env("TELEMETRY_HEAD_LINES", "3").env("TELEMETRY_TAIL_LINES", "2").run(&["seq", "5"]);
+    assert_eq!(short.event("process_end")["stdout_lines"], serde_json::json!(["1", "2", "3", "4", "5"]));
+}
+
+#[test]
+fn closed_driver_stdout_still_gives_an_end_event() {
+    use std::io::Read;
+
+    // Whoever reads the driver's stdout goes away after the first bytes
+    let (mut child, _scratch, log) = driver().spawn(&["sh", "-c", "seq 200000; echo err >&2; exit 3"]);
+    let mut stdout = child.stdout.take().unwrap();
+    let mut first = [0u8; 16];
+    stdout.read_exact(&mut first).unwrap();
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+
+    let events = common::read_events(&log);
+    let end = events.iter().find(|event| event["event_type"] == "process_end").expect("end event logged");
+    assert_eq!(end["exit_code"], 3);
+    assert_eq!(end["stderr_lines"], serde_json::json!(["err"]));
+}
+
+#[test]
+fn raw_capture_keeps_binary_output_exactly() {
+    // Every byte value, invalid UTF-8, NULs and CRLFs included, over several reads
+    let input: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let run = driver().env("TELEMETRY_CAPTURE_RAW", "1").stdin(input.clone()).run(&["cat"]);
+    assert!(run.status.success());
+    assert_eq!(run.stdout, input);
+    let end = run.event("process_end");
+    assert_eq!(BASE64.decode(end["stdout_raw"].as_str().unwrap()).unwrap(), input);
+    assert_eq!(BASE64.decode(end["stderr_raw"].as_str().unwrap()).unwrap(), b"");
+    assert_eq!(end["stdout_size_bytes"], input.len());
+}
@@ -0,0 +1,230 @@
+//! Running the driver binary against a scratch log, for the integration tests.
+//! The test process's own `TELEMETRY_*` variables are cleared so each run only
+//! sees what the test sets, and banners are off unless a test asks for them.
+#![allow(dead_code)] // each test crate uses its own subset
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::Value;
+
+pub const DRIVER: &str = env!("CARGO_BIN_EXE_rust-telemetry-driver");
+
+/// A directory under the system temp dir, removed on drop.
+pub struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("telemetry-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch { dir }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// One invocation of the driver binary, built up like a [`Command`].
+pub struct Driver {
+    command: Command,
+    stdin: Option<Vec<u8>>,
+    log: PathBuf,
+    scratch: Scratch,
+}
+
+/// A driver with a clean `TELEMETRY_*` environment logging to `events.jsonl` in a scratch dir.
+pub fn driver() -> Driver {
+    let scratch = Scratch::new();
+    let log = scratch.path("events.jsonl");
+    let mut command = Command::new(DRIVER);
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("TELEMETRY_") {
+            command.env_remove(name);
+        }
+    }
+    command.env("TELEMETRY_LOG", &log).env("TELEMETRY_BANNER", "never");
+    Driver { command, stdin: None, log, scratch }
+}
+
+impl Driver {
+    pub fn env(mut self, name: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.command.env(name, value);
+        self
+    }
+
+    pub fn env_remove(mut self, name: impl AsRef<OsStr>) -> Self {
+        self.command.env_remove(name);
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.command.current_dir(dir);
+        self
+    }
+
+    /// Bytes written to the driver's stdin; without this its stdin is null.
+    pub fn stdin(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn log(&self) -> &Path {
+        &self.log
+    }
+
+    pub fn scratch(&self) -> &Scratch {
+        &self.scratch
+    }
+
+    /// Runs `args` (flags and the command) to completion.
+    pub fn run(mut self, args: &[&str]) -> Run {
+        self.command.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+        self.command.stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+        let mut child = self.command.spawn().expect("driver spawns");
+        if let Some(input) = self.stdin.take() {
+            let mut stdin = child.stdin.take().unwrap();
+            // The child may exit without reading it all
+            let _ = stdin.write_all(&input);
+        }
+        let output = child.wait_with_output().unwrap();
+        Run {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            events: read_events(&self.log),
+            log: self.log,
+            scratch: self.scratch,
+        }
+    }
+
+    /// Starts `args` with piped stdout and stderr, for tests that signal the driver.
+    pub fn spawn(mut self, args: &[&str]) -> (Child, Scratch, PathBuf) {
+        self.command.args(args).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        (self.command.spawn().expect("driver spawns"), self.scratch, self.log)
+    }
+
+    /// The command for `args` with its stdio left alone, for tests that attach the driver some other way.
+    pub fn command(mut self, args: &[&str]) -> (Command, Scratch, PathBuf) {
+        self.command.args(args);
+        (self.command, self.scratch, self.log)
+    }
+}
+
+/// What a finished driver run left behind.
+pub struct Run {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub events: Vec<Value>,
+    pub log: PathBuf,
+    pub scratch: Scratch,
+}
+
+impl Run {
+    /// The only event of `event_type`.
+    pub fn event(&self, event_type: &str) -> &Value {
+        let mut matching = self.events.iter().filter(|event| event["event_type"] == event_type);
+        let event = matching.next().unwrap_or_else(|| panic!("no {} event in {:?}", event_type, self.event_types()));
+        assert!(matching.next().is_none(), "more than one {} event", event_type);
+        event
+    }
+
+    pub fn event_types(&self) -> Vec<&str> {
+        self.events.iter().map(|event| event["event_type"].as_str().unwrap()).collect()
+    }
+
+    pub fn stdout_text(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_text(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+
+    pub fn code(&self) -> i32 {
+        self.status.code().expect("driver exited normally")
+    }
+}
+
+/// The JSONL events at `path`; none if it doesn't exist.
+pub fn read_events(path: &Path) -> Vec<Value> {
+    match fs::read_to_string(path) {
+        Ok(log) => log.lines().map(|line| serde_json::from_str(line).expect("event is JSON")).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// An HTTP server on localhost answering each request with the next of
+/// `statuses` (200 once they run out) and handing over the request bodies.
+pub struct MockHttp {
+    pub url: String,
+    bodies: std::sync::mpsc::Receiver<(String, Vec<u8>)>,
+}
+
+impl MockHttp {
+    pub fn start(statuses: impl IntoIterator<Item = u16>) -> Self {
+        use std::io::{BufRead, BufReader, Read};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, bodies) = std::sync::mpsc::channel();
+        let mut statuses = statuses.into_iter().collect::<Vec<_>>().into_iter();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                // Recorded before answering, so it's there by the time the driver exits
+                if sender.send((path, body)).is_err() {
+                    return;
+                }
+                let status = statuses.next().unwrap_or(200);
+                let response = format!("HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        MockHttp { url, bodies }
+    }
+
+    /// Every `(path, body)` received so far.
+    pub fn requests(&self) -> Vec<(String, Vec<u8>)> {
+        self.bodies.try_iter().collect()
+    }
+}
@@ -0,0 +1,219 @@
+//! Settings that decide whether a run is logged, and how many events it gets.
+#![cfg(unix)]
+
+mod common;
+
+use common::driver;
+
+#[test]
+fn sample_rate_zero_logs_nothing_and_one_logs_everything() {
+    for _ in 0..5 {
+        let never = driver().env("TELEMETRY_SAMPLE_RATE", "0").run(&["echo", "hi"]);
+        assert_eq!(never.stdout_text(), "hi\n");
+        assert!(!never.log.exists());
+
+        let always = driver().env("TELEMETRY_SAMPLE_RATE", "1").run(&["echo", "hi"]);
+        assert_eq!(always.event_types(), ["process_start", "process_end", "process_stats"]);
+    }
+}
+
+#[test]
+fn only_failures_logs_nothing_for_a_success_and_everything_for_a_failure() {
+    let success = driver().env("TELEMETRY_ONLY_FAILURES", "1").run(&["true"]);
+    assert!(success.status.success());
+    assert!(success.events.is_empty());
+
+    let failure = driver().env("TELEMETRY_ONLY_FAILURES", "1").run(&["false"]);
+    assert_eq!(failure.code(), 1);
+    assert_eq!(failure.event_types(), ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn single_event_mode_writes_exactly_one_line() {
+    let run = driver().env("TELEMETRY_EVENT_MODE", "single").run(&["echo", "hi"]);
+    assert_eq!(std::fs::read_to_string(&run.log).unwrap().lines().count(), 1);
+    let event = run.event("process_complete");
+    assert_eq!(event["exit_code"], 0);
+    assert_eq!(event["stdout_lines"], serde_json::json!(["hi"]));
+    assert!(event["env"].as_object().is_some_and(|env| env.contains_key("PATH")));
+}
+
+#[test]
+fn driver_flags_go_before_the_separator() {
+    let run = driver().run(&["--tag", "team=ci", "--event-mode", "single", "--", "echo", "--tag", "x"]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    assert_eq!(run.stdout_text(), "--tag x\n");
+    let complete = run.event("process_complete");
+    assert_eq!(complete["tags"]["team"], "ci");
+    assert_eq!(complete["command"], serde_json::json!(["echo", "--tag", "x"]));
+
+    // After the command they belong to it
+    let run = driver().run(&["echo", "--tag", "team=ci", "--"]);
+    assert_eq!(run.stdout_text(), "--tag team=ci --\n");
+    assert!(run.event("process_end")["tags"].as_object().unwrap().is_empty());
+}
+
+#[test]
+fn deterministic_ids_repeat_for_the_same_session() {
+    let ids = |session: &str| -> Vec<String> {
+        let run = driver()
+            .env("TELEMETRY_DETERMINISTIC_IDS", "1")
+            .env("TELEMETRY_SESSION_ID", session)
+            .run(&["true"]);
+        run.events.iter().map(|event| event["event_id"].as_str().unwrap().to_string()).collect()
+    };
+    let first = ids("snapshot");
+    assert_eq!(first.len(), 3);
+    assert_eq!(first, ids("snapshot"));
+    assert_ne!(first, ids("other"));
+    assert!(first.iter().all(|id| uuid_version(id) == '5'), "{:?}", first);
+
+    // Random v4 ids by default
+    let random = driver().env("TELEMETRY_SESSION_ID", "snapshot").run(&["true"]);
+    let random = random.events[0]["event_id"].as_str().unwrap().to_string();
+    assert_eq!(uuid_version(&random), '4');
+    assert!(!first.contains(&random));
+}
+
+/// The version digit of a hyphenated UUID.
+fn uuid_version(id: &str) -> char {
+    id.chars().nth(14).unwrap()
+}
+
+const CONFIG_FILE: &str = r#"
+format = "csv"
+sink = "stderr"
+env_redact = ["SECRET_*", "*_TOKEN"]
+max_capture_bytes = 5
+timeout_ms = 300
+event_mode = "single"
+tags = { team = "infra" }
+"#;
+
+#[test]
+fn config_file_sets_each_setting() {
+    use rust_telemetry_driver::{Config, EventMode, LogStream, OutputFormat};
+
+    let scratch = common::Scratch::new();
+    let path = scratch.path("telemetry.toml");
+    std::fs::write(&path, CONFIG_FILE).unwrap();
+    let config = Config::from_file(path.to_str().unwrap()).unwrap();
+    assert_eq!(config.format, OutputFormat::Csv);
+    assert_eq!(config.log_stream, Some(LogStream::Stderr));
+    assert!(["SECRET_*", "*_TOKEN"].iter().all(|pattern| config.env_redact.iter().any(|p| p == pattern)));
+    assert_eq!(config.max_capture_bytes, 5);
+    assert_eq!(config.timeout, Some(std::time::Duration::from_millis(300)));
+    assert_eq!(config.event_mode, EventMode::Single);
+    assert_eq!(config.tags["team"], "infra");
+}
+
+#[test]
+fn environment_overrides_the_config_file_and_flags_override_both() {
+    let file_only = driver();
+    let path = file_only.scratch().path("telemetry.toml");
+    std::fs::write(&path, "event_mode = \"single\"\ntimeout_ms = 300\ntags = { team = \"infra\" }\n").unwrap();
+    let run = file_only.env("TELEMETRY_CONFIG", &path).run(&["sleep", "2"]);
+    assert_eq!(run.code(), rust_telemetry_driver::TIMEOUT_EXIT_CODE);
+    assert_eq!(run.event("process_complete")["tags"]["team"], "infra");
+
+    let overridden = driver()
+        .env("TELEMETRY_CONFIG", &path)
+        .env("TELEMETRY_EVENT_MODE", "multi")
+        .env("TELEMETRY_TAGS", "team=env")
+        .env("TELEMETRY_TIMEOUT_MS", "100")
+        .run(&["--timeout", "5", "--tag", "team=flag", "--", "sleep", "0.5"]);
+    assert!(overridden.status.success(), "{}", overridden.stderr_text());
+    assert_eq!(overridden.event_types(), ["process_start", "process_end", "process_stats"]);
+    assert_eq!(overridden.event("process_end")["tags"]["team"], "flag");
+
+    let env_only = driver().env("TELEMETRY_CONFIG", &path).env("TELEMETRY_TAGS", "team=env").run(&["true"]);
+    assert_eq!(env_only.event("process_complete")["tags"]["team"], "env");
+}
+
+#[test]
+fn json_case_camel_renames_logged_fields() {
+    let camel = driver().env("TELEMETRY_JSON_CASE", "camel").run(&["true"]);
+    assert!(camel.events.iter().all(|event| event["eventType"].is_string() && event.get("event_type").is_none()));
+    assert_eq!(camel.events[1]["eventType"], "process_end");
+
+    let default = driver().run(&["true"]);
+    assert!(default.events.iter().all(|event| event["event_type"].is_string() && event.get("eventType").is_none()));
+}
+
+#[test]
+fn command_outside_the_allowlist_runs_without_telemetry() {
+    let excluded = driver().env("TELEMETRY_ALLOWED_COMMANDS", "make,cargo").run(&["sh", "-c", "echo hi; exit 4"]);
+    assert_eq!(excluded.code(), 4);
+    assert_eq!(excluded.stdout_text(), "hi\n");
+    assert!(!excluded.log.exists());
+
+    // Matched by basename
+    let allowed = driver().env("TELEMETRY_ALLOWED_COMMANDS", "make, echo").run(&["/bin/echo", "hi"]);
+    assert_eq!(allowed.event_types(), ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn denied_command_runs_normally_without_telemetry() {
+    let denied = driver()
+        .env("TELEMETRY_DENIED_COMMANDS", "cat,gpg")
+        .env("TELEMETRY_CAPTURE_STDIN", "1")
+        .stdin("secret input\n")
+        .run(&["cat"]);
+    assert!(denied.status.success());
+    assert_eq!(denied.stdout_text(), "secret input\n");
+    assert!(!denied.log.exists());
+
+    // Deny wins over allow
+    let both = driver()
+        .env("TELEMETRY_ALLOWED_COMMANDS", "echo")
+        .env("TELEMETRY_DENIED_COMMANDS", "echo")
+        .run(&["echo", "hi"]);
+    assert_eq!(both.stdout_text(), "hi\n");
+    assert!(!both.log.exists());
+}
+
+#[test]
+fn summary_json_replaces_the_completion_banner() {
+    let run = driver()
+        .env("TELEMETRY_BANNER", "always")
+        .env("TELEMETRY_SUMMARY_JSON", "1")
+        .env("TELEMETRY_SESSION_ID", "summary-session")
+        .run(&["sh", "-c", "echo out; exit 2"]);
+    assert_eq!(run.code(), 2);
+    let stderr = run.stderr_text();
+    assert!(!stderr.contains("Completed in"), "{}", stderr);
+    let summary: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    let mut keys: Vec<_> = summary.as_object().unwrap().keys().map(String::as_str).collect();
+    keys.sort();
+    assert_eq!(keys, [
+        "duration_ms",
+        "exit_code",
+        "session_id",
+        "short_id",
+        "signal",
+        "stderr_bytes",
+        "stderr_lines",
+        "stdout_bytes",
+        "stdout_lines",
+        "telemetry",
+        "timed_out",
+    ]);
+    assert_eq!(summary["session_id"], "summary-session");
+    assert_eq!(summary["exit_code"], 2);
+    assert_eq!((summary["stdout_lines"].as_u64(), summary["stdout_bytes"].as_u64()), (Some(1), Some(4)));
+    assert_eq!(summary["short_id"], run.event("process_end")["short_id"]);
+}
+
+#[test]
+fn auto_banner_only_shows_for_failures() {
+    let success = driver().env("TELEMETRY_BANNER", "auto").run(&["true"]);
+    assert!(success.stderr.is_empty(), "{}", success.stderr_text());
+
+    let failure = driver().env("TELEMETRY_BANNER", "auto").run(&["false"]);
+    let stderr = failure.stderr_text();
+    assert!(stderr.contains("Executing: false"), "{}", stderr);
+    assert!(stderr.contains("Exit: 1"), "{}", stderr);
+
+    let always = driver().env_remove("TELEMETRY_BANNER").run(&["true"]);
+    assert!(always.stderr_text().contains("Completed in"), "banners default to always");
+}
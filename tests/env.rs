@@ -0,0 +1,126 @@
+//! Which environment variables reach the log, and in what form.
+#![cfg(unix)]
+
+mod common;
+
+use common::driver;
+use rust_telemetry_driver::REDACTED;
+
+#[test]
+fn secret_values_never_reach_the_log() {
+    let run = driver()
+        .env("DEPLOY_SECRET", "default-pattern-secret")
+        .env("INTERNAL_DSN", "user-pattern-secret")
+        .env("TELEMETRY_ENV_REDACT", "*_DSN")
+        .run(&["true"]);
+    let log = std::fs::read_to_string(&run.log).unwrap();
+    assert!(!log.contains("default-pattern-secret"));
+    assert!(!log.contains("user-pattern-secret"));
+    let env = &run.event("process_start")["env"];
+    assert_eq!(env["DEPLOY_SECRET"], REDACTED);
+    assert_eq!(env["INTERNAL_DSN"], REDACTED);
+}
+
+#[test]
+fn allowlist_keeps_only_matching_variables() {
+    let run = driver().env("APP_NAME", "demo").env("OTHER_VAR", "x").env("TELEMETRY_ENV_ALLOW", "APP_*").run(&["true"]);
+    let env = run.event("process_start")["env"].as_object().unwrap();
+    assert_eq!(env["APP_NAME"], "demo");
+    assert!(!env.contains_key("OTHER_VAR"));
+    assert!(!env.contains_key("PATH"));
+}
+
+#[test]
+fn redaction_alone_keeps_every_variable() {
+    let run = driver().env("APP_NAME", "demo").env("APP_TOKEN", "t0ken").run(&["true"]);
+    let env = run.event("process_start")["env"].as_object().unwrap();
+    assert_eq!(env["APP_NAME"], "demo");
+    assert_eq!(env["APP_TOKEN"], REDACTED);
+    assert!(env.contains_key("PATH"));
+}
+
+#[test]
+fn allowed_variables_are_still_redacted() {
+    let run = driver()
+        .env("APP_NAME", "demo")
+        .env("APP_TOKEN", "t0ken")
+        .env("OTHER_TOKEN", "t0ken")
+        .env("TELEMETRY_ENV_ALLOW", "APP_*")
+        .run(&["true"]);
+    let env = run.event("process_start")["env"].as_object().unwrap();
+    assert_eq!(env["APP_NAME"], "demo");
+    assert_eq!(env["APP_TOKEN"], REDACTED);
+    assert!(!env.contains_key("OTHER_TOKEN"));
+}
+
+#[test]
+fn tags_appear_on_every_event() {
+    for tags in ["team=infra,build=42", r#"{"team": "infra", "build": 42}"#] {
+        let run = driver().env("TELEMETRY_TAGS", tags).run(&["true"]);
+        assert_eq!(run.events.len(), 3);
+        for event in &run.events {
+            assert_eq!(event["tags"], serde_json::json!({"team": "infra", "build": "42"}), "{}", tags);
+        }
+    }
+}
+
+#[test]
+fn child_environment_overrides_are_applied_and_recorded() {
+    let run = driver()
+        .env("DROPPED_VAR", "before")
+        .env("TELEMETRY_CHILD_ENV_SET", "INJECTED_VAR=hello,TRACE_HINT=on")
+        .env("TELEMETRY_CHILD_ENV_UNSET", "DROPPED_VAR")
+        .run(&["sh", "-c", "echo \"$INJECTED_VAR $TRACE_HINT ${DROPPED_VAR-unset}\""]);
+    assert_eq!(run.stdout_text(), "hello on unset\n");
+
+    let start = run.event("process_start");
+    let overrides = &start["child_env_overrides"];
+    assert_eq!(overrides["set"], serde_json::json!({"INJECTED_VAR": "hello", "TRACE_HINT": "on"}));
+    assert_eq!(overrides["unset"], serde_json::json!(["DROPPED_VAR"]));
+    // The recorded env is what the child saw
+    let env = start["env"].as_object().unwrap();
+    assert_eq!(env["INJECTED_VAR"], "hello");
+    assert!(!env.contains_key("DROPPED_VAR"));
+}
+
+#[test]
+fn oversized_env_values_are_truncated_with_a_marker() {
+    let run = driver()
+        .env("HUGE_VALUE", "x".repeat(10_000))
+        .env("SMALL_VALUE", "fits")
+        .env("TELEMETRY_ENV_MAX_VALUE_BYTES", "100")
+        .run(&["sh", "-c", "echo ${#HUGE_VALUE}"]);
+    // The child still gets the whole value
+    assert_eq!(run.stdout_text(), "10000\n");
+    let start = run.event("process_start");
+    assert_eq!(start["env"]["HUGE_VALUE"], format!("{}…[truncated 9900 bytes]", "x".repeat(100)));
+    assert_eq!(start["env"]["SMALL_VALUE"], "fits");
+    assert_eq!(start["env_truncated"], true);
+}
+
+#[test]
+fn env_count_cap_keeps_the_first_names() {
+    let run = driver().env("TELEMETRY_ENV_MAX_COUNT", "3").run(&["true"]);
+    let start = run.event("process_start");
+    let env = start["env"].as_object().unwrap();
+    assert_eq!(env.len(), 3);
+    let mut all: Vec<String> = std::env::vars().map(|(name, _)| name).collect();
+    all.sort();
+    assert!(env.keys().all(|name| all[..3].contains(name)), "{:?} of {:?}", env.keys(), all);
+    assert_eq!(start["env_truncated"], true);
+}
+
+#[test]
+fn env_source_tags_driver_added_variables() {
+    let run = driver()
+        .env("TELEMETRY_CHILD_ENV_SET", "INJECTED_VAR=hello")
+        .run(&["sh", "-c", "echo \"$TELEMETRY_SESSION_ID\""]);
+    let start = run.event("process_start");
+    // The child got the session the events carry
+    assert_eq!(run.stdout_text().trim_end(), start["session_id"]);
+    let source = &start["env_source"];
+    assert_eq!(source["TELEMETRY_SESSION_ID"], "driver-added");
+    assert_eq!(source["INJECTED_VAR"], "driver-added");
+    assert_eq!(source["PATH"], "inherited");
+    assert_eq!(source.as_object().unwrap().len(), start["env"].as_object().unwrap().len());
+}
@@ -0,0 +1,55 @@
+//! The Kafka sink against librdkafka's in-process mock cluster.
+#![cfg(all(feature = "kafka", unix))]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::driver;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::mocking::MockCluster;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use serde_json::Value;
+
+const TOPIC: &str = "telemetry";
+
+#[test]
+fn events_are_produced_keyed_by_session() {
+    let cluster = MockCluster::new(1).unwrap();
+    cluster.create_topic(TOPIC, 3, 1).unwrap();
+
+    let run = driver()
+        .env("TELEMETRY_KAFKA_BROKERS", cluster.bootstrap_servers())
+        .env("TELEMETRY_KAFKA_TOPIC", TOPIC)
+        .env("TELEMETRY_SESSION_ID", "kafka-session")
+        .run(&["echo", "hi"]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    assert!(run.events.is_empty(), "the file is only a fallback: {:?}", run.event_types());
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", cluster.bootstrap_servers())
+        .set("group.id", "test")
+        .create()
+        .unwrap();
+    let mut partitions = TopicPartitionList::new();
+    for partition in 0..3 {
+        partitions.add_partition_offset(TOPIC, partition, Offset::Beginning).unwrap();
+    }
+    consumer.assign(&partitions).unwrap();
+
+    let mut received = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while received.len() < 3 && Instant::now() < deadline {
+        let Some(message) = consumer.poll(Duration::from_millis(100)) else { continue };
+        let message = message.unwrap();
+        let event: Value = serde_json::from_slice(message.payload().unwrap()).unwrap();
+        received.push((message.partition(), message.key().map(<[u8]>::to_vec), event));
+    }
+    let types: Vec<_> = received.iter().map(|(_, _, event)| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+    // One key, so one partition, in order
+    assert!(received.iter().all(|(partition, key, _)| {
+        *partition == received[0].0 && key.as_deref() == Some(b"kafka-session".as_slice())
+    }));
+}
@@ -0,0 +1,43 @@
+//! The driver embedded as a library: what a run leaves of the host process alone.
+#![cfg(unix)]
+
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::Scratch;
+use rust_telemetry_driver::{BannerMode, Config, TelemetryDriver};
+
+fn sigint_handler() -> libc::sighandler_t {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    unsafe { libc::sigaction(libc::SIGINT, std::ptr::null(), &mut action) };
+    action.sa_sigaction
+}
+
+#[test]
+fn signal_handlers_are_installed_only_when_asked_for() {
+    assert_eq!(sigint_handler(), libc::SIG_DFL);
+    for forward_signals in [false, true] {
+        let scratch = Scratch::new();
+        let mut config = Config::default();
+        config.telemetry_file = scratch.path("telemetry.jsonl").to_string_lossy().into_owned();
+        config.banner = BannerMode::Never;
+        config.quiet = true;
+        config.forward_signals = forward_signals;
+        let driver = TelemetryDriver::new(config);
+        let run = thread::spawn(move || driver.run(&["sleep".to_string(), "1".to_string()]));
+
+        // Watch the disposition for as long as the child runs
+        let started = Instant::now();
+        let mut installed = false;
+        while !run.is_finished() && started.elapsed() < Duration::from_secs(5) {
+            installed |= sigint_handler() != libc::SIG_DFL;
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = run.join().unwrap().expect("run succeeded");
+        assert_eq!(stats.exit_code, 0);
+        assert_eq!(installed, forward_signals);
+        assert_eq!(sigint_handler(), libc::SIG_DFL, "the handler outlived the run");
+    }
+}
@@ -0,0 +1,186 @@
+//! The JSONL log file itself: compression, concurrent appends, rotation, path templates,
+//! the event filter and the versions and ids stamped on every record.
+#![cfg(unix)]
+
+mod common;
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use common::{driver, Scratch};
+use flate2::read::MultiGzDecoder;
+use serde_json::Value;
+
+fn gunzip(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    MultiGzDecoder::new(bytes).read_to_string(&mut text).unwrap();
+    text
+}
+
+#[test]
+fn gzipped_log_decompresses_to_the_events() {
+    let first = driver().env("TELEMETRY_COMPRESS", "gzip").run(&["echo", "one"]);
+    // A second run appends another gzip member
+    driver().env("TELEMETRY_COMPRESS", "gzip").env("TELEMETRY_LOG", &first.log).run(&["echo", "two"]);
+    let bytes = std::fs::read(&first.log).unwrap();
+    assert_eq!(bytes[..2], [0x1f, 0x8b]);
+
+    let events: Vec<Value> = gunzip(&bytes).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let types: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"].repeat(2));
+    assert_eq!(events[1]["stdout_lines"], serde_json::json!(["one"]));
+    assert_eq!(events[4]["stdout_lines"], serde_json::json!(["two"]));
+}
+
+#[test]
+fn log_named_gz_is_compressed() {
+    let driver = driver();
+    let log = driver.scratch().path("events.jsonl.gz");
+    // Keeps the scratch dir until the end of the test
+    let _run = driver.run(&["--log", log.to_str().unwrap(), "--", "true"]);
+    let text = gunzip(&std::fs::read(&log).unwrap());
+    assert_eq!(text.lines().count(), 3);
+}
+
+#[test]
+fn concurrent_drivers_append_whole_lines() {
+    const WRITERS: usize = 16;
+    let scratch = Scratch::new();
+    let log = scratch.path("shared.jsonl");
+    let script = "i=0; while [ $i -lt 200 ]; do echo line-$i-with-some-padding-to-make-events-larger; i=$((i+1)); done";
+    let writers: Vec<_> =
+        (0..WRITERS).map(|_| driver().env("TELEMETRY_LOG", &log).spawn(&["sh", "-c", script])).collect();
+    for (mut child, _scratch, _log) in writers {
+        let mut output = String::new();
+        child.stdout.take().unwrap().read_to_string(&mut output).unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    let text = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), WRITERS * 3);
+    for line in lines {
+        serde_json::from_str::<Value>(line).unwrap_or_else(|e| panic!("torn line ({}): {}", e, line));
+    }
+}
+
+#[test]
+fn log_rotates_once_it_would_pass_the_size_limit() {
+    let scratch = Scratch::new();
+    let log = scratch.path("events.jsonl");
+    let generation = |suffix: &str| common::read_events(&scratch.path(&format!("events.jsonl{}", suffix)));
+    for run in ["one", "two", "three", "four"] {
+        let run = driver()
+            .env("TELEMETRY_LOG", &log)
+            .env("TELEMETRY_MAX_LOG_BYTES", "1000")
+            .env("TELEMETRY_LOG_KEEP", "2")
+            .env("TELEMETRY_SESSION_ID", run)
+            .run(&["true"]);
+        assert!(run.status.success());
+    }
+
+    // Each run's events exceed the limit on their own, so each run starts a new file
+    for (suffix, session) in [("", "four"), (".1", "three"), (".2", "two")] {
+        let events = generation(suffix);
+        assert_eq!(events.len(), 3, "events.jsonl{}", suffix);
+        assert!(events.iter().all(|event| event["session_id"] == session), "events.jsonl{}", suffix);
+    }
+    assert!(!scratch.path("events.jsonl.3").exists());
+}
+
+#[test]
+fn log_path_template_tokens_are_substituted() {
+    let scratch = Scratch::new();
+    let template = scratch.path("{session}-{pid}-{date}.jsonl");
+    let run = driver().env("TELEMETRY_LOG", &template).env("TELEMETRY_SESSION_ID", "templated").run(&["true"]);
+    assert!(run.status.success());
+
+    let files: Vec<_> = std::fs::read_dir(scratch.dir()).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(files.len(), 1);
+    let events = common::read_events(&files[0]);
+    let start = &events[0];
+    let date = chrono::DateTime::from_timestamp(start["timestamp"].as_f64().unwrap() as i64, 0).unwrap();
+    let expected = format!("templated-{}-{}.jsonl", start["pid"], date.format("%Y-%m-%d"));
+    assert_eq!(files[0].file_name().unwrap().to_str().unwrap(), expected);
+}
+
+#[test]
+fn every_event_carries_the_driver_and_schema_versions() {
+    let run = driver().env("TELEMETRY_HEARTBEAT_MS", "100").run(&["sleep", "0.3"]);
+    let failed = driver().run(&["definitely-not-a-command-on-this-path"]);
+    let events: Vec<_> = run.events.iter().chain(&failed.events).collect();
+    assert!(events.iter().any(|event| event["event_type"] == "process_heartbeat"));
+    assert!(events.iter().any(|event| event["event_type"] == "process_spawn_error"));
+    for event in events {
+        assert_eq!(event["driver_version"], env!("CARGO_PKG_VERSION"), "{}", event["event_type"]);
+        assert_eq!(event["schema_version"], rust_telemetry_driver::SCHEMA_VERSION, "{}", event["event_type"]);
+    }
+    assert_eq!(rust_telemetry_driver::DRIVER_VERSION, env!("CARGO_PKG_VERSION"));
+}
+
+#[test]
+fn short_id_is_the_event_id_prefix_and_names_the_summary() {
+    let run = driver().env("TELEMETRY_BANNER", "always").env("TELEMETRY_HEARTBEAT_MS", "100").run(&["sleep", "0.3"]);
+    assert!(run.events.iter().any(|event| event["event_type"] == "process_heartbeat"));
+    for event in &run.events {
+        let (event_id, short_id) = (event["event_id"].as_str().unwrap(), event["short_id"].as_str().unwrap());
+        assert_eq!(short_id.len(), 8, "{}", event["event_type"]);
+        assert_eq!(short_id, &event_id[..8], "{}", event["event_type"]);
+    }
+    let short_id = run.event("process_end")["short_id"].as_str().unwrap();
+    assert!(run.stderr_text().contains(&format!("(event {})", short_id)), "{}", run.stderr_text());
+}
+
+#[test]
+fn event_filter_output_is_what_gets_logged() {
+    let run = driver()
+        .env("TELEMETRY_EVENT_FILTER", r#"sed 's/^{/{"injected":"by-filter",/'"#)
+        .run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert_eq!(run.events.len(), 3);
+    for event in &run.events {
+        assert_eq!(event["injected"], "by-filter");
+        assert!(event["event_id"].is_string());
+    }
+
+    for broken in ["cat >/dev/null; exit 1", "cat >/dev/null; echo not-json"] {
+        let run = driver().env("TELEMETRY_EVENT_FILTER", broken).run(&["echo", "hi"]);
+        assert_eq!(run.event_types(), ["process_start", "process_end", "process_stats"], "{}", broken);
+        assert!(run.events.iter().all(|event| event.get("injected").is_none()));
+        assert!(run.stderr_text().contains("logging it unfiltered"), "{}", run.stderr_text());
+    }
+}
+
+#[test]
+fn hung_event_filter_is_killed_and_the_event_logged_unfiltered() {
+    let started = Instant::now();
+    let run = driver()
+        .env("TELEMETRY_EVENT_FILTER", "sleep 30")
+        .env("TELEMETRY_EVENT_FILTER_TIMEOUT_MS", "200")
+        .run(&["echo", "hi"]);
+    assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+    assert_eq!(run.event_types(), ["process_start", "process_end", "process_stats"]);
+    assert_eq!(run.event("process_end")["stdout_lines"], serde_json::json!(["hi"]));
+    assert!(run.stderr_text().contains("timed out after 200ms"), "{}", run.stderr_text());
+}
+
+#[test]
+fn nanosecond_timestamps_tell_quick_commands_apart() {
+    let first = driver().run(&["true"]);
+    let second = driver().run(&["true"]);
+    let ns = |run: &common::Run, event_type: &str| run.event(event_type)["timestamp_ns"].as_u64().unwrap();
+    // The stats event is stamped with the end time, so starts and ends are what must differ
+    let stamps = [
+        ns(&first, "process_start"),
+        ns(&first, "process_end"),
+        ns(&second, "process_start"),
+        ns(&second, "process_end"),
+    ];
+    assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", stamps);
+
+    // The float timestamp is the same instant, in seconds
+    for event in first.events.iter().chain(&second.events) {
+        let ns = event["timestamp_ns"].as_u64().unwrap() as f64;
+        assert!((event["timestamp"].as_f64().unwrap() - ns / 1e9).abs() < 1e-3, "{}", event);
+    }
+}
@@ -0,0 +1,566 @@
+//! What the driver records about the wrapped process: ids, exit, signals and timing.
+#![cfg(unix)]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::driver;
+use rust_telemetry_driver::TIMEOUT_EXIT_CODE;
+
+#[test]
+fn ppid_is_the_process_that_started_the_driver() {
+    let run = driver().run(&["true"]);
+    assert!(run.status.success());
+    assert_eq!(run.events.len(), 3);
+    for event in &run.events {
+        assert_eq!(event["ppid"], std::process::id());
+    }
+}
+
+#[test]
+fn signal_that_killed_the_child_is_recorded() {
+    let run = driver().run(&["sh", "-c", "kill -TERM $$"]);
+    assert_eq!(run.code(), 128 + libc::SIGTERM);
+    let end = run.event("process_end");
+    assert_eq!(end["signal"], libc::SIGTERM);
+    assert_eq!(end["terminated_by_signal"], true);
+    assert_eq!(end["exit_code"], 128 + libc::SIGTERM);
+}
+
+#[test]
+fn missing_command_exits_127_and_logs_a_spawn_error() {
+    let run = driver().run(&["definitely-not-a-command-on-this-path"]);
+    assert_eq!(run.code(), 127);
+    assert_eq!(run.event_types(), ["process_start", "process_spawn_error"]);
+    assert!(run.event("process_spawn_error")["error"].as_str().is_some_and(|error| !error.is_empty()));
+}
+
+#[test]
+fn timeout_kills_the_child_promptly() {
+    let started = Instant::now();
+    let run = driver().env("TELEMETRY_TIMEOUT_MS", "200").run(&["sleep", "10"]);
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    assert_eq!(run.code(), TIMEOUT_EXIT_CODE);
+    let end = run.event("process_end");
+    assert_eq!(end["timed_out"], true);
+    assert_eq!(end["signal"], libc::SIGKILL);
+}
+
+#[test]
+fn timeout_kills_grandchildren_holding_the_output_pipes() {
+    let started = Instant::now();
+    let run = driver().env("TELEMETRY_TIMEOUT_MS", "200").run(&["sh", "-c", "sleep 10 & sleep 10"]);
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    assert_eq!(run.event("process_end")["timed_out"], true);
+}
+
+#[test]
+fn nested_driver_links_to_its_parent_session() {
+    let run = driver().run(&[common::DRIVER, "true"]);
+    assert!(run.status.success());
+    let outer = run.events.iter().find(|event| event["command"][0] == common::DRIVER).unwrap();
+    let inner = run.events.iter().find(|event| event["command"][0] == "true").unwrap();
+    assert_eq!(inner["parent_session_id"], outer["session_id"]);
+    assert_ne!(inner["session_id"], outer["session_id"]);
+    assert!(outer["parent_session_id"].is_null());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn ancestry_names_the_wrapping_processes() {
+    let script = format!("{} true; :", common::DRIVER);
+    let run = driver().env("TELEMETRY_CAPTURE_ANCESTRY", "1").run(&["sh", "-c", &script]);
+    assert!(run.status.success());
+    let inner = run.events.iter().find(|event| event["command"][0] == "true").unwrap();
+    let ancestry = inner["ancestry"].as_array().unwrap();
+    let comms: Vec<_> = ancestry.iter().map(|ancestor| ancestor["comm"].as_str().unwrap()).collect();
+    // comm is cut to 15 bytes
+    assert_eq!(comms[..2], ["sh", "rust-telemetry-"]);
+}
+
+#[test]
+fn exit_codes_are_recorded_as_returned() {
+    for (script, code, signal) in [("exit 0", 0, None), ("exit 42", 42, None), ("kill -KILL $$", 137, Some(libc::SIGKILL))] {
+        let run = driver().run(&["sh", "-c", script]);
+        assert_eq!(run.code(), code, "{}", script);
+        let end = run.event("process_end");
+        assert_eq!(end["exit_code"], code, "{}", script);
+        assert_eq!(end["signal"], serde_json::json!(signal), "{}", script);
+        assert_eq!(end["terminated_by_signal"], signal.is_some(), "{}", script);
+        assert_eq!(run.event("process_stats")["exit_code"], code, "{}", script);
+    }
+}
+
+#[test]
+fn bare_command_name_is_resolved_to_an_absolute_path() {
+    let driver = driver();
+    let dir = driver.scratch().dir().canonicalize().unwrap();
+    let run = driver.current_dir(&dir).run(&["sh", "-c", "true"]);
+    let start = run.event("process_start");
+    assert_eq!(start["command"][0], "sh");
+    let resolved = std::path::Path::new(start["resolved_executable"].as_str().unwrap());
+    assert!(resolved.is_absolute(), "{}", resolved.display());
+    assert!(resolved.is_file());
+    assert_eq!(start["cwd"], dir.to_str().unwrap());
+}
+
+#[test]
+fn command_is_resolved_on_the_childs_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let driver = driver();
+    let bin = driver.scratch().path("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let tool = bin.join("rvtool");
+    std::fs::write(&tool, "#!/bin/sh\necho from-rvtool\n").unwrap();
+    std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let run = driver.env("TELEMETRY_CHILD_ENV_SET", format!("PATH={}", path)).run(&["rvtool"]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    assert_eq!(run.stdout_text(), "from-rvtool\n");
+    let resolved = run.event("process_start")["resolved_executable"].as_str().unwrap().to_string();
+    assert_eq!(resolved, tool.canonicalize().unwrap().to_str().unwrap());
+}
+
+/// Durations come from the monotonic clock, so they stay non-negative and
+/// cover the run even though timestamps are wall-clock.
+#[test]
+fn durations_are_monotonic_and_never_negative() {
+    let run = driver().run(&["sleep", "0.1"]);
+    let (start, end) = (run.event("process_start"), run.event("process_end"));
+    let duration_ms = end["duration_ms"].as_u64().unwrap();
+    assert!((100..5_000).contains(&duration_ms), "{}", duration_ms);
+    assert_eq!(run.event("process_stats")["duration_ms"], duration_ms);
+    assert!(end["timestamp"].as_f64().unwrap() >= start["timestamp"].as_f64().unwrap());
+    assert!(run.event("process_stats")["driver_overhead_ms"].as_f64().unwrap() >= 0.0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn deleted_working_directory_still_runs_the_command() {
+    let driver = driver();
+    let gone = driver.scratch().path("gone");
+    std::fs::create_dir(&gone).unwrap();
+    // The inner driver starts in a directory that no longer exists
+    let script = format!("cd '{0}' && rmdir '{0}' && exec {1} echo ok", gone.display(), common::DRIVER);
+    let run = driver.run(&["sh", "-c", &script]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    assert_eq!(run.stdout_text(), "ok\n");
+    let inner = run.events.iter().find(|event| event["command"][0] == "echo").unwrap();
+    assert_eq!(inner["cwd"], "<unknown>");
+}
+
+#[test]
+fn heartbeats_are_logged_while_the_child_runs() {
+    let run = driver().env("TELEMETRY_HEARTBEAT_MS", "300").run(&["sleep", "1"]);
+    let types = run.event_types();
+    let heartbeats = types.iter().filter(|event_type| **event_type == "process_heartbeat").count();
+    assert!(heartbeats >= 2, "{:?}", types);
+    assert_eq!(types.first(), Some(&"process_start"));
+    assert_eq!(types[types.len() - 2..], ["process_end", "process_stats"]);
+}
+
+#[test]
+fn heartbeats_of_a_failed_run_survive_only_failures() {
+    let beating = || driver().env("TELEMETRY_HEARTBEAT_MS", "200").env("TELEMETRY_ONLY_FAILURES", "1");
+    let failed = beating().run(&["sh", "-c", "sleep 0.7; exit 1"]);
+    assert!(failed.event_types().contains(&"process_heartbeat"), "{:?}", failed.event_types());
+
+    let passed = beating().run(&["sleep", "0.7"]);
+    assert!(passed.events.is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn resource_samples_follow_a_growing_resident_set() {
+    // Doubles a shell variable up to 32MB, pausing so the sampler sees each step
+    let script = "s=x; for i in $(seq 25); do s=\"$s$s\"; sleep 0.02; done; sleep 0.2";
+    let run = driver().env("TELEMETRY_SAMPLE_INTERVAL_MS", "20").run(&["sh", "-c", script]);
+    let samples = run.event("process_end")["resource_samples"].as_array().unwrap().clone();
+    assert!(samples.len() >= 5, "{:?}", samples);
+    let rss: Vec<_> = samples.iter().map(|sample| sample["rss_kb"].as_i64().unwrap()).collect();
+    assert!(rss.last().unwrap() - rss[0] > 16 * 1024, "{:?}", rss);
+    assert!(samples.windows(2).all(|pair| pair[0]["ts"].as_f64() < pair[1]["ts"].as_f64()));
+}
+
+/// Spawns the driver on `script` and waits for the child's first line of output.
+fn spawn_until_ready(script: &str) -> (std::process::Child, common::Scratch, std::path::PathBuf, String) {
+    use std::io::{BufRead, BufReader};
+
+    let (mut child, scratch, log) = driver().spawn(&["sh", "-c", script]);
+    let mut first_line = String::new();
+    BufReader::new(child.stdout.as_mut().unwrap()).read_line(&mut first_line).unwrap();
+    (child, scratch, log, first_line.trim_end().to_string())
+}
+
+#[test]
+fn stopped_child_is_reported_and_waited_for() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let (mut driver, _scratch, log, child_pid) = spawn_until_ready("echo $$; kill -STOP $$; echo resumed");
+    let mut warning = String::new();
+    BufReader::new(driver.stderr.as_mut().unwrap()).read_line(&mut warning).unwrap();
+    assert!(warning.contains(&format!("stopped by signal {}", libc::SIGSTOP)), "{:?}", warning);
+    assert!(driver.try_wait().unwrap().is_none(), "the driver gave up on a stopped child");
+
+    unsafe { libc::kill(child_pid.parse().unwrap(), libc::SIGCONT) };
+    let mut rest = String::new();
+    driver.stdout.take().unwrap().read_to_string(&mut rest).unwrap();
+    assert!(driver.wait().unwrap().success());
+    assert_eq!(rest, "resumed\n");
+    let events = common::read_events(&log);
+    assert!(events.iter().any(|event| event["event_type"] == "process_end" && event["exit_code"] == 0));
+}
+
+/// Runs the driver as a session leader on a new PTY, as from an interactive shell,
+/// typing `input` and continuing the driver whenever it stops, as `fg` would.
+/// Returns its exit status, what it wrote to the terminal and how often it
+/// stopped; fails the test if the run doesn't finish within a few seconds.
+#[cfg(target_os = "linux")]
+fn run_on_terminal(args: &[&str], input: &[u8]) -> (std::process::ExitStatus, String, usize) {
+    use std::io::{Read, Write};
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let (mut command, _scratch, _log) = driver().command(args);
+    command.stdin(Stdio::from(pty.slave.try_clone().unwrap()));
+    command.stdout(Stdio::from(pty.slave.try_clone().unwrap()));
+    command.stderr(Stdio::from(pty.slave));
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            libc::ioctl(0, libc::TIOCSCTTY as _, 0);
+            Ok(())
+        });
+    }
+    let mut driver = command.spawn().unwrap();
+    drop(command);
+    let mut master = std::fs::File::from(pty.master);
+    master.write_all(input).unwrap();
+    let mut reader = master.try_clone().unwrap();
+    let output = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buf = [0u8; 4096];
+        // EIO once the last process on the terminal has gone
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            output.extend_from_slice(&buf[..n]);
+        }
+        output
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stops = 0;
+    let status = loop {
+        if let Some(status) = driver.try_wait().unwrap() {
+            break status;
+        }
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", driver.id())).unwrap_or_default();
+        if stat.rsplit_once(") ").is_some_and(|(_, fields)| fields.starts_with('T')) {
+            stops += 1;
+            unsafe { libc::kill(driver.id() as i32, libc::SIGCONT) };
+        }
+        if Instant::now() > deadline {
+            let _ = driver.kill();
+            panic!("{:?} hung on the terminal", args);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    drop(master);
+    (status, String::from_utf8_lossy(&output.join().unwrap()).into_owned(), stops)
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn child_using_the_terminal_runs_to_completion() {
+    let (status, _, stops) = run_on_terminal(&["sh", "-c", "stty -echo </dev/tty && stty echo </dev/tty"], b"");
+    assert!(status.success());
+    assert_eq!(stops, 0);
+
+    let (status, output, _) = run_on_terminal(&["sh", "-c", "read line </dev/tty; echo \"got $line\""], b"typed\n");
+    assert!(status.success());
+    assert!(output.contains("got typed"), "{:?}", output);
+
+    // Stdin is the terminal too, read by the child itself
+    let (status, output, _) = run_on_terminal(&["sh", "-c", "read line; echo \"got $line\""], b"piped\n");
+    assert!(status.success());
+    assert!(output.contains("got piped"), "{:?}", output);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn driver_stops_with_its_child_and_resumes_it() {
+    // Ctrl-Z stops the terminal's foreground group, which is the child's
+    let (status, output, stops) = run_on_terminal(&["sh", "-c", "kill -TSTP $$; stty size; echo resumed"], b"");
+    assert!(status.success(), "{:?}", output);
+    assert_eq!(stops, 1);
+    // The child had the terminal again once continued
+    assert!(output.contains("resumed") && !output.contains("stty:"), "{:?}", output);
+}
+
+#[test]
+fn sigterm_to_the_driver_stops_the_child_and_logs_an_interruption() {
+    let (driver, _scratch, log, child_pid) = spawn_until_ready("echo $$; exec sleep 10");
+    let started = Instant::now();
+    unsafe { libc::kill(driver.id() as i32, libc::SIGTERM) };
+    let output = driver.wait_with_output().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+    assert_eq!(output.status.code(), Some(128 + libc::SIGTERM));
+
+    let child_pid: i32 = child_pid.parse().unwrap();
+    assert_eq!(unsafe { libc::kill(child_pid, 0) }, -1, "child {} is still running", child_pid);
+
+    let events = common::read_events(&log);
+    let types: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_interrupted", "process_stats"]);
+    let interrupted = &events[1];
+    assert_eq!(interrupted["interrupted_by"], libc::SIGTERM);
+    assert_eq!(interrupted["stdout_lines"], serde_json::json!([child_pid.to_string()]));
+}
+
+#[test]
+fn sigint_to_the_driver_reaches_the_child() {
+    let script = "trap 'echo got-int; exit 5' INT; echo ready; while :; do sleep 0.05; done";
+    let (driver, _scratch, log, ready) = spawn_until_ready(script);
+    assert_eq!(ready, "ready");
+    unsafe { libc::kill(driver.id() as i32, libc::SIGINT) };
+    let output = driver.wait_with_output().unwrap();
+    // The child's trap ran and chose the exit code
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "got-int\n");
+    assert_eq!(output.status.code(), Some(5));
+
+    let events = common::read_events(&log);
+    let interrupted = events.iter().find(|event| event["event_type"] == "process_interrupted").unwrap();
+    assert_eq!(interrupted["interrupted_by"], libc::SIGINT);
+    assert_eq!(interrupted["exit_code"], 5);
+    assert_eq!(interrupted["stdout_lines"], serde_json::json!(["ready", "got-int"]));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn core_dump_is_flagged() {
+    let dumping = driver();
+    let dir = dumping.scratch().dir().to_path_buf();
+    // The core, if the kernel writes one to the working directory, goes away with the scratch dir
+    let run = dumping.current_dir(&dir).run(&["sh", "-c", "ulimit -c unlimited && kill -SEGV $$"]);
+    assert_eq!(run.code(), 128 + libc::SIGSEGV);
+    let end = run.event("process_end");
+    assert_eq!(end["signal"], libc::SIGSEGV);
+    assert_eq!(end["core_dumped"], true);
+
+    let run = driver().run(&["sh", "-c", "ulimit -c 0 && kill -SEGV $$"]);
+    let end = run.event("process_end");
+    assert_eq!(end["signal"], libc::SIGSEGV);
+    assert_eq!(end["core_dumped"], false);
+}
+
+#[test]
+fn credentials_are_those_of_the_caller() {
+    let run = driver().run(&["true"]);
+    let start = run.event("process_start");
+    unsafe {
+        assert_eq!(start["uid"], libc::getuid());
+        assert_eq!(start["gid"], libc::getgid());
+        assert_eq!(start["euid"], libc::geteuid());
+        assert_eq!(start["egid"], libc::getegid());
+    }
+
+    let script = format!("umask 027 && exec {} true", common::DRIVER);
+    let run = driver().run(&["sh", "-c", &script]);
+    let inner = run.events.iter().find(|event| event["command"][0] == "true").unwrap();
+    assert_eq!(inner["umask"], "0027");
+}
+
+#[test]
+fn driver_overhead_is_logged_on_the_stats_event_only() {
+    let run = driver().run(&["sleep", "0.2"]);
+    let overhead = run.event("process_stats")["driver_overhead_ms"].as_f64().unwrap();
+    assert!(overhead >= 0.0, "{}", overhead);
+    // The child's own 200ms isn't counted
+    assert!(overhead < run.event("process_stats")["duration_ms"].as_f64().unwrap(), "{}", overhead);
+    assert!(run.event("process_start")["driver_overhead_ms"].is_null());
+    assert!(run.event("process_end")["driver_overhead_ms"].is_null());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn peak_open_fds_follows_descriptors_the_child_held() {
+    let open = "for i in $(seq 10 39); do eval \"exec $i</dev/null\"; done";
+    let close = "for i in $(seq 10 39); do eval \"exec $i<&-\"; done";
+    let script = format!("{}; sleep 0.3; {}; sleep 0.2", open, close);
+    let run = driver().env("TELEMETRY_SAMPLE_INTERVAL_MS", "20").run(&["bash", "-c", &script]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    let end = run.event("process_end");
+    let peak = end["peak_open_fds"].as_u64().unwrap();
+    // The 30 opened plus stdio and whatever bash keeps open itself
+    assert!((33..45).contains(&peak), "{}", peak);
+    let last = end["resource_samples"].as_array().unwrap().last().unwrap()["open_fds"].as_u64().unwrap();
+    assert!(last < peak, "descriptors were closed again: {} of {}", last, peak);
+}
+
+#[test]
+fn resource_usage_covers_the_whole_tree() {
+    // A busy grandchild; `times` reports the direct child's own user time first
+    let busy = "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done";
+    let script = format!("sh -c '{}'; times", busy);
+    let run = driver().run(&["sh", "-c", &script]);
+    let own_user_ms = run.stdout_text().lines().next().and_then(user_time_ms).unwrap();
+
+    let stats = run.event("process_stats");
+    assert_eq!(stats["resource_scope"], "tree");
+    let tree_user_ms = stats["resource_usage"]["user_time_ms"].as_f64().unwrap();
+    assert!(tree_user_ms > own_user_ms + 50.0, "tree {}ms, child alone {}ms", tree_user_ms, own_user_ms);
+}
+
+/// The user time in milliseconds from a `times` line like `0m0.010000s 0m0.000000s`.
+fn user_time_ms(line: &str) -> Option<f64> {
+    let (minutes, seconds) = line.split_whitespace().next()?.strip_suffix('s')?.split_once('m')?;
+    Some((minutes.parse::<f64>().ok()? * 60.0 + seconds.parse::<f64>().ok()?) * 1000.0)
+}
+
+#[test]
+fn run_near_its_timeout_is_flagged_slow() {
+    // 90% of the timeout, against the default 80% threshold
+    let slow = driver().env("TELEMETRY_TIMEOUT_MS", "2000").run(&["sleep", "1.8"]);
+    assert!(slow.status.success());
+    assert_eq!(slow.event("process_end")["slow_warning"], true);
+    assert_eq!(slow.event("process_stats")["slow_warning"], true);
+    assert!(slow.stderr_text().contains("of the 2000ms timeout"), "{}", slow.stderr_text());
+
+    let quick = driver().env("TELEMETRY_TIMEOUT_MS", "1000").run(&["sleep", "0.1"]);
+    assert_eq!(quick.event("process_end")["slow_warning"], false);
+    assert!(quick.stderr.is_empty());
+
+    let custom = driver()
+        .env("TELEMETRY_TIMEOUT_MS", "1000")
+        .env("TELEMETRY_SLOW_THRESHOLD_PCT", "10")
+        .run(&["sleep", "0.2"]);
+    assert_eq!(custom.event("process_end")["slow_warning"], true);
+}
+
+#[test]
+fn every_event_names_the_host() {
+    let mut name = [0u8; 256];
+    assert_eq!(unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) }, 0);
+    let hostname = std::ffi::CStr::from_bytes_until_nul(&name).unwrap().to_str().unwrap();
+    assert!(!hostname.is_empty());
+
+    let run = driver().run(&["true"]);
+    for event in &run.events {
+        assert_eq!(event["hostname"], hostname, "{}", event["event_type"]);
+        assert_eq!(event["container_id"], run.events[0]["container_id"]);
+    }
+}
+
+#[test]
+fn git_context_is_recorded_inside_a_repo() {
+    let repo = common::Scratch::new();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(repo.dir())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    git(&["init", "-q", "-b", "feature/telemetry"]);
+    std::fs::write(repo.path("tracked.txt"), "one\n").unwrap();
+    git(&["add", "tracked.txt"]);
+    git(&["commit", "-q", "-m", "initial"]);
+    let sha = git(&["rev-parse", "HEAD"]);
+
+    let run = driver().env("TELEMETRY_CAPTURE_GIT", "1").current_dir(repo.dir()).run(&["true"]);
+    let start = run.event("process_start");
+    assert_eq!(start["git_branch"], "feature/telemetry");
+    assert_eq!(start["git_sha"], sha);
+    assert_eq!(start["git_dirty"], false);
+
+    std::fs::write(repo.path("tracked.txt"), "two\n").unwrap();
+    let dirty = driver().env("TELEMETRY_CAPTURE_GIT", "1").current_dir(repo.dir()).run(&["true"]);
+    assert_eq!(dirty.event("process_start")["git_dirty"], true);
+
+    // Off unless asked for
+    let plain = driver().current_dir(repo.dir()).run(&["true"]);
+    assert!(plain.event("process_start").get("git_sha").is_none_or(|sha| sha.is_null()));
+}
+
+#[test]
+fn matching_output_fails_a_successful_run() {
+    let script = "echo 'warning: deprecated API'; echo done";
+    let run = driver().env("TELEMETRY_FAIL_ON_PATTERN", "deprecat(ed|ion)").run(&["sh", "-c", script]);
+    assert_eq!(run.code(), 1);
+    // Everything is still replayed
+    assert_eq!(run.stdout_text(), "warning: deprecated API\ndone\n");
+    let end = run.event("process_end");
+    assert_eq!(end["pattern_matched"], true);
+
+    let custom = driver()
+        .env("TELEMETRY_FAIL_ON_PATTERN", "deprecated")
+        .env("TELEMETRY_FAIL_PATTERN_EXIT_CODE", "66")
+        .run(&["sh", "-c", "echo deprecated >&2"]);
+    assert_eq!(custom.code(), 66);
+
+    let clean = driver().env("TELEMETRY_FAIL_ON_PATTERN", "deprecated").run(&["echo", "fine"]);
+    assert_eq!(clean.code(), 0);
+    assert_eq!(clean.event("process_end")["pattern_matched"], false);
+}
+
+#[test]
+fn failed_command_is_retried_until_it_succeeds() {
+    let retrying = driver();
+    let marker = retrying.scratch().path("tried");
+    let script = format!("if [ -e '{0}' ]; then echo second; else touch '{0}'; echo first; exit 1; fi", marker.display());
+    let run = retrying
+        .env("TELEMETRY_RETRIES", "3")
+        .env("TELEMETRY_RETRY_DELAY_MS", "10")
+        .run(&["sh", "-c", &script]);
+    assert_eq!(run.code(), 0);
+    let ends: Vec<_> = run.events.iter().filter(|event| event["event_type"] == "process_end").collect();
+    assert_eq!(ends.len(), 2);
+    assert_eq!((&ends[0]["attempt"], &ends[0]["exit_code"]), (&serde_json::json!(1), &serde_json::json!(1)));
+    assert_eq!((&ends[1]["attempt"], &ends[1]["exit_code"]), (&serde_json::json!(2), &serde_json::json!(0)));
+    assert_eq!(ends[1]["stdout_lines"], serde_json::json!(["second"]));
+    assert!(run.events.iter().all(|event| event["session_id"] == run.events[0]["session_id"]));
+
+    // A signal isn't retried
+    let killed = driver().env("TELEMETRY_RETRIES", "3").run(&["sh", "-c", "kill -KILL $$"]);
+    assert_eq!(killed.code(), 128 + libc::SIGKILL);
+    assert_eq!(killed.events.iter().filter(|event| event["event_type"] == "process_end").count(), 1);
+}
+
+#[test]
+fn pipefail_reports_a_failing_first_stage() {
+    let pipeline = ["bash", "-c", "exit 3 | cat"];
+    let strict = driver().env("TELEMETRY_PIPEFAIL", "1").run(&pipeline);
+    assert_eq!(strict.code(), 3);
+    assert_eq!(strict.event("process_start")["pipefail"], true);
+    assert_eq!(strict.event("process_end")["exit_code"], 3);
+
+    let lenient = driver().run(&pipeline);
+    assert_eq!(lenient.code(), 0);
+    assert!(lenient.event("process_start")["pipefail"].is_null());
+
+    // Anything but a shell known to have pipefail runs as given
+    let other = driver().env("TELEMETRY_PIPEFAIL", "1").run(&["true"]);
+    assert_eq!(other.code(), 0);
+    assert_eq!(other.event("process_start")["pipefail"], false);
+}
+
+#[test]
+fn driver_usage_is_recorded_apart_from_the_childs() {
+    let busy = "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done";
+    let run = driver().run(&["sh", "-c", busy]);
+    let stats = run.event("process_stats");
+    let (child, driver) = (&stats["resource_usage"], &stats["driver_resource_usage"]);
+    for field in ["user_time_ms", "system_time_ms", "max_rss_kb", "page_faults", "context_switches"] {
+        assert!(child[field].is_i64() && driver[field].is_i64(), "{}: {} / {}", field, child, driver);
+    }
+    // The child did the work while the driver waited
+    let (child_user, driver_user) = (child["user_time_ms"].as_i64().unwrap(), driver["user_time_ms"].as_i64().unwrap());
+    assert!(child_user > driver_user + 50, "child {}ms, driver {}ms", child_user, driver_user);
+    assert!(run.event("process_end")["driver_resource_usage"].is_null());
+}
@@ -0,0 +1,206 @@
+//! Where events are delivered besides the JSONL file, and what happens when that fails.
+#![cfg(unix)]
+
+mod common;
+
+use common::{driver, MockHttp};
+use serde_json::Value;
+
+#[test]
+fn http_endpoint_receives_all_three_events() {
+    let server = MockHttp::start([]);
+    let run = driver().env("TELEMETRY_HTTP_ENDPOINT", &server.url).run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert!(run.events.is_empty(), "the file is only a fallback");
+
+    let events: Vec<Value> = server
+        .requests()
+        .iter()
+        .flat_map(|(_, body)| serde_json::from_slice::<Vec<Value>>(body).unwrap())
+        .collect();
+    let types: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn otlp_collector_receives_the_process_span() {
+    let collector = MockHttp::start([]);
+    let run = driver()
+        .env("TELEMETRY_OTLP_ENDPOINT", &collector.url)
+        .env("TELEMETRY_SESSION_ID", "otlp-session")
+        .run(&["sh", "-c", "echo out; exit 3"]);
+    assert_eq!(run.code(), 3);
+
+    let requests = collector.requests();
+    assert_eq!(requests.len(), 1);
+    let (path, body) = &requests[0];
+    assert_eq!(path, "/v1/traces");
+    let body: Value = serde_json::from_slice(body).unwrap();
+    let span = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+    assert_eq!(span["name"], "sh -c echo out; exit 3");
+    assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+    assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+
+    let attribute = |key: &str| {
+        let attributes = span["attributes"].as_array().unwrap();
+        let attribute = attributes.iter().find(|attribute| attribute["key"] == key);
+        attribute.unwrap_or_else(|| panic!("no {} attribute", key))["value"].clone()
+    };
+    assert_eq!(attribute("process.command")["stringValue"], "sh");
+    assert_eq!(attribute("telemetry.session_id")["stringValue"], "otlp-session");
+    assert_eq!(attribute("process.exit_code")["intValue"], "3");
+    assert_eq!(attribute("process.stdout_lines")["intValue"], "1");
+    assert_eq!(attribute("process.timed_out")["boolValue"], false);
+}
+
+#[test]
+fn unix_socket_receives_all_three_events_on_one_connection() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    let driver = driver();
+    let socket = driver.scratch().path("telemetry.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let received = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        BufReader::new(stream).lines().map(Result::unwrap).collect::<Vec<_>>()
+    });
+
+    let run = driver.env("TELEMETRY_SOCKET", &socket).run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert!(run.events.is_empty(), "the file is only a fallback");
+
+    let lines = received.join().unwrap();
+    let types: Vec<String> = lines
+        .iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["event_type"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn missing_unix_socket_falls_back_to_the_file() {
+    let driver = driver();
+    let socket = driver.scratch().path("nobody-listening.sock");
+    let run = driver.env("TELEMETRY_SOCKET", &socket).run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert_eq!(run.event_types(), ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn syslog_socket_receives_each_event_with_its_severity() {
+    use std::os::unix::net::UnixDatagram;
+
+    let driver = driver();
+    let path = driver.scratch().path("syslog.sock");
+    let syslog = UnixDatagram::bind(&path).unwrap();
+    syslog.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+
+    let run = driver
+        .env("TELEMETRY_SYSLOG", "1")
+        .env("TELEMETRY_SYSLOG_SOCKET", &path)
+        .env("TELEMETRY_SYSLOG_FACILITY", "local3")
+        .run(&["sh", "-c", "exit 2"]);
+    assert_eq!(run.code(), 2);
+    assert_eq!(run.event_types(), ["process_start", "process_end", "process_stats"], "syslog is an extra sink");
+
+    let mut buf = vec![0; 64 * 1024];
+    for expected in &run.events {
+        let len = syslog.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        // local3 is facility 19; the failed end and stats are warnings (4), the start info (6)
+        let severity = if expected["event_type"] == "process_start" { 6 } else { 4 };
+        let prefix = format!("<{}>", 19 * 8 + severity);
+        assert!(message.starts_with(&prefix), "{}", message);
+        let (header, body) = message.split_once(": ").unwrap();
+        assert!(header.ends_with(&format!(" sh[{}]", expected["pid"])), "{}", header);
+        // Large events lose their env and output, so only the identity is compared
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!((&body["event_id"], &body["event_type"]), (&expected["event_id"], &expected["event_type"]));
+    }
+}
+
+#[test]
+fn flaky_endpoint_gets_the_events_after_retries() {
+    let server = MockHttp::start([500, 503]);
+    let run = driver()
+        .env("TELEMETRY_HTTP_ENDPOINT", &server.url)
+        .env("TELEMETRY_SINK_RETRIES", "2")
+        .env("TELEMETRY_SINK_BACKOFF_MS", "10")
+        .run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert!(run.events.is_empty(), "nothing fell back to the file: {:?}", run.event_types());
+
+    let requests = server.requests();
+    // The two failed attempts carried the same events as the one that landed
+    assert!(requests.len() >= 3, "{} requests", requests.len());
+    assert_eq!(requests[0].1, requests[2].1);
+    let delivered: Vec<Value> = requests[2..]
+        .iter()
+        .flat_map(|(_, body)| serde_json::from_slice::<Vec<Value>>(body).unwrap())
+        .collect();
+    let types: Vec<_> = delivered.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+}
+
+#[test]
+fn endpoint_failing_past_the_retries_falls_back_to_the_file() {
+    let server = MockHttp::start([500; 10]);
+    let run = driver()
+        .env("TELEMETRY_HTTP_ENDPOINT", &server.url)
+        .env("TELEMETRY_SINK_RETRIES", "1")
+        .env("TELEMETRY_SINK_BACKOFF_MS", "10")
+        .run(&["echo", "hi"]);
+    assert!(run.status.success());
+    assert_eq!(run.event_types(), ["process_start", "process_end", "process_stats"]);
+    assert_eq!(server.requests().len() % 2, 0, "each send is tried twice");
+}
+
+/// Splits a stream into the events the driver wrote, by their prefix, and everything else.
+fn split_stream(text: &str, prefix: &str) -> (Vec<Value>, Vec<String>) {
+    let (events, other): (Vec<&str>, Vec<&str>) = text.lines().partition(|line| line.starts_with(prefix));
+    let events = events.iter().map(|line| serde_json::from_str(&line[prefix.len()..]).unwrap()).collect();
+    (events, other.into_iter().map(str::to_string).collect())
+}
+
+#[test]
+fn stream_sink_keeps_events_apart_from_child_output() {
+    use rust_telemetry_driver::DEFAULT_STREAM_PREFIX;
+
+    let script = "echo out; echo err >&2";
+    let run = driver().env("TELEMETRY_SINK", "stderr").run(&["sh", "-c", script]);
+    assert!(!run.log.exists());
+    assert_eq!(run.stdout_text(), "out\n");
+    let (events, child) = split_stream(&run.stderr_text(), DEFAULT_STREAM_PREFIX);
+    assert_eq!(child, ["err"]);
+    let types: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["process_start", "process_end", "process_stats"]);
+
+    let run = driver().env("TELEMETRY_LOG", "-").env("TELEMETRY_STREAM_PREFIX", "@@ ").run(&["sh", "-c", script]);
+    assert_eq!(run.stderr_text(), "err\n");
+    let (events, child) = split_stream(&run.stdout_text(), "@@ ");
+    assert_eq!(child, ["out"]);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1]["stdout_lines"], serde_json::json!(["out"]));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn failed_log_write_reports_a_driver_error_on_stderr() {
+    // Every write to /dev/full fails with ENOSPC
+    let run = driver().env("TELEMETRY_LOG", "/dev/full").run(&["echo", "hi"]);
+    assert!(run.status.success(), "the command's outcome stands");
+    assert_eq!(run.stdout_text(), "hi\n");
+    let errors: Vec<Value> = run
+        .stderr_text()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|error| error["event_type"] == "driver_error")
+        .collect();
+    assert!(!errors.is_empty(), "no driver_error in {:?}", run.stderr_text());
+    for error in &errors {
+        assert!(error["category"].as_str().unwrap().starts_with("sink_"), "{}", error);
+        assert!(error["message"].as_str().unwrap().contains("No space left"), "{}", error);
+        assert!(error["session_id"].is_string() && error["timestamp"].is_number());
+    }
+}
@@ -0,0 +1,85 @@
+//! What the child did besides produce output: files it opened, connections it
+//! made and processes it spawned.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::driver;
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn traced_files_include_one_the_command_opened() {
+    let driver = driver();
+    let known = driver.scratch().path("known.txt");
+    std::fs::write(&known, "contents\n").unwrap();
+    let run = driver.env("TELEMETRY_TRACE_FILES", "1").run(&["cat", known.to_str().unwrap()]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    assert_eq!(run.stdout_text(), "contents\n");
+
+    let files = run.event("process_end")["files_opened"].as_array().unwrap().clone();
+    assert!(files.iter().any(|file| file == known.to_str().unwrap()), "{:?}", files);
+    assert!(files.windows(2).all(|pair| pair[0].as_str() < pair[1].as_str()), "not deduplicated: {:?}", files);
+}
+
+#[test]
+fn traced_connections_include_a_local_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let accepted = std::thread::spawn(move || listener.accept().map(|_| ()));
+
+    // bash holds the connection open on fd 3 across several samples
+    let script = format!("exec 3<>/dev/tcp/{}; sleep 0.5", address.replace(':', "/"));
+    let run = driver()
+        .env("TELEMETRY_TRACE_NET", "1")
+        .env("TELEMETRY_SAMPLE_INTERVAL_MS", "20")
+        .run(&["bash", "-c", &script]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    accepted.join().unwrap().unwrap();
+
+    let connections = &run.event("process_end")["net_connections"];
+    assert_eq!(connections, &serde_json::json!([address]));
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn process_tree_records_each_descendant() {
+    let run = driver()
+        .env("TELEMETRY_CAPTURE_TREE", "1")
+        .run(&["sh", "-c", "sleep 0.01; sh -c 'exit 3'; true"]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    let processes = run.event("process_end")["child_processes"].as_array().unwrap().clone();
+    let summary: Vec<_> = processes
+        .iter()
+        .map(|process| (process["comm"].as_str().unwrap(), process["exit_code"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(summary, [("sleep", 0), ("sh", 3)]);
+    assert!(processes.iter().all(|process| process["pid"].as_i64().unwrap() > 0));
+
+    let untraced = driver().run(&["sh", "-c", "sleep 0.01"]);
+    assert!(untraced.event("process_end")["child_processes"].is_null());
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn detached_descendants_are_let_go_once_the_command_exits() {
+    // The background sleep outlives the command but holds none of its output
+    let started = std::time::Instant::now();
+    let run = driver()
+        .env("TELEMETRY_CAPTURE_TREE", "1")
+        .env("TELEMETRY_TRACE_FILES", "1")
+        .run(&["sh", "-c", "sleep 5 >/dev/null 2>&1 & echo $!"]);
+    assert!(started.elapsed() < std::time::Duration::from_secs(3), "took {:?}", started.elapsed());
+    assert!(run.status.success(), "{}", run.stderr_text());
+
+    let end = run.event("process_end");
+    assert!(end["duration_ms"].as_f64().unwrap() < 1000.0, "{}", end["duration_ms"]);
+    let sleep_pid: i32 = run.stdout_text().trim().parse().unwrap();
+    let sleep = end["child_processes"].as_array().unwrap().iter().find(|process| process["pid"] == sleep_pid);
+    assert_eq!(sleep.expect("the sleep is recorded")["exit_code"], serde_json::Value::Null);
+
+    // Still running, untraced and not left stopped
+    let status = std::fs::read_to_string(format!("/proc/{}/status", sleep_pid)).unwrap();
+    assert!(status.lines().any(|line| line == "TracerPid:\t0"), "{}", status);
+    assert!(!status.lines().any(|line| line.starts_with("State:\tT")), "{}", status);
+    unsafe { libc::kill(sleep_pid, libc::SIGKILL) };
+}
@@ -0,0 +1,15 @@
+//! Windows-only parts of the driver, for the Windows CI job.
+#![cfg(windows)]
+
+mod common;
+
+use common::driver;
+
+#[test]
+fn cpu_busy_command_reports_nonzero_cpu_times() {
+    let run = driver().run(&["cmd", "/C", "for /L %i in (1,1,3000000) do @rem"]);
+    assert!(run.status.success(), "{}", run.stderr_text());
+    let usage = &run.event("process_end")["resource_usage"];
+    let cpu_ms = usage["user_time_ms"].as_i64().unwrap() + usage["system_time_ms"].as_i64().unwrap();
+    assert!(cpu_ms > 0, "{}", usage);
+}